@@ -0,0 +1,81 @@
+#!/usr/bin/env python3
+"""
+Write reference dense vectors for `litomi-local-search verify-model`.
+
+Input: a text file (one text per line) or JSONL with a "text" field
+(optionally "id", "kind": "query" | "document").
+Output: JSONL lines {"id"?, "text", "kind", "maxLength", "embedding"}.
+"""
+
+from __future__ import annotations
+
+import argparse
+import json
+import os
+from pathlib import Path
+
+
+def parse_args() -> argparse.Namespace:
+    p = argparse.ArgumentParser()
+    p.add_argument("--model-id", default="BAAI/bge-m3")
+    p.add_argument("--input", required=True)
+    p.add_argument("--out", default="data/model/references.jsonl")
+    p.add_argument("--max-length", type=int, default=512)
+    p.add_argument("--batch-size", type=int, default=16)
+    p.add_argument("--fp16", action="store_true", help="encode in fp16 (FlagEmbedding default)")
+    return p.parse_args()
+
+
+def read_items(path: Path) -> list[dict]:
+    items = []
+    with path.open(encoding="utf-8") as f:
+        for line in f:
+            line = line.rstrip("\n")
+            if not line.strip():
+                continue
+            if path.suffix == ".jsonl":
+                item = json.loads(line)
+            else:
+                item = {"text": line}
+            item.setdefault("kind", "query")
+            items.append(item)
+    return items
+
+
+def main() -> None:
+    args = parse_args()
+
+    # Lazy import so the script can show a clean error if deps are missing.
+    try:
+        from FlagEmbedding import BGEM3FlagModel
+    except Exception as e:
+        raise RuntimeError("FlagEmbedding is required: pip install FlagEmbedding") from e
+
+    items = read_items(Path(args.input))
+    print(f"[refs] model={args.model_id} texts={len(items)}")
+
+    model = BGEM3FlagModel(args.model_id, use_fp16=args.fp16)
+    out = model.encode(
+        [item["text"] for item in items],
+        batch_size=args.batch_size,
+        max_length=args.max_length,
+        return_dense=True,
+        return_sparse=False,
+        return_colbert_vecs=False,
+    )
+
+    out_path = Path(args.out)
+    out_path.parent.mkdir(parents=True, exist_ok=True)
+    with out_path.open("w", encoding="utf-8") as f:
+        for item, vec in zip(items, out["dense_vecs"]):
+            line = {k: item[k] for k in ("id", "text", "kind") if k in item}
+            line["maxLength"] = args.max_length
+            line["embedding"] = [float(x) for x in vec]
+            f.write(json.dumps(line, ensure_ascii=False) + "\n")
+
+    print(f"[refs] wrote {out_path}")
+
+
+if __name__ == "__main__":
+    os.environ.setdefault("TOKENIZERS_PARALLELISM", "false")
+    main()
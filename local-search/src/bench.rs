@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::{
+  doc_template::DEFAULT_DOC_TEMPLATE,
+  embedder::ModelSpec,
+  index_builder::create_tables,
+  manifest::{BuildReport, IndexManifest},
+  metrics::LatencySummary,
+  vector_file::VectorWriter,
+};
+
+/// Queries used when `bench` isn't given a queries file.
+pub const DEFAULT_QUERIES: &[&str] = &[
+  "마법사 소녀의 여행",
+  "어떤 국왕에게 프리렌과 페른 모두 뺏기고 절망하는 슈타르크가 나오는 만화",
+  "학교 옥상에서 고백하는 로맨스",
+  "좀비 아포칼립스 생존물",
+  "요리 대결 만화",
+  "time loop mystery with a detective",
+  "이세계 전생 먼치킨",
+  "우울한 분위기의 호러 단편",
+];
+
+/// Writes an index of `docs` random unit vectors (with placeholder docs) that
+/// `model` can search, for scan timings at corpus sizes you don't have.
+pub fn synthetic_index(dir: &Path, model: &ModelSpec, docs: usize, seed: u64) -> anyhow::Result<()> {
+  std::fs::create_dir_all(dir)?;
+  let dims = model.dims;
+  let mut rng = XorShift(seed.max(1));
+
+  let mut writer = VectorWriter::create(&dir.join("vectors.f32"), dims)?;
+  let mut v = vec![0f32; dims];
+  for _ in 0..docs {
+    for x in v.iter_mut() {
+      *x = rng.gaussian();
+    }
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::MIN_POSITIVE);
+    v.iter_mut().for_each(|x| *x /= norm);
+    writer.push(&v)?;
+  }
+  writer.finish()?;
+
+  let sqlite_path = dir.join("doc_meta.sqlite");
+  if sqlite_path.exists() {
+    std::fs::remove_file(&sqlite_path)?;
+  }
+  let mut conn = Connection::open(&sqlite_path)?;
+  create_tables(&conn)?;
+  let tx = conn.transaction()?;
+  {
+    let mut doc = tx.prepare("INSERT INTO doc (doc_id, manga_id, title, text) VALUES (?1, ?2, ?3, ?4)")?;
+    let mut map = tx.prepare("INSERT INTO vec_map (row, doc_id) VALUES (?1, ?2)")?;
+    for row in 0..docs as i64 {
+      let doc_id = format!("synthetic:{row}");
+      doc.execute(params![doc_id, row, format!("synthetic {row}"), "synthetic document text"])?;
+      map.execute(params![row, doc_id])?;
+    }
+  }
+  tx.commit()?;
+
+  IndexManifest {
+    model: model.clone(),
+    docs,
+    doc_max_length: 1024,
+    long_doc: Default::default(),
+    doc_template: DEFAULT_DOC_TEMPLATE.to_string(),
+    fields: Vec::new(),
+    report: BuildReport::default(),
+    shard: None,
+  }
+  .save(dir)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchLevel {
+  pub concurrency: usize,
+  pub requests: usize,
+  pub errors: usize,
+  pub qps: f64,
+  /// End-to-end `search` latency as seen by the caller.
+  pub total_ms: LatencySummary,
+  pub queue_wait_ms: LatencySummary,
+  /// Per forward pass (a batch may hold several queries).
+  pub tokenize_ms: LatencySummary,
+  pub onnx_ms: LatencySummary,
+  pub scan_ms: LatencySummary,
+  pub sqlite_ms: LatencySummary,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchReport {
+  pub collection: String,
+  pub docs: usize,
+  pub dims: usize,
+  pub synthetic: bool,
+  pub top_k: usize,
+  pub levels: Vec<BenchLevel>,
+}
+
+/// xorshift64*; plenty for random test vectors and keeps runs reproducible.
+/// The state must be non-zero.
+pub struct XorShift(pub u64);
+
+impl XorShift {
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  /// Uniform in (0, 1].
+  fn unit(&mut self) -> f32 {
+    ((self.next_u64() >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+  }
+
+  /// Standard normal (Box-Muller), so normalized vectors are uniform on the sphere.
+  fn gaussian(&mut self) -> f32 {
+    let (u, v) = (self.unit(), self.unit());
+    (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+  }
+}
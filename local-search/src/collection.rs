@@ -0,0 +1,747 @@
+use std::{
+  fs::{self, File, TryLockError},
+  ops::Deref,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Condvar, Mutex, MutexGuard, RwLock,
+  },
+  time::Duration,
+};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+  embedder::ModelSpec,
+  embedder_pool::EmbedderPool,
+  manifest::IndexManifest,
+  query_cache::QueryCache,
+  vector_store::VectorStore,
+};
+
+/// Name of the collection served from `<data-dir>/index` (and by `/api/search`).
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// Held (`flock`) by the one process writing an index directory.
+pub const WRITE_LOCK_FILE: &str = "write.lock";
+
+/// WAL rows that trigger a flush right after an upsert.
+const WAL_FLUSH_ROWS: usize = 4096;
+
+/// Read-only connections opened with each generation; lookups beyond this
+/// many at once wait for one to come back.
+const READERS: usize = 8;
+
+/// How long a query waits on a writer's sqlite lock before failing.
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Query-side model state, shared by every collection whose index was built
+/// with a compatible model.
+pub struct SharedModel {
+  pub spec: ModelSpec,
+  pub embedder: EmbedderPool,
+  pub query_cache: QueryCache,
+}
+
+/// One opened index directory: the vector store (segments + WAL) and the
+/// sqlite connections that map its rows to docs. Every file handle is opened
+/// in `open`/`open_writer` and nothing is reopened by path afterwards, so a
+/// generation keeps reading the files it was loaded from even after
+/// `build-index` unlinks and replaces them. Read-only unless opened with
+/// `open_writer`.
+pub struct Index {
+  pub dir: PathBuf,
+  pub dims: usize,
+  pub manifest: Option<IndexManifest>,
+  /// Named vectors from `--vector-field`, sorted.
+  pub fields: Vec<String>,
+  /// Opened with `open_writer`: upserts, deletes and compaction are allowed.
+  pub writable: bool,
+  store: RwLock<VectorStore>,
+  /// Read-only connections; each lookup borrows one (see `conn`).
+  readers: ReaderPool,
+  /// The only read-write connection, for `open_writer` indexes.
+  writer: Option<Mutex<Connection>>,
+}
+
+struct ReaderPool {
+  idle: Mutex<Vec<Connection>>,
+  returned: Condvar,
+}
+
+/// A read-only connection borrowed from an index's pool; goes back on drop.
+pub struct ReadConn<'a> {
+  conn: Option<Connection>,
+  pool: &'a ReaderPool,
+}
+
+impl Deref for ReadConn<'_> {
+  type Target = Connection;
+
+  fn deref(&self) -> &Connection {
+    self.conn.as_ref().expect("connection is present until drop")
+  }
+}
+
+impl Drop for ReadConn<'_> {
+  fn drop(&mut self) {
+    let Some(conn) = self.conn.take() else {
+      return;
+    };
+    match self.pool.idle.lock() {
+      Ok(mut idle) => idle.push(conn),
+      Err(poisoned) => poisoned.into_inner().push(conn),
+    }
+    self.pool.returned.notify_one();
+  }
+}
+
+/// Exclusive lock on an index directory for its one writer (`serve`, `sync`,
+/// or `build-index`/`merge-index` while they rewrite it). Two writers would
+/// both append from their own `next_row` and `next_id`, reusing rows and
+/// segment names. Released on drop or exit.
+pub struct WriteLock {
+  _file: File,
+}
+
+impl WriteLock {
+  pub fn acquire(dir: &Path) -> anyhow::Result<Self> {
+    let path = dir.join(WRITE_LOCK_FILE);
+    let file = File::options()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(&path)
+      .map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+    match file.try_lock() {
+      Ok(()) => Ok(Self { _file: file }),
+      Err(TryLockError::WouldBlock) => anyhow::bail!(
+        "index {} is already open for writing by another `serve`, `sync`, `build-index` or `merge-index`; stop it first",
+        dir.display()
+      ),
+      Err(TryLockError::Error(e)) => anyhow::bail!("failed to lock {}: {e}", path.display()),
+    }
+  }
+}
+
+/// A doc pushed through `/api/docs`, already embedded.
+pub struct NewDoc {
+  pub doc_id: String,
+  pub manga_id: Option<i64>,
+  pub title: String,
+  pub text: String,
+  pub vector: Vec<f32>,
+  /// One vector per named field, in `Index::fields` order.
+  pub field_vectors: Vec<Vec<f32>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Upserted {
+  pub doc_id: String,
+  pub row: usize,
+  pub replaced: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredDoc {
+  pub doc_id: String,
+  pub manga_id: i64,
+  pub title: String,
+  pub text: String,
+  pub row: usize,
+  /// Segment holding the vector (`"wal"` until the next flush).
+  pub segment: String,
+}
+
+/// A searchable doc and the row holding its vectors, as stored in sqlite.
+pub struct RowDoc {
+  pub row: usize,
+  pub doc_id: String,
+  pub manga_id: Option<i64>,
+  pub title: Option<String>,
+  pub text: Option<String>,
+}
+
+/// What one compaction did.
+pub struct Compacted {
+  pub wal_rows: usize,
+  pub merged_segments: usize,
+  pub rows: usize,
+}
+
+impl Index {
+  /// Opens `dir` read-only (nothing in it is created or rewritten), checks its
+  /// manifest (if any) against `model` and that every `vec_map` row points at
+  /// a stored vector.
+  pub fn open(dir: &Path, model: &ModelSpec) -> anyhow::Result<Self> {
+    Self::load(dir, model, false)
+  }
+
+  /// Opens `dir` for `/api/docs` writes and compaction: cuts a torn WAL tail
+  /// first. Callers hold the directory's `WriteLock`.
+  pub fn open_writer(dir: &Path, model: &ModelSpec) -> anyhow::Result<Self> {
+    Self::load(dir, model, true)
+  }
+
+  fn load(dir: &Path, model: &ModelSpec, writable: bool) -> anyhow::Result<Self> {
+    let manifest = IndexManifest::load(dir)?;
+    let dims = match &manifest {
+      Some(manifest) => {
+        if !manifest.model.is_compatible(model) {
+          anyhow::bail!(
+            "index {} was built with model {} ({} dims, {:?} pooling) but the loaded model is {} ({} dims, {:?} pooling); rebuild the index",
+            dir.display(),
+            manifest.model.id,
+            manifest.model.dims,
+            manifest.model.pooling,
+            model.id,
+            model.dims,
+            model.pooling,
+          );
+        }
+        manifest.model.dims
+      }
+      None => {
+        warn!(index_dir = %dir.display(), "index has no manifest; assuming it matches the model");
+        model.dims
+      }
+    };
+
+    let mut fields: Vec<String> = manifest.iter().flat_map(|m| &m.fields).map(|f| f.name.clone()).collect();
+    fields.sort();
+
+    // Never created here: a missing file is an error, not an empty index.
+    let sqlite_path = dir.join("doc_meta.sqlite");
+    let flags = if writable {
+      OpenFlags::SQLITE_OPEN_READ_WRITE
+    } else {
+      OpenFlags::SQLITE_OPEN_READ_ONLY
+    };
+    let conn = open_sqlite(&sqlite_path, flags)?;
+
+    let mut store = VectorStore::open(dir, dims, &fields)?;
+    if writable {
+      store.prepare_writes()?;
+    }
+    {
+      let mut stmt = conn.prepare("SELECT row FROM vec_map")?;
+      let mut rows = stmt.query([])?;
+      while let Some(r) = rows.next()? {
+        let row: i64 = r.get(0)?;
+        if row < 0 || store.location(row as usize).is_none() {
+          anyhow::bail!(
+            "{} maps row {row} but the index has no vector for it; rebuild the index",
+            sqlite_path.display()
+          );
+        }
+        store.set_alive(row as usize, true);
+      }
+    }
+    let mut readers = (1..READERS)
+      .map(|_| open_sqlite(&sqlite_path, OpenFlags::SQLITE_OPEN_READ_ONLY))
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    let writer = if writable {
+      readers.push(open_sqlite(&sqlite_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?);
+      Some(Mutex::new(conn))
+    } else {
+      readers.push(conn);
+      None
+    };
+
+    Ok(Self {
+      dir: dir.to_path_buf(),
+      dims,
+      manifest,
+      fields,
+      writable,
+      store: RwLock::new(store),
+      readers: ReaderPool {
+        idle: Mutex::new(readers),
+        returned: Condvar::new(),
+      },
+      writer,
+    })
+  }
+
+  /// Searchable docs (replaced/deleted rows don't count).
+  pub fn docs(&self) -> usize {
+    self.store().docs()
+  }
+
+  /// Rows appended since the last flush.
+  pub fn wal_rows(&self) -> usize {
+    self.store().wal_rows()
+  }
+
+  pub fn segments(&self) -> usize {
+    self.store().segment_count()
+  }
+
+  /// A read-only connection to this generation's sqlite file, from a fixed
+  /// pool so concurrent searches don't serialize on one connection. Waits
+  /// while every connection is borrowed.
+  pub fn conn(&self) -> anyhow::Result<ReadConn<'_>> {
+    let mut idle = self
+      .readers
+      .idle
+      .lock()
+      .map_err(|_| anyhow::anyhow!("sqlite readers for {} are poisoned", self.dir.display()))?;
+    let conn = loop {
+      if let Some(conn) = idle.pop() {
+        break conn;
+      }
+      idle = self
+        .readers
+        .returned
+        .wait(idle)
+        .map_err(|_| anyhow::anyhow!("sqlite readers for {} are poisoned", self.dir.display()))?;
+    };
+    Ok(ReadConn {
+      conn: Some(conn),
+      pool: &self.readers,
+    })
+  }
+
+  /// The read-write connection; only `open_writer` indexes have one.
+  fn writer(&self) -> anyhow::Result<MutexGuard<'_, Connection>> {
+    let Some(writer) = &self.writer else {
+      anyhow::bail!("index {} is open read-only", self.dir.display());
+    };
+    writer
+      .lock()
+      .map_err(|_| anyhow::anyhow!("sqlite connection for {} is poisoned", self.dir.display()))
+  }
+
+  fn store(&self) -> std::sync::RwLockReadGuard<'_, VectorStore> {
+    match self.store.read() {
+      Ok(store) => store,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+
+  fn store_mut(&self) -> std::sync::RwLockWriteGuard<'_, VectorStore> {
+    match self.store.write() {
+      Ok(store) => store,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+
+  /// Default-field vector of `row`.
+  pub fn vector(&self, row: usize) -> Option<Vec<f32>> {
+    self.store().vector(0, row).map(<[f32]>::to_vec)
+  }
+
+  /// Vector of `row` in `field` (`"default"` = `vectors.f32`).
+  pub fn field_vector(&self, field: &str, row: usize) -> Option<Vec<f32>> {
+    let store = self.store();
+    store.vector(store.column(field)?, row).map(<[f32]>::to_vec)
+  }
+
+  /// Best `top_k` alive rows by `sum(weight * (q . v))` over `fields`
+  /// (`"default"` = `vectors.f32`; empty = default only).
+  pub fn search(&self, fields: &[(String, f32)], q: &[f32], top_k: usize) -> anyhow::Result<Vec<(usize, f32)>> {
+    self.store().search(fields, q, top_k)
+  }
+
+  pub fn get_doc(&self, doc_id: &str) -> anyhow::Result<Option<StoredDoc>> {
+    let conn = self.conn()?;
+    let doc = conn
+      .query_row(
+        r#"
+SELECT doc.doc_id, doc.manga_id, doc.title, doc.text, vec_map.row
+FROM doc
+JOIN vec_map ON vec_map.doc_id = doc.doc_id
+WHERE doc.doc_id = ?1
+"#,
+        [doc_id],
+        |r| {
+          let row: i64 = r.get(4)?;
+          Ok(StoredDoc {
+            doc_id: r.get(0)?,
+            manga_id: r.get(1)?,
+            title: r.get(2)?,
+            text: r.get(3)?,
+            row: row as usize,
+            segment: String::new(),
+          })
+        },
+      )
+      .optional()?;
+    drop(conn);
+    Ok(doc.map(|doc| StoredDoc {
+      segment: self.store().location(doc.row).unwrap_or_default().to_string(),
+      ..doc
+    }))
+  }
+
+  /// Every searchable doc (mapped in `vec_map`), in row order.
+  pub fn docs_by_row(&self) -> anyhow::Result<Vec<RowDoc>> {
+    let conn = self.conn()?;
+    let mut stmt = conn.prepare(
+      "SELECT v.row, d.doc_id, d.manga_id, d.title, d.text FROM vec_map v JOIN doc d ON d.doc_id = v.doc_id ORDER BY v.row",
+    )?;
+    let docs = stmt
+      .query_map([], |r| {
+        Ok(RowDoc {
+          row: r.get::<_, i64>(0)? as usize,
+          doc_id: r.get(1)?,
+          manga_id: r.get(2)?,
+          title: r.get(3)?,
+          text: r.get(4)?,
+        })
+      })?
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(docs)
+  }
+
+  fn check_writable(&self) -> anyhow::Result<()> {
+    if !self.writable {
+      anyhow::bail!("index {} is open read-only", self.dir.display());
+    }
+    Ok(())
+  }
+
+  /// Logs the batch to the WAL with one sync, maps it in one sqlite
+  /// transaction, then flips liveness. The store's write lock is only taken
+  /// for the in-memory steps. Callers serialize writers.
+  fn upsert(&self, docs: Vec<NewDoc>) -> anyhow::Result<Vec<Upserted>> {
+    self.check_writable()?;
+    let batch: Vec<Vec<&[f32]>> = docs
+      .iter()
+      .map(|doc| {
+        std::iter::once(doc.vector.as_slice())
+          .chain(doc.field_vectors.iter().map(Vec::as_slice))
+          .collect()
+      })
+      .collect();
+    // Logged rows start dead, so a failed sqlite write just leaves holes.
+    let first_row = self.store().log_rows(&batch)?;
+    self.store_mut().push_rows(first_row, &batch);
+    drop(batch);
+
+    let mut out = Vec::with_capacity(docs.len());
+    let mut replaced_rows = Vec::new();
+    let mut conn = self.writer()?;
+    let tx = conn.transaction()?;
+    {
+      let mut find = tx.prepare("SELECT row FROM vec_map WHERE doc_id = ?1")?;
+      let mut unmap = tx.prepare("DELETE FROM vec_map WHERE doc_id = ?1")?;
+      let mut insert_doc =
+        tx.prepare("INSERT OR REPLACE INTO doc (doc_id, manga_id, title, text) VALUES (?1, ?2, ?3, ?4)")?;
+      let mut insert_row = tx.prepare("INSERT INTO vec_map (row, doc_id) VALUES (?1, ?2)")?;
+      for (i, doc) in docs.into_iter().enumerate() {
+        let row = first_row + i;
+        let old_row: Option<i64> = find.query_row([&doc.doc_id], |r| r.get(0)).optional()?;
+        unmap.execute([&doc.doc_id])?;
+        insert_doc.execute(params![doc.doc_id, doc.manga_id.unwrap_or(row as i64), doc.title, doc.text])?;
+        insert_row.execute(params![row as i64, doc.doc_id])?;
+        replaced_rows.extend(old_row);
+        out.push(Upserted {
+          doc_id: doc.doc_id,
+          row,
+          replaced: old_row.is_some(),
+        });
+      }
+    }
+    tx.commit()?;
+    drop(conn);
+
+    // New rows first: a doc upserted twice in one batch replaced its own row.
+    let mut store = self.store_mut();
+    for upserted in &out {
+      store.set_alive(upserted.row, true);
+    }
+    for row in replaced_rows {
+      store.set_alive(row as usize, false);
+    }
+    Ok(out)
+  }
+
+  fn delete(&self, doc_id: &str) -> anyhow::Result<bool> {
+    self.check_writable()?;
+    let mut conn = self.writer()?;
+    let tx = conn.transaction()?;
+    let row: Option<i64> = tx
+      .query_row("SELECT row FROM vec_map WHERE doc_id = ?1", [doc_id], |r| r.get(0))
+      .optional()?;
+    let Some(row) = row else {
+      return Ok(false);
+    };
+    tx.execute("DELETE FROM vec_map WHERE doc_id = ?1", [doc_id])?;
+    tx.execute("DELETE FROM doc WHERE doc_id = ?1", [doc_id])?;
+    tx.commit()?;
+    drop(conn);
+    self.store_mut().set_alive(row as usize, false);
+    Ok(true)
+  }
+
+  /// Flushes the WAL into a new segment and merges small or mostly-dead
+  /// segments into it. The segment is written without blocking searches; only
+  /// the swap takes the store's write lock. Callers serialize writers.
+  fn compact(&self) -> anyhow::Result<Option<Compacted>> {
+    self.check_writable()?;
+    let Some(plan) = self.store().plan_compaction() else {
+      return Ok(None);
+    };
+    let done = Compacted {
+      wal_rows: plan.wal_rows,
+      merged_segments: plan.inputs.len(),
+      rows: plan.rows.len(),
+    };
+    let columns = self.store().columns().to_vec();
+    let segment = VectorStore::write_segment(&self.dir, self.dims, &columns, &plan)?;
+    self.store_mut().finish_compaction(plan, segment)?;
+    Ok(Some(done))
+  }
+}
+
+fn open_sqlite(path: &Path, flags: OpenFlags) -> anyhow::Result<Connection> {
+  let conn =
+    Connection::open_with_flags(path, flags).map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+  conn.busy_timeout(SQLITE_BUSY_TIMEOUT)?;
+  Ok(conn)
+}
+
+pub struct Collection {
+  pub name: String,
+  pub model: Arc<SharedModel>,
+  index: RwLock<Arc<Index>>,
+  generation: AtomicU64,
+  /// Serializes reloads, compactions and `/api/docs` writes; searches only ever
+  /// take the `index` read lock.
+  writing: Mutex<()>,
+  /// Held for the collection's lifetime when the index is writable, so
+  /// reloads reopen it without racing another writer.
+  _write_lock: Option<WriteLock>,
+}
+
+impl Collection {
+  pub fn new(name: String, model: Arc<SharedModel>, index: Index, write_lock: Option<WriteLock>) -> Self {
+    Self {
+      name,
+      model,
+      index: RwLock::new(Arc::new(index)),
+      generation: AtomicU64::new(1),
+      writing: Mutex::new(()),
+      _write_lock: write_lock,
+    }
+  }
+
+  /// The current generation. Callers keep the `Arc` for the whole request, so
+  /// a reload never swaps the index out from under a running search.
+  pub fn index(&self) -> Arc<Index> {
+    match self.index.read() {
+      Ok(index) => index.clone(),
+      Err(poisoned) => poisoned.into_inner().clone(),
+    }
+  }
+
+  pub fn generation(&self) -> u64 {
+    self.generation.load(Ordering::Relaxed)
+  }
+
+  /// Re-opens the index directory and swaps it in once it validates; on error
+  /// the current generation keeps serving. Blocking (mmap + sqlite).
+  pub fn reload(&self) -> anyhow::Result<Arc<Index>> {
+    let _writing = self.writing.lock();
+    self.reopen()
+  }
+
+  /// Upserts already-embedded docs into the WAL. Blocking.
+  pub fn upsert(&self, docs: Vec<NewDoc>) -> anyhow::Result<Vec<Upserted>> {
+    let _writing = self.writing.lock();
+    let index = self.index();
+    let out = index.upsert(docs)?;
+    // Keep the WAL small between the periodic compactions.
+    if index.wal_rows() >= WAL_FLUSH_ROWS {
+      if let Err(e) = index.compact() {
+        warn!(collection = %self.name, error = %e, "WAL flush failed");
+      }
+    }
+    Ok(out)
+  }
+
+  /// Returns whether `doc_id` existed. Blocking.
+  pub fn delete(&self, doc_id: &str) -> anyhow::Result<bool> {
+    let _writing = self.writing.lock();
+    self.index().delete(doc_id)
+  }
+
+  /// Flushes the WAL and merges segments (see `Index::compact`). The current
+  /// generation is updated in place, so no reload is needed. Blocking.
+  pub fn compact(&self) -> anyhow::Result<Option<Compacted>> {
+    let _writing = self.writing.lock();
+    self.index().compact()
+  }
+
+  fn reopen(&self) -> anyhow::Result<Arc<Index>> {
+    let old = self.index();
+    let index = if old.writable {
+      Index::open_writer(&old.dir, &self.model.spec)?
+    } else {
+      Index::open(&old.dir, &self.model.spec)?
+    };
+    let index = Arc::new(index);
+    let mut current = match self.index.write() {
+      Ok(current) => current,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    *current = index.clone();
+    self.generation.fetch_add(1, Ordering::Relaxed);
+    Ok(index)
+  }
+}
+
+/// Where a collection lives on disk and which model directory it needs.
+pub struct CollectionSource {
+  pub name: String,
+  pub index_dir: PathBuf,
+  /// `None` = the server's `<data-dir>/model`.
+  pub model_dir: Option<PathBuf>,
+}
+
+/// Lists collections: `default` from `<data-dir>/index` (when present), every
+/// subdirectory of `<data-dir>/collections`, then `--collection NAME=DIR`.
+/// A collection directory holds the index files and may carry its own `model/`.
+pub fn discover(data_dir: &Path, extra: &[String]) -> anyhow::Result<Vec<CollectionSource>> {
+  let mut out: Vec<CollectionSource> = Vec::new();
+
+  let default_dir = data_dir.join("index");
+  if default_dir.join("vectors.f32").exists() {
+    out.push(CollectionSource {
+      name: DEFAULT_COLLECTION.to_string(),
+      index_dir: default_dir,
+      model_dir: None,
+    });
+  }
+
+  let mut candidates: Vec<(String, PathBuf)> = Vec::new();
+  let collections_dir = data_dir.join("collections");
+  if collections_dir.is_dir() {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(&collections_dir)? {
+      let path = entry?.path();
+      if path.is_dir() {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+          found.push((name.to_string(), path.clone()));
+        }
+      }
+    }
+    found.sort();
+    candidates.extend(found);
+  }
+  for spec in extra {
+    let Some((name, dir)) = spec.split_once('=') else {
+      anyhow::bail!("--collection `{spec}`: expected NAME=DIR");
+    };
+    candidates.push((name.trim().to_string(), PathBuf::from(dir)));
+  }
+
+  for (name, dir) in candidates {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+      anyhow::bail!("collection name `{name}` must be non-empty [A-Za-z0-9_-]");
+    }
+    if out.iter().any(|c| c.name == name) {
+      anyhow::bail!("duplicate collection name `{name}`");
+    }
+    let model_dir = dir.join("model");
+    out.push(CollectionSource {
+      name,
+      model_dir: model_dir.is_dir().then_some(model_dir),
+      index_dir: dir,
+    });
+  }
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{index_builder::create_tables, vector_file::write_vector_file};
+
+  fn model() -> ModelSpec {
+    ModelSpec {
+      dims: 2,
+      ..ModelSpec::bge_m3()
+    }
+  }
+
+  fn doc(doc_id: &str, vector: [f32; 2]) -> NewDoc {
+    NewDoc {
+      doc_id: doc_id.to_string(),
+      manga_id: None,
+      title: doc_id.to_string(),
+      text: String::new(),
+      vector: vector.to_vec(),
+      field_vectors: Vec::new(),
+    }
+  }
+
+  fn alive(index: &Index) -> Vec<usize> {
+    let mut rows: Vec<usize> = index
+      .search(&[], &[1.0, 0.0], 10)
+      .unwrap()
+      .into_iter()
+      .map(|(row, _)| row)
+      .collect();
+    rows.sort();
+    rows
+  }
+
+  #[test]
+  fn upsert_delete_then_compaction_keeps_the_right_rows_alive() {
+    let dir = std::env::temp_dir().join(format!("local-search-collection-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    write_vector_file(&dir.join("vectors.f32"), 2, &[]).unwrap();
+    create_tables(&Connection::open(dir.join("doc_meta.sqlite")).unwrap()).unwrap();
+    let index = Index::open_writer(&dir, &model()).unwrap();
+
+    let out = index
+      .upsert(vec![doc("a", [1.0, 0.0]), doc("b", [0.0, 1.0]), doc("c", [0.6, 0.8])])
+      .unwrap();
+    assert_eq!(out.iter().map(|u| u.row).collect::<Vec<_>>(), [0, 1, 2]);
+    // "d" twice in one batch: the second copy replaces the first.
+    let out = index
+      .upsert(vec![doc("a", [0.8, 0.6]), doc("d", [1.0, 0.0]), doc("d", [0.0, 1.0])])
+      .unwrap();
+    assert_eq!(out.iter().map(|u| u.replaced).collect::<Vec<_>>(), [true, false, true]);
+    assert!(index.delete("b").unwrap());
+    assert!(!index.delete("b").unwrap());
+    assert_eq!(index.docs(), 3);
+    assert_eq!(alive(&index), [2, 3, 5]);
+
+    let done = index.compact().unwrap().unwrap();
+    assert_eq!((done.wal_rows, done.rows), (6, 3));
+    assert_eq!(index.wal_rows(), 0);
+    assert_eq!(alive(&index), [2, 3, 5]);
+    let a = index.get_doc("a").unwrap().unwrap();
+    assert_eq!((a.row, a.segment.as_str()), (3, "seg-000001"));
+    assert_eq!(index.vector(5), Some(vec![0.0, 1.0]));
+    assert!(index.get_doc("b").unwrap().is_none());
+
+    let reopened = Index::open(&dir, &model()).unwrap();
+    assert_eq!(alive(&reopened), [2, 3, 5]);
+    assert_eq!(reopened.wal_rows(), 0);
+    drop((index, reopened));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn only_one_writer_holds_an_index() {
+    let dir = std::env::temp_dir().join(format!("local-search-write-lock-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let lock = WriteLock::acquire(&dir).unwrap();
+    let err = WriteLock::acquire(&dir).err().unwrap();
+    assert!(err.to_string().contains("already open for writing"), "{err}");
+    drop(lock);
+    WriteLock::acquire(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,191 @@
+use serde_json::Value;
+
+pub const DEFAULT_DOC_TEMPLATE: &str = "{text}";
+
+/// Composes the text that gets embedded from corpus fields, e.g.
+/// `"{title}\n태그: {tags}\n{text}"`. `{{`/`}}` are literal braces and `\n`/`\t`
+/// are unescaped so templates can be passed on a shell command line.
+#[derive(Debug, Clone)]
+pub struct DocTemplate {
+  source: String,
+  parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+  Literal(String),
+  Field(String),
+}
+
+impl DocTemplate {
+  pub fn parse(source: &str) -> anyhow::Result<Self> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+      match c {
+        '{' if chars.peek() == Some(&'{') => {
+          chars.next();
+          literal.push('{');
+        }
+        '}' if chars.peek() == Some(&'}') => {
+          chars.next();
+          literal.push('}');
+        }
+        '{' => {
+          let mut name = String::new();
+          loop {
+            match chars.next() {
+              Some('}') => break,
+              Some(c) => name.push(c),
+              None => anyhow::bail!("doc template: unclosed `{{{name}`"),
+            }
+          }
+          let name = name.trim();
+          if name.is_empty() {
+            anyhow::bail!("doc template: empty field name `{{}}`");
+          }
+          if !literal.is_empty() {
+            parts.push(Part::Literal(std::mem::take(&mut literal)));
+          }
+          parts.push(Part::Field(name.to_string()));
+        }
+        '}' => anyhow::bail!("doc template: unmatched `}}` (use `}}}}` for a literal brace)"),
+        '\\' => match chars.peek() {
+          Some('n') => {
+            chars.next();
+            literal.push('\n');
+          }
+          Some('t') => {
+            chars.next();
+            literal.push('\t');
+          }
+          _ => literal.push('\\'),
+        },
+        c => literal.push(c),
+      }
+    }
+    if !literal.is_empty() {
+      parts.push(Part::Literal(literal));
+    }
+
+    if !parts.iter().any(|p| matches!(p, Part::Field(_))) {
+      anyhow::bail!("doc template must reference at least one field, e.g. {{text}}");
+    }
+
+    Ok(Self {
+      source: source.to_string(),
+      parts,
+    })
+  }
+
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  pub fn fields(&self) -> impl Iterator<Item = &str> {
+    self.parts.iter().filter_map(|p| match p {
+      Part::Field(name) => Some(name.as_str()),
+      Part::Literal(_) => None,
+    })
+  }
+
+  /// Renders with `lookup` resolving field names. Missing fields render empty.
+  /// Template whitespace before the first and after the last non-empty field
+  /// is dropped so absent leading/trailing fields leave no blank lines; field
+  /// values themselves are never trimmed, so `{text}` renders byte-identical.
+  pub fn render<'a>(&self, lookup: impl Fn(&str) -> Option<&'a Value>) -> String {
+    let pieces: Vec<(bool, String)> = self
+      .parts
+      .iter()
+      .map(|part| match part {
+        Part::Literal(s) => (false, s.clone()),
+        Part::Field(name) => {
+          let mut value = String::new();
+          if let Some(v) = lookup(name) {
+            push_value(&mut value, v);
+          }
+          (true, value)
+        }
+      })
+      .collect();
+    let filled = |&(is_field, ref s): &(bool, String)| is_field && !s.is_empty();
+    let first = pieces.iter().position(filled).unwrap_or(pieces.len());
+    let last = pieces.iter().rposition(filled);
+
+    let mut out = String::new();
+    for (i, (is_field, s)) in pieces.iter().enumerate() {
+      let mut s = s.as_str();
+      if !is_field && i < first {
+        s = s.trim_start();
+      }
+      if !is_field && last.is_none_or(|last| i > last) {
+        s = s.trim_end();
+      }
+      out.push_str(s);
+    }
+    out
+  }
+}
+
+/// Strings as-is, arrays joined with ", ", null as nothing.
+fn push_value(out: &mut String, v: &Value) {
+  match v {
+    Value::Null => {}
+    Value::String(s) => out.push_str(s),
+    Value::Array(items) => {
+      let mut first = true;
+      for item in items {
+        if item.is_null() {
+          continue;
+        }
+        if !first {
+          out.push_str(", ");
+        }
+        first = false;
+        push_value(out, item);
+      }
+    }
+    other => out.push_str(&other.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn render(template: &str, fields: &Value) -> String {
+    DocTemplate::parse(template).unwrap().render(|name| fields.get(name))
+  }
+
+  #[test]
+  fn text_field_is_byte_identical() {
+    let fields = json!({ "text": "  leading and trailing\n\n" });
+    assert_eq!(render(DEFAULT_DOC_TEMPLATE, &fields), "  leading and trailing\n\n");
+  }
+
+  #[test]
+  fn joins_arrays_and_skips_nulls() {
+    let fields = json!({ "title": "원피스", "tags": ["모험", null, "해적"], "pages": 12 });
+    assert_eq!(render("{title}\\n태그: {tags} ({pages})", &fields), "원피스\n태그: 모험, 해적 (12)");
+  }
+
+  #[test]
+  fn missing_edge_fields_leave_no_blank_lines() {
+    let fields = json!({ "text": "본문" });
+    assert_eq!(render("{title}\\n{text}\\n{tags}", &fields), "본문");
+    assert_eq!(render("{title}\\n{text}", &json!({ "title": null, "text": " x" })), " x");
+  }
+
+  #[test]
+  fn braces_and_errors() {
+    assert_eq!(render("{{{text}}}", &json!({ "text": "a" })), "{a}");
+    assert!(DocTemplate::parse("no fields").is_err());
+    assert!(DocTemplate::parse("{text").is_err());
+    assert!(DocTemplate::parse("text}").is_err());
+    assert!(DocTemplate::parse("{ }").is_err());
+  }
+}
@@ -0,0 +1,55 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader},
+  path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::embedder::InputKind;
+
+/// One text for `embed`: a line of a `.txt` file, or a JSONL line with `text`
+/// (and optionally `id` and `kind`).
+#[derive(Serialize, Deserialize)]
+pub struct EmbedText {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<serde_json::Value>,
+  pub text: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub kind: Option<InputKind>,
+}
+
+pub fn load_texts(path: &Path) -> anyhow::Result<Vec<EmbedText>> {
+  let f = File::open(path).map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+  let jsonl = path.extension().is_some_and(|e| e == "jsonl");
+  let mut out = Vec::new();
+  for (i, line) in BufReader::new(f).lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let text = if jsonl {
+      serde_json::from_str(&line).map_err(|e| anyhow::anyhow!("{}:{}: {e}", path.display(), i + 1))?
+    } else {
+      EmbedText {
+        id: None,
+        text: line,
+        kind: None,
+      }
+    };
+    out.push(text);
+  }
+  Ok(out)
+}
+
+/// Output line of `embed --format jsonl`; also a valid `verify-model` reference.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddedText<'a> {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub id: Option<&'a serde_json::Value>,
+  pub text: &'a str,
+  pub kind: InputKind,
+  pub max_length: usize,
+  pub embedding: &'a [f32],
+}
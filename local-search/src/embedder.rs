@@ -1,49 +1,281 @@
-use std::path::Path;
+use std::{
+  borrow::Cow,
+  cell::Cell,
+  fs,
+  path::Path,
+  time::{Duration, Instant},
+};
 
-use ort::session::builder::GraphOptimizationLevel;
-use ort::{inputs, session::Session, value::Tensor};
+use ort::session::Session;
+use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
+use tracing::info;
 
-pub struct BgeM3Embedder {
-  tokenizer: Tokenizer,
-  session: Session,
+use crate::{
+  long_doc::{plan_windows, DocEmbedding, LongDocOptions, LongDocStrategy},
+  model_io::{ModelIo, OutputKind},
+  session_options::{build_session, SessionOptions},
+};
+
+/// Whether a text is a search query or an indexed document. Models like e5 or
+/// bge-*-en expect different instruction prefixes for the two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum InputKind {
+  #[default]
+  Query,
+  Document,
 }
 
-impl BgeM3Embedder {
-  pub fn new(
-    model_path: &Path,
-    tokenizer_path: &Path,
-    ort_dylib_path: Option<&Path>,
-  ) -> anyhow::Result<Self> {
-    if let Some(p) = ort_dylib_path {
-      let ok = ort::init_from(p.to_string_lossy().as_ref())?.commit();
-      if !ok {
-        anyhow::bail!("failed to initialize onnxruntime from provided dylib path");
-      }
+pub struct EmbedInput<'a> {
+  pub text: &'a str,
+  pub max_length: usize,
+  pub kind: InputKind,
+}
+
+/// A dense text embedding model.
+pub trait Embedder: Send {
+  fn spec(&self) -> &ModelSpec;
+
+  /// Raw (not normalized) embeddings, one per input, each `spec().dims` long.
+  fn embed_batch(&mut self, inputs: &[EmbedInput<'_>]) -> anyhow::Result<Vec<Vec<f32>>>;
+
+  fn dims(&self) -> usize {
+    self.spec().dims
+  }
+
+  /// Tokenizer and forward-pass time accumulated since the last call.
+  fn take_stage_times(&mut self) -> StageTimes {
+    StageTimes::default()
+  }
+
+  /// Normalized document vector for text that may exceed `max_length` tokens.
+  fn embed_long_document(
+    &mut self,
+    text: &str,
+    max_length: usize,
+    opts: &LongDocOptions,
+  ) -> anyhow::Result<DocEmbedding>;
+}
+
+/// Where embedding time went, for `/api/stats` and `bench`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimes {
+  pub tokenize: Duration,
+  pub onnx: Duration,
+}
+
+/// How token vectors are reduced to one text vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pooling {
+  /// First token (`[CLS]`/`<s>`), as used by bge-m3.
+  Cls,
+  /// Attention-masked mean over all tokens (e5, most sentence-transformers).
+  Mean,
+  /// Last non-padding token (decoder-style embedders).
+  LastToken,
+}
+
+/// Describes a model directory. Read from `model.json` next to the model when
+/// present; otherwise the bge-m3 defaults are assumed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelSpec {
+  pub id: String,
+  pub model_file: String,
+  #[serde(default = "default_tokenizer_file")]
+  pub tokenizer_file: String,
+  pub dims: usize,
+  pub pooling: Pooling,
+  #[serde(default)]
+  pub query_prefix: String,
+  #[serde(default)]
+  pub document_prefix: String,
+  /// Output to read; detected from the model when unset.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub output_name: Option<String>,
+}
+
+fn default_tokenizer_file() -> String {
+  "tokenizer.json".to_string()
+}
+
+impl ModelSpec {
+  pub fn bge_m3() -> Self {
+    Self {
+      id: "BAAI/bge-m3".to_string(),
+      model_file: "bge-m3.onnx".to_string(),
+      tokenizer_file: default_tokenizer_file(),
+      dims: 1024,
+      pooling: Pooling::Cls,
+      query_prefix: String::new(),
+      document_prefix: String::new(),
+      output_name: None,
+    }
+  }
+
+  pub fn load(model_dir: &Path) -> anyhow::Result<Self> {
+    let path = model_dir.join("model.json");
+    if !path.exists() {
+      return Ok(Self::bge_m3());
+    }
+    let spec: Self = serde_json::from_str(&fs::read_to_string(&path)?)
+      .map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))?;
+    if spec.dims == 0 {
+      anyhow::bail!("{}: dims must be > 0", path.display());
+    }
+    Ok(spec)
+  }
+
+  /// Whether vectors from `self` and `other` live in the same embedding space
+  /// (file names may differ, e.g. fp32 vs int8 exports of one model).
+  pub fn is_compatible(&self, other: &ModelSpec) -> bool {
+    self.id == other.id
+      && self.dims == other.dims
+      && self.pooling == other.pooling
+      && self.query_prefix == other.query_prefix
+      && self.document_prefix == other.document_prefix
+  }
+
+  fn prefixed<'a>(&self, text: &'a str, kind: InputKind) -> Cow<'a, str> {
+    let prefix = match kind {
+      InputKind::Query => &self.query_prefix,
+      InputKind::Document => &self.document_prefix,
+    };
+    if prefix.is_empty() {
+      Cow::Borrowed(text)
     } else {
-      // Best-effort init: works if ORT is discoverable.
-      let ok = ort::init().commit();
-      if !ok {
-        anyhow::bail!("failed to initialize onnxruntime (ORT not discoverable?)");
-      }
+      Cow::Owned(format!("{prefix}{text}"))
     }
+  }
+}
+
+/// Initializes the process-wide onnxruntime environment. Must run once before any
+/// `OnnxEmbedder` is created.
+pub fn init_ort(ort_dylib_path: Option<&Path>) -> anyhow::Result<()> {
+  if let Some(p) = ort_dylib_path {
+    let ok = ort::init_from(p.to_string_lossy().as_ref())?.commit();
+    if !ok {
+      anyhow::bail!("failed to initialize onnxruntime from provided dylib path");
+    }
+  } else {
+    // Best-effort init: works if ORT is discoverable.
+    let ok = ort::init().commit();
+    if !ok {
+      anyhow::bail!("failed to initialize onnxruntime (ORT not discoverable?)");
+    }
+  }
+  Ok(())
+}
 
-    let tokenizer = Tokenizer::from_file(tokenizer_path)
+/// Transformer encoder exported to ONNX plus a HuggingFace tokenizer.
+pub struct OnnxEmbedder {
+  spec: ModelSpec,
+  tokenizer: Tokenizer,
+  session: Session,
+  io: ModelIo,
+  /// `tokenize` takes `&self`, hence the cells.
+  tokenize_time: Cell<Duration>,
+  onnx_time: Cell<Duration>,
+}
+
+impl OnnxEmbedder {
+  pub fn new(model_dir: &Path, spec: ModelSpec, session_options: &SessionOptions) -> anyhow::Result<Self> {
+    let tokenizer = Tokenizer::from_file(model_dir.join(&spec.tokenizer_file))
       .map_err(|e| anyhow::anyhow!("failed to load tokenizer: {e}"))?;
 
-    let session = Session::builder()?
-      .with_optimization_level(GraphOptimizationLevel::Level3)?
-      .commit_from_file(model_path)
-      .map_err(|e| anyhow::anyhow!("failed to load onnx: {e}"))?;
+    let model_path = model_dir.join(&spec.model_file);
+    let session = build_session(&model_path, session_options)?;
+    let io = ModelIo::detect(&session, &spec)
+      .map_err(|e| anyhow::anyhow!("{} doesn't fit {}: {e}", model_path.display(), spec.id))?;
+    info!(
+      model = %spec.id,
+      inputs = ?io.feed_names(),
+      output = %io.output,
+      output_kind = ?io.output_kind,
+      "model io detected"
+    );
 
-    Ok(Self { tokenizer, session })
+    Ok(Self {
+      spec,
+      tokenizer,
+      session,
+      io,
+      tokenize_time: Cell::new(Duration::ZERO),
+      onnx_time: Cell::new(Duration::ZERO),
+    })
   }
 
-  pub fn embed_dense_cls(&mut self, text: &str, max_length: usize) -> anyhow::Result<Vec<f32>> {
+  /// Runs one padded forward pass over already tokenized inputs.
+  fn run(&mut self, encoded: &[Encoded]) -> anyhow::Result<Vec<Vec<f32>>> {
+    if encoded.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let batch = encoded.len();
+    let seq = encoded.iter().map(|e| e.ids.len()).max().unwrap_or(1).max(1);
+    let pad_id = self.tokenizer.token_to_id("<pad>").unwrap_or(0) as i64;
+
+    let mut ids = Vec::with_capacity(batch * seq);
+    let mut mask = Vec::with_capacity(batch * seq);
+    let mut type_ids = Vec::with_capacity(batch * seq);
+    for e in encoded {
+      let pad = seq - e.ids.len();
+      ids.extend_from_slice(&e.ids);
+      ids.extend(std::iter::repeat_n(pad_id, pad));
+      mask.extend_from_slice(&e.mask);
+      mask.extend(std::iter::repeat_n(0, pad));
+      type_ids.extend_from_slice(&e.type_ids);
+      type_ids.extend(std::iter::repeat_n(0, pad));
+    }
+
+    let feeds = self.io.feeds(batch, seq, &ids, &mask, &type_ids)?;
+    let started = Instant::now();
+    let outputs = self.session.run(feeds)?;
+    self.onnx_time.set(self.onnx_time.get() + started.elapsed());
+
+    let output = outputs
+      .get(&self.io.output)
+      .ok_or_else(|| anyhow::anyhow!("model returned no `{}` output", self.io.output))?;
+    let (_shape, data) = output.try_extract_tensor::<f32>()?;
+    let dims = self.spec.dims;
+
+    if self.io.output_kind == OutputKind::Pooled {
+      if data.len() != batch * dims {
+        anyhow::bail!("unexpected output size {} (expected [{batch}, {dims}])", data.len());
+      }
+      return Ok(data.chunks_exact(dims).map(|c| c.to_vec()).collect());
+    }
+    if data.len() != batch * seq * dims {
+      anyhow::bail!(
+        "unexpected output size {} (expected [{batch}, {seq}, {dims}])",
+        data.len()
+      );
+    }
+
+    Ok(
+      (0..batch)
+        .map(|b| {
+          let hidden = &data[b * seq * dims..(b + 1) * seq * dims];
+          pool(hidden, &mask[b * seq..(b + 1) * seq], dims, self.spec.pooling)
+        })
+        .collect(),
+    )
+  }
+
+  fn tokenize(&self, text: &str, add_special_tokens: bool) -> anyhow::Result<tokenizers::Encoding> {
+    let started = Instant::now();
     let enc = self
       .tokenizer
-      .encode(text, true)
-      .map_err(|e| anyhow::anyhow!("tokenize failed: {e}"))?;
+      .encode(text, add_special_tokens)
+      .map_err(|e| anyhow::anyhow!("tokenize failed: {e}"));
+    self.tokenize_time.set(self.tokenize_time.get() + started.elapsed());
+    enc
+  }
+
+  fn encode(&self, text: &str, max_length: usize) -> anyhow::Result<Encoded> {
+    let enc = self.tokenize(text, true)?;
 
     let mut ids: Vec<i64> = enc.get_ids().iter().map(|&v| v as i64).collect();
     let mut mask: Vec<i64> = enc
@@ -63,7 +295,6 @@ impl BgeM3Embedder {
       type_ids.truncate(max_length);
     }
 
-    let seq = ids.len().max(1);
     if ids.is_empty() {
       // Safety: shouldn't happen, but keep shapes valid.
       ids.push(0);
@@ -71,32 +302,166 @@ impl BgeM3Embedder {
       type_ids.push(0);
     }
 
-    let input_ids = Tensor::from_array(([1usize, seq], ids))?;
-    let attention_mask = Tensor::from_array(([1usize, seq], mask))?;
-    let token_type_ids = Tensor::from_array(([1usize, seq], type_ids))?;
+    Ok(Encoded { ids, mask, type_ids })
+  }
+}
+
+struct Encoded {
+  ids: Vec<i64>,
+  mask: Vec<i64>,
+  type_ids: Vec<i64>,
+}
 
-    let outputs = self.session.run(inputs![
-      "input_ids" => input_ids,
-      "attention_mask" => attention_mask,
-      "token_type_ids" => token_type_ids
-    ])?;
+impl Embedder for OnnxEmbedder {
+  fn spec(&self) -> &ModelSpec {
+    &self.spec
+  }
 
-    let (_shape, data) = outputs[0].try_extract_tensor::<f32>()?;
-    if data.len() < 1024 {
-      anyhow::bail!("unexpected output size: {}", data.len());
+  fn take_stage_times(&mut self) -> StageTimes {
+    StageTimes {
+      tokenize: self.tokenize_time.take(),
+      onnx: self.onnx_time.take(),
     }
+  }
 
-    Ok(data[0..1024].to_vec())
+  /// Embeds several texts in one padded forward pass. Each item is truncated to
+  /// its own max length; shorter sequences are right-padded with mask 0, which
+  /// pooling ignores.
+  fn embed_batch(&mut self, inputs: &[EmbedInput<'_>]) -> anyhow::Result<Vec<Vec<f32>>> {
+    if inputs.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let mut encoded = Vec::with_capacity(inputs.len());
+    for input in inputs {
+      let text = self.spec.prefixed(input.text, input.kind);
+      encoded.push(self.encode(&text, input.max_length)?);
+    }
+    self.run(&encoded)
   }
 
-  pub fn embed_dense_cls_normalized(&mut self, text: &str, max_length: usize) -> anyhow::Result<Vec<f32>> {
-    let mut v = self.embed_dense_cls(text, max_length)?;
-    l2_normalize_in_place(&mut v);
-    Ok(v)
+  /// Texts that fit (or `head`) go through the regular path. Otherwise the
+  /// content tokens are re-framed with the model's leading/trailing special
+  /// tokens (and the document prefix) per window, so each window looks like a
+  /// normal input to the model.
+  fn embed_long_document(
+    &mut self,
+    text: &str,
+    max_length: usize,
+    opts: &LongDocOptions,
+  ) -> anyhow::Result<DocEmbedding> {
+    let prefixed = self.spec.prefixed(text, InputKind::Document);
+    let tokens = self.tokenize(&prefixed, true)?.get_ids().len();
+
+    if tokens <= max_length || opts.strategy == LongDocStrategy::Head {
+      let mut out = self.run(&[self.encode(&prefixed, max_length)?])?;
+      let mut vector = out.pop().ok_or_else(|| anyhow::anyhow!("embedder returned no vector"))?;
+      l2_normalize_in_place(&mut vector);
+      return Ok(DocEmbedding {
+        vector,
+        tokens,
+        truncated: tokens > max_length,
+        windows: 1,
+      });
+    }
+
+    let enc = self.tokenize(text, true)?;
+    let ids: Vec<i64> = enc.get_ids().iter().map(|&v| v as i64).collect();
+    let special = enc.get_special_tokens_mask();
+    let lead = special.iter().take_while(|&&m| m == 1).count();
+    let trail = special[lead..].iter().rev().take_while(|&&m| m == 1).count();
+    let content = &ids[lead..ids.len() - trail];
+
+    let prefix_ids: Vec<i64> = if self.spec.document_prefix.is_empty() {
+      Vec::new()
+    } else {
+      self
+        .tokenize(&self.spec.document_prefix, false)?
+        .get_ids()
+        .iter()
+        .map(|&v| v as i64)
+        .collect()
+    };
+
+    let overhead = lead + trail + prefix_ids.len();
+    if overhead >= max_length {
+      anyhow::bail!("max length {max_length} leaves no room for content ({overhead} special/prefix tokens)");
+    }
+    let (windows, truncated) = plan_windows(content.len(), max_length - overhead, opts);
+
+    let encoded: Vec<Encoded> = windows
+      .iter()
+      .map(|ranges| {
+        let mut window = ids[..lead].to_vec();
+        window.extend_from_slice(&prefix_ids);
+        for r in ranges {
+          window.extend_from_slice(&content[r.clone()]);
+        }
+        window.extend_from_slice(&ids[ids.len() - trail..]);
+        let n = window.len();
+        Encoded {
+          ids: window,
+          mask: vec![1; n],
+          type_ids: vec![0; n],
+        }
+      })
+      .collect();
+
+    let dims = self.spec.dims;
+    let mut vector = vec![0.0_f32; dims];
+    for chunk in encoded.chunks(WINDOW_BATCH) {
+      for mut v in self.run(chunk)? {
+        l2_normalize_in_place(&mut v);
+        for (acc, x) in vector.iter_mut().zip(v.iter()) {
+          *acc += x;
+        }
+      }
+    }
+    l2_normalize_in_place(&mut vector);
+
+    Ok(DocEmbedding {
+      vector,
+      tokens,
+      truncated,
+      windows: encoded.len(),
+    })
   }
 }
 
-fn l2_normalize_in_place(v: &mut [f32]) {
+/// Windows of one document embedded per forward pass.
+const WINDOW_BATCH: usize = 8;
+
+/// Reduces one sequence of token vectors (`[seq, dims]`, row-major) to a single vector.
+fn pool(hidden: &[f32], mask: &[i64], dims: usize, pooling: Pooling) -> Vec<f32> {
+  match pooling {
+    Pooling::Cls => hidden[0..dims].to_vec(),
+    Pooling::Mean => {
+      let mut out = vec![0.0_f32; dims];
+      let mut n = 0usize;
+      for (token, &m) in hidden.chunks_exact(dims).zip(mask.iter()) {
+        if m == 0 {
+          continue;
+        }
+        n += 1;
+        for (acc, x) in out.iter_mut().zip(token.iter()) {
+          *acc += x;
+        }
+      }
+      if n > 0 {
+        for x in out.iter_mut() {
+          *x /= n as f32;
+        }
+      }
+      out
+    }
+    Pooling::LastToken => {
+      let last = mask.iter().rposition(|&m| m != 0).unwrap_or(0);
+      hidden[last * dims..(last + 1) * dims].to_vec()
+    }
+  }
+}
+
+pub fn l2_normalize_in_place(v: &mut [f32]) {
   let mut sum_sq = 0.0_f32;
   for &x in v.iter() {
     sum_sq += x * x;
@@ -110,3 +475,38 @@ fn l2_normalize_in_place(v: &mut [f32]) {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Three tokens of two dims; the last one is padding.
+  const HIDDEN: [f32; 6] = [1.0, 2.0, 3.0, 4.0, 100.0, 100.0];
+  const MASK: [i64; 3] = [1, 1, 0];
+
+  #[test]
+  fn cls_takes_the_first_token() {
+    assert_eq!(pool(&HIDDEN, &MASK, 2, Pooling::Cls), [1.0, 2.0]);
+  }
+
+  #[test]
+  fn mean_skips_padding() {
+    assert_eq!(pool(&HIDDEN, &MASK, 2, Pooling::Mean), [2.0, 3.0]);
+    assert_eq!(pool(&HIDDEN, &[0, 0, 0], 2, Pooling::Mean), [0.0, 0.0]);
+  }
+
+  #[test]
+  fn last_token_is_the_last_unmasked_one() {
+    assert_eq!(pool(&HIDDEN, &MASK, 2, Pooling::LastToken), [3.0, 4.0]);
+    assert_eq!(pool(&HIDDEN, &[1, 1, 1], 2, Pooling::LastToken), [100.0, 100.0]);
+  }
+
+  #[test]
+  fn normalize_leaves_zero_vectors_alone() {
+    let mut v = [3.0, 4.0];
+    l2_normalize_in_place(&mut v);
+    assert_eq!(v, [0.6, 0.8]);
+    let mut zero = [0.0, 0.0];
+    l2_normalize_in_place(&mut zero);
+    assert_eq!(zero, [0.0, 0.0]);
+  }
+}
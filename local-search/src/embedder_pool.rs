@@ -0,0 +1,461 @@
+use std::{
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    mpsc::{self, Receiver, SyncSender, TrySendError},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::info;
+
+use crate::{
+  embedder::{EmbedInput, Embedder, InputKind},
+  long_doc::{DocEmbedding, LongDocOptions},
+  metrics::{LatencyRecorder, LatencySummary},
+};
+
+/// Fixed set of embedder sessions, each owned by a worker thread, fed from one
+/// bounded queue. Requests beyond `queue_capacity` are rejected with `PoolBusy`
+/// instead of piling up behind a single lock.
+///
+/// A worker that picks up a job keeps collecting queued jobs for up to
+/// `BatchConfig::max_wait` (or until `max_batch` items) and runs them as one
+/// padded batch, so bursts of small queries share a forward pass.
+pub struct EmbedderPool {
+  tx: SyncSender<Work>,
+  shared: Arc<Shared>,
+  sessions: usize,
+  queue_capacity: usize,
+  batch: BatchConfig,
+  dims: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+  /// Max items per forward pass (1 disables batching).
+  pub max_batch: usize,
+  /// How long a worker waits for more items after the first one arrives.
+  pub max_wait: Duration,
+}
+
+enum Work {
+  Embed(Job),
+  /// Long-document embedding for `/api/docs`; runs on its own, not batched.
+  Document(DocJob),
+}
+
+impl Work {
+  fn enqueued(&self) -> Instant {
+    match self {
+      Work::Embed(job) => job.enqueued,
+      Work::Document(job) => job.enqueued,
+    }
+  }
+}
+
+struct Job {
+  text: String,
+  max_length: usize,
+  kind: InputKind,
+  enqueued: Instant,
+  reply: oneshot::Sender<anyhow::Result<Vec<f32>>>,
+}
+
+impl Job {
+  fn input(&self) -> EmbedInput<'_> {
+    EmbedInput {
+      text: &self.text,
+      max_length: self.max_length,
+      kind: self.kind,
+    }
+  }
+}
+
+struct DocJob {
+  text: String,
+  max_length: usize,
+  opts: LongDocOptions,
+  enqueued: Instant,
+  reply: oneshot::Sender<anyhow::Result<DocEmbedding>>,
+}
+
+struct Shared {
+  queued: AtomicUsize,
+  busy: AtomicUsize,
+  rejected: AtomicU64,
+  batches: AtomicU64,
+  batched_items: AtomicU64,
+  queue_wait: LatencyRecorder,
+  inference: LatencyRecorder,
+  tokenize: LatencyRecorder,
+  onnx: LatencyRecorder,
+}
+
+/// Returned when the queue is full; callers should answer 503.
+#[derive(Debug)]
+pub struct PoolBusy;
+
+impl std::fmt::Display for PoolBusy {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("embedder queue is full, try again shortly")
+  }
+}
+
+impl std::error::Error for PoolBusy {}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+  pub sessions: usize,
+  pub queue_capacity: usize,
+  pub queued: usize,
+  pub busy: usize,
+  pub rejected: u64,
+  pub max_batch: usize,
+  pub max_wait_ms: u64,
+  pub batches: u64,
+  pub avg_batch_size: f64,
+  pub queue_wait_ms: LatencySummary,
+  pub inference_ms: LatencySummary,
+  /// Per job or batch, like `inference_ms`, split into its two stages.
+  pub tokenize_ms: LatencySummary,
+  pub onnx_ms: LatencySummary,
+}
+
+impl EmbedderPool {
+  pub fn new(
+    embedders: Vec<Box<dyn Embedder>>,
+    queue_capacity: usize,
+    batch: BatchConfig,
+  ) -> anyhow::Result<Self> {
+    if embedders.is_empty() {
+      anyhow::bail!("embedder pool needs at least one session");
+    }
+    if queue_capacity == 0 {
+      anyhow::bail!("embed queue capacity must be at least 1");
+    }
+    if !embedders.iter().all(|e| e.spec().is_compatible(embedders[0].spec())) {
+      anyhow::bail!("embedder pool sessions must all use the same model");
+    }
+    let batch = BatchConfig {
+      max_batch: batch.max_batch.max(1),
+      ..batch
+    };
+    let dims = embedders[0].dims();
+
+    let sessions = embedders.len();
+    let (tx, rx) = mpsc::sync_channel::<Work>(queue_capacity);
+    let rx = Arc::new(Mutex::new(rx));
+    let shared = Arc::new(Shared {
+      queued: AtomicUsize::new(0),
+      busy: AtomicUsize::new(0),
+      rejected: AtomicU64::new(0),
+      batches: AtomicU64::new(0),
+      batched_items: AtomicU64::new(0),
+      queue_wait: LatencyRecorder::new(),
+      inference: LatencyRecorder::new(),
+      tokenize: LatencyRecorder::new(),
+      onnx: LatencyRecorder::new(),
+    });
+
+    for (i, embedder) in embedders.into_iter().enumerate() {
+      let rx = rx.clone();
+      let shared = shared.clone();
+      std::thread::Builder::new()
+        .name(format!("embedder-{i}"))
+        .spawn(move || worker_loop(embedder, rx, shared, batch))?;
+    }
+
+    info!(
+      sessions,
+      queue_capacity,
+      max_batch = batch.max_batch,
+      max_wait_ms = batch.max_wait.as_millis() as u64,
+      "embedder pool started"
+    );
+
+    Ok(Self {
+      tx,
+      shared,
+      sessions,
+      queue_capacity,
+      batch,
+      dims,
+    })
+  }
+
+  pub fn dims(&self) -> usize {
+    self.dims
+  }
+
+  /// Embeds `text` on the next free session (raw, not normalized).
+  pub async fn embed(&self, text: String, max_length: usize, kind: InputKind) -> anyhow::Result<Vec<f32>> {
+    let (reply, rx) = oneshot::channel();
+    self.submit(Work::Embed(Job {
+      text,
+      max_length,
+      kind,
+      enqueued: Instant::now(),
+      reply,
+    }))?;
+    rx.await
+      .map_err(|_| anyhow::anyhow!("embedder worker dropped the request"))?
+  }
+
+  /// Embeds a document with the index's long-document strategy (normalized).
+  pub async fn embed_document(
+    &self,
+    text: String,
+    max_length: usize,
+    opts: LongDocOptions,
+  ) -> anyhow::Result<DocEmbedding> {
+    let (reply, rx) = oneshot::channel();
+    self.submit(Work::Document(DocJob {
+      text,
+      max_length,
+      opts,
+      enqueued: Instant::now(),
+      reply,
+    }))?;
+    rx.await
+      .map_err(|_| anyhow::anyhow!("embedder worker dropped the request"))?
+  }
+
+  fn submit(&self, work: Work) -> anyhow::Result<()> {
+    self.shared.queued.fetch_add(1, Ordering::Relaxed);
+    match self.tx.try_send(work) {
+      Ok(()) => Ok(()),
+      Err(TrySendError::Full(_)) => {
+        self.shared.queued.fetch_sub(1, Ordering::Relaxed);
+        self.shared.rejected.fetch_add(1, Ordering::Relaxed);
+        Err(PoolBusy.into())
+      }
+      Err(TrySendError::Disconnected(_)) => {
+        self.shared.queued.fetch_sub(1, Ordering::Relaxed);
+        anyhow::bail!("embedder workers have stopped");
+      }
+    }
+  }
+
+  pub fn stats(&self) -> PoolStats {
+    let batches = self.shared.batches.load(Ordering::Relaxed);
+    let batched_items = self.shared.batched_items.load(Ordering::Relaxed);
+    PoolStats {
+      sessions: self.sessions,
+      queue_capacity: self.queue_capacity,
+      queued: self.shared.queued.load(Ordering::Relaxed),
+      busy: self.shared.busy.load(Ordering::Relaxed),
+      rejected: self.shared.rejected.load(Ordering::Relaxed),
+      max_batch: self.batch.max_batch,
+      max_wait_ms: self.batch.max_wait.as_millis() as u64,
+      batches,
+      avg_batch_size: if batches == 0 {
+        0.0
+      } else {
+        batched_items as f64 / batches as f64
+      },
+      queue_wait_ms: self.shared.queue_wait.summary(),
+      inference_ms: self.shared.inference.summary(),
+      tokenize_ms: self.shared.tokenize.summary(),
+      onnx_ms: self.shared.onnx.summary(),
+    }
+  }
+
+  /// Clears the latency windows and batch counters.
+  pub fn reset_stats(&self) {
+    for recorder in [&self.shared.queue_wait, &self.shared.inference, &self.shared.tokenize, &self.shared.onnx] {
+      recorder.reset();
+    }
+    self.shared.batches.store(0, Ordering::Relaxed);
+    self.shared.batched_items.store(0, Ordering::Relaxed);
+  }
+}
+
+impl Shared {
+  fn record_stages(&self, embedder: &mut dyn Embedder) {
+    let times = embedder.take_stage_times();
+    self.tokenize.record(times.tokenize);
+    self.onnx.record(times.onnx);
+  }
+}
+
+fn worker_loop(
+  mut embedder: Box<dyn Embedder>,
+  rx: Arc<Mutex<Receiver<Work>>>,
+  shared: Arc<Shared>,
+  batch: BatchConfig,
+) {
+  loop {
+    let work = {
+      let Ok(rx) = rx.lock() else {
+        return;
+      };
+      match collect_batch(&rx, batch) {
+        Some(work) => work,
+        // Sender dropped: the pool is shutting down.
+        None => return,
+      }
+    };
+
+    shared.queued.fetch_sub(work.len(), Ordering::Relaxed);
+    let mut jobs = Vec::with_capacity(work.len());
+    let mut docs = Vec::new();
+    for w in work {
+      shared.queue_wait.record(w.enqueued().elapsed());
+      match w {
+        Work::Embed(job) => jobs.push(job),
+        Work::Document(job) => docs.push(job),
+      }
+    }
+
+    for job in docs {
+      shared.busy.fetch_add(1, Ordering::Relaxed);
+      let started = Instant::now();
+      let result = embedder.embed_long_document(&job.text, job.max_length, &job.opts);
+      shared.inference.record(started.elapsed());
+      shared.record_stages(embedder.as_mut());
+      shared.busy.fetch_sub(1, Ordering::Relaxed);
+      let _ = job.reply.send(result);
+    }
+    if jobs.is_empty() {
+      continue;
+    }
+
+    shared.busy.fetch_add(1, Ordering::Relaxed);
+    let started = Instant::now();
+    let inputs: Vec<EmbedInput<'_>> = jobs.iter().map(Job::input).collect();
+    let result = embedder.embed_batch(&inputs);
+    drop(inputs);
+    shared.inference.record(started.elapsed());
+    shared.record_stages(embedder.as_mut());
+    shared.busy.fetch_sub(1, Ordering::Relaxed);
+    shared.batches.fetch_add(1, Ordering::Relaxed);
+    shared.batched_items.fetch_add(jobs.len() as u64, Ordering::Relaxed);
+
+    // The handler may have gone away (client disconnect); nothing to do then.
+    match result {
+      Ok(vectors) => {
+        for (job, v) in jobs.into_iter().zip(vectors) {
+          let _ = job.reply.send(Ok(v));
+        }
+      }
+      Err(e) if jobs.len() == 1 => {
+        let _ = jobs.remove(0).reply.send(Err(e));
+      }
+      // One bad input fails the whole forward pass; rerun the jobs one by one
+      // so only the bad one gets an error.
+      Err(_) => {
+        for job in jobs {
+          shared.busy.fetch_add(1, Ordering::Relaxed);
+          let started = Instant::now();
+          let result = embedder
+            .embed_batch(&[job.input()])
+            .and_then(|vectors| vectors.into_iter().next().ok_or_else(|| anyhow::anyhow!("embedder returned no vector")));
+          shared.inference.record(started.elapsed());
+          shared.record_stages(embedder.as_mut());
+          shared.busy.fetch_sub(1, Ordering::Relaxed);
+          let _ = job.reply.send(result);
+        }
+      }
+    }
+  }
+}
+
+/// Blocks for the first job, then gathers more until the batch is full or
+/// `max_wait` has passed since the first one was picked up. A document job
+/// ends the batch early since it runs on its own anyway.
+fn collect_batch(rx: &Receiver<Work>, batch: BatchConfig) -> Option<Vec<Work>> {
+  let first = rx.recv().ok()?;
+  if matches!(first, Work::Document(_)) {
+    return Some(vec![first]);
+  }
+  let mut jobs = vec![first];
+  let deadline = Instant::now() + batch.max_wait;
+
+  while jobs.len() < batch.max_batch {
+    let now = Instant::now();
+    let next = if now >= deadline {
+      rx.try_recv().ok()
+    } else {
+      rx.recv_timeout(deadline - now).ok()
+    };
+    match next {
+      Some(work @ Work::Document(_)) => {
+        jobs.push(work);
+        break;
+      }
+      Some(work) => jobs.push(work),
+      None => break,
+    }
+  }
+
+  Some(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::embedder::ModelSpec;
+
+  /// Fails any batch that contains the text `bad`.
+  struct PickyEmbedder(ModelSpec);
+
+  impl Embedder for PickyEmbedder {
+    fn spec(&self) -> &ModelSpec {
+      &self.0
+    }
+
+    fn embed_batch(&mut self, inputs: &[EmbedInput<'_>]) -> anyhow::Result<Vec<Vec<f32>>> {
+      if inputs.iter().any(|i| i.text == "bad") {
+        anyhow::bail!("tokenizer rejected the input");
+      }
+      Ok(inputs.iter().map(|i| vec![i.text.len() as f32, 1.0]).collect())
+    }
+
+    fn embed_long_document(&mut self, _: &str, _: usize, _: &LongDocOptions) -> anyhow::Result<DocEmbedding> {
+      anyhow::bail!("not used")
+    }
+  }
+
+  #[tokio::test]
+  async fn a_bad_input_only_fails_its_own_request() {
+    let spec = ModelSpec {
+      dims: 2,
+      ..ModelSpec::bge_m3()
+    };
+    let pool = EmbedderPool::new(
+      vec![Box::new(PickyEmbedder(spec))],
+      8,
+      BatchConfig {
+        max_batch: 8,
+        max_wait: Duration::from_millis(200),
+      },
+    )
+    .unwrap();
+    let (a, bad, b) = tokio::join!(
+      pool.embed("a".to_string(), 16, InputKind::Query),
+      pool.embed("bad".to_string(), 16, InputKind::Query),
+      pool.embed("bb".to_string(), 16, InputKind::Query),
+    );
+    assert_eq!(a.unwrap(), [1.0, 1.0]);
+    assert!(bad.unwrap_err().to_string().contains("rejected"));
+    assert_eq!(b.unwrap(), [2.0, 1.0]);
+    assert_eq!(pool.stats().batches, 1);
+  }
+
+  #[test]
+  fn zero_queue_capacity_is_rejected() {
+    let spec = ModelSpec {
+      dims: 2,
+      ..ModelSpec::bge_m3()
+    };
+    let batch = BatchConfig {
+      max_batch: 1,
+      max_wait: Duration::ZERO,
+    };
+    assert!(EmbedderPool::new(vec![Box::new(PickyEmbedder(spec))], 0, batch).is_err());
+  }
+}
@@ -0,0 +1,239 @@
+use std::{
+  collections::BTreeMap,
+  fs::File,
+  io::{BufRead, BufReader},
+  path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// One line of the queries file: an id plus any `/api/search` request fields.
+pub struct EvalQuery {
+  pub id: String,
+  pub query: String,
+  /// The whole line, deserialized into a search request by the caller.
+  pub request: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QrelLine {
+  query_id: String,
+  doc_id: String,
+  #[serde(default = "default_relevance")]
+  relevance: f64,
+}
+
+fn default_relevance() -> f64 {
+  1.0
+}
+
+/// Graded judgements: query id -> doc id -> relevance (> 0 = relevant).
+pub type Qrels = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Reads `{"queryId" | "id", "query", ...}` lines; the id defaults to the line number.
+pub fn load_queries(path: &Path) -> anyhow::Result<Vec<EvalQuery>> {
+  let mut out = Vec::new();
+  for (n, line) in read_lines(path)? {
+    let request: Map<String, Value> = serde_json::from_str(&line)
+      .map_err(|e| anyhow::anyhow!("{}:{n}: {e}", path.display()))?;
+    let id = match request.get("queryId").or_else(|| request.get("id")) {
+      Some(Value::String(s)) => s.clone(),
+      Some(Value::Number(num)) => num.to_string(),
+      _ => n.to_string(),
+    };
+    let query = request.get("query").and_then(Value::as_str).unwrap_or_default().to_string();
+    out.push(EvalQuery { id, query, request });
+  }
+  Ok(out)
+}
+
+/// Reads `{"queryId", "docId", "relevance"?}` lines (relevance defaults to 1).
+pub fn load_qrels(path: &Path) -> anyhow::Result<Qrels> {
+  let mut out = Qrels::new();
+  for (n, line) in read_lines(path)? {
+    let qrel: QrelLine = serde_json::from_str(&line)
+      .map_err(|e| anyhow::anyhow!("{}:{n}: {e}", path.display()))?;
+    out.entry(qrel.query_id).or_default().insert(qrel.doc_id, qrel.relevance);
+  }
+  Ok(out)
+}
+
+fn read_lines(path: &Path) -> anyhow::Result<Vec<(usize, String)>> {
+  let f = File::open(path).map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+  let mut out = Vec::new();
+  for (i, line) in BufReader::new(f).lines().enumerate() {
+    let line = line?;
+    if !line.trim().is_empty() {
+      out.push((i + 1, line));
+    }
+  }
+  Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Scores {
+  pub recall: f64,
+  pub mrr: f64,
+  pub ndcg: f64,
+}
+
+/// Scores a ranking (doc ids, best first) against one query's judgements.
+pub fn score(ranked: &[String], judged: &BTreeMap<String, f64>, k: usize) -> Scores {
+  let ranked = &ranked[..ranked.len().min(k)];
+  let relevance = |doc: &String| judged.get(doc).copied().unwrap_or(0.0).max(0.0);
+  let relevant = judged.values().filter(|r| **r > 0.0).count();
+
+  let found = ranked.iter().filter(|d| relevance(d) > 0.0).count();
+  let recall = if relevant == 0 { 0.0 } else { found as f64 / relevant as f64 };
+  let mrr = ranked
+    .iter()
+    .position(|d| relevance(d) > 0.0)
+    .map_or(0.0, |i| 1.0 / (i + 1) as f64);
+
+  let dcg = |gains: &mut dyn Iterator<Item = f64>| -> f64 {
+    gains
+      .enumerate()
+      .map(|(i, rel)| (2f64.powf(rel) - 1.0) / ((i + 2) as f64).log2())
+      .sum()
+  };
+  let mut ideal: Vec<f64> = judged.values().copied().filter(|r| *r > 0.0).collect();
+  ideal.sort_by(|a, b| b.total_cmp(a));
+  let idcg = dcg(&mut ideal.into_iter().take(k));
+  let ndcg = if idcg > 0.0 {
+    dcg(&mut ranked.iter().map(relevance)) / idcg
+  } else {
+    0.0
+  };
+
+  Scores { recall, mrr, ndcg }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryReport {
+  pub query_id: String,
+  pub query: String,
+  #[serde(flatten)]
+  pub scores: Scores,
+  /// Relevant docs missing from the top k.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub missing: Vec<String>,
+  pub top: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvalReport {
+  pub collection: String,
+  pub k: usize,
+  /// Queries with judgements that were run.
+  pub queries: usize,
+  /// Queries without any judgements (not scored).
+  pub unjudged: usize,
+  /// Means over `queries`; failed searches count as 0.
+  pub mean: Scores,
+  /// Queries that errored or found no relevant doc in the top k.
+  pub failures: Vec<String>,
+  pub per_query: Vec<QueryReport>,
+}
+
+impl EvalReport {
+  pub fn new(collection: String, k: usize, unjudged: usize, per_query: Vec<QueryReport>) -> Self {
+    let n = per_query.len().max(1) as f64;
+    let sum = |f: fn(&Scores) -> f64| per_query.iter().map(|q| f(&q.scores)).sum::<f64>() / n;
+    let mean = Scores {
+      recall: sum(|s| s.recall),
+      mrr: sum(|s| s.mrr),
+      ndcg: sum(|s| s.ndcg),
+    };
+    let failures = per_query
+      .iter()
+      .filter(|q| q.error.is_some() || q.scores.mrr == 0.0)
+      .map(|q| q.query_id.clone())
+      .collect();
+    Self {
+      collection,
+      k,
+      queries: per_query.len(),
+      unjudged,
+      mean,
+      failures,
+      per_query,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ids(docs: &[&str]) -> Vec<String> {
+    docs.iter().map(|d| d.to_string()).collect()
+  }
+
+  fn judged(docs: &[(&str, f64)]) -> BTreeMap<String, f64> {
+    docs.iter().map(|(d, r)| (d.to_string(), *r)).collect()
+  }
+
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+  }
+
+  #[test]
+  fn binary_judgements() {
+    let s = score(&ids(&["x", "a", "y", "b"]), &judged(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]), 4);
+    assert!(close(s.recall, 2.0 / 3.0));
+    assert!(close(s.mrr, 0.5));
+    let dcg = 1.0 / 3f64.log2() + 1.0 / 5f64.log2();
+    let idcg = 1.0 + 1.0 / 3f64.log2() + 1.0 / 4f64.log2();
+    assert!(close(s.ndcg, dcg / idcg), "{}", s.ndcg);
+  }
+
+  #[test]
+  fn graded_judgements_reward_the_better_order() {
+    let judgements = judged(&[("a", 2.0), ("b", 1.0), ("z", 0.0)]);
+    let best = score(&ids(&["a", "b"]), &judgements, 2);
+    let swapped = score(&ids(&["b", "a"]), &judgements, 2);
+    assert!(close(best.ndcg, 1.0));
+    assert!(close(swapped.ndcg, (1.0 + 3.0 / 3f64.log2()) / (3.0 + 1.0 / 3f64.log2())));
+    assert!(close(swapped.recall, 1.0) && close(swapped.mrr, 1.0));
+  }
+
+  #[test]
+  fn only_the_top_k_counts() {
+    let judgements = judged(&[("a", 1.0)]);
+    let s = score(&ids(&["x", "a"]), &judgements, 1);
+    assert_eq!((s.recall, s.mrr, s.ndcg), (0.0, 0.0, 0.0));
+    let s = score(&ids(&["z"]), &judged(&[("z", 0.0)]), 10);
+    assert_eq!((s.recall, s.mrr, s.ndcg), (0.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn report_means_and_failures() {
+    let query = |id: &str, mrr: f64, error: Option<&str>| QueryReport {
+      query_id: id.to_string(),
+      query: String::new(),
+      scores: Scores {
+        recall: mrr,
+        mrr,
+        ndcg: mrr,
+      },
+      missing: Vec::new(),
+      top: Vec::new(),
+      error: error.map(str::to_string),
+    };
+    let report = EvalReport::new(
+      "default".to_string(),
+      10,
+      1,
+      vec![query("q1", 1.0, None), query("q2", 0.5, None), query("q3", 0.0, Some("timeout"))],
+    );
+    assert_eq!(report.queries, 3);
+    assert!(close(report.mean.mrr, 0.5));
+    assert_eq!(report.failures, ["q3"]);
+  }
+}
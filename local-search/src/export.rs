@@ -0,0 +1,343 @@
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+  collection::{Index, RowDoc},
+  manifest::DEFAULT_VECTOR_FIELD,
+  npy::NpyWriter,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+  /// `schema.sql` (pgvector DDL) plus a `COPY` text file with docs and vectors.
+  Pgvector,
+  /// `vectors.npy` (`[docs, dims]` float32) plus `doc_ids.txt`.
+  Npy,
+  /// `base.fvecs` plus `doc_ids.txt`; optionally `query.fvecs` + `groundtruth.ivecs`.
+  Fvecs,
+}
+
+pub struct ExportOptions {
+  pub format: ExportFormat,
+  /// Postgres table name (pgvector).
+  pub table: String,
+  /// Vector field written by `npy`/`fvecs` (pgvector writes every field).
+  pub field: String,
+  /// Docs sampled as queries for `groundtruth.ivecs` (fvecs, 0 = none).
+  pub ground_truth_queries: usize,
+  /// Exact neighbors per sampled query.
+  pub ground_truth_k: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+  pub format: ExportFormat,
+  pub docs: usize,
+  pub dims: usize,
+  pub files: Vec<String>,
+}
+
+/// Writes every searchable doc of `index` into `out_dir`, in row order.
+pub fn export_index(index: &Index, out_dir: &Path, opts: &ExportOptions) -> anyhow::Result<ExportSummary> {
+  std::fs::create_dir_all(out_dir)?;
+  let fields: Vec<String> = std::iter::once(DEFAULT_VECTOR_FIELD.to_string())
+    .chain(index.fields.iter().cloned())
+    .collect();
+  if !fields.contains(&opts.field) {
+    anyhow::bail!("unknown vector field `{}` (available: {})", opts.field, fields.join(", "));
+  }
+  let docs = index.docs_by_row()?;
+  let vector = |field: &str, doc: &RowDoc| {
+    index
+      .field_vector(field, doc.row)
+      .ok_or_else(|| anyhow::anyhow!("no {field} vector for row {} ({})", doc.row, doc.doc_id))
+  };
+
+  // Every file written is listed in the summary.
+  let mut files = Vec::new();
+  let mut register = |name: &str| -> PathBuf {
+    let path = out_dir.join(name);
+    files.push(path.display().to_string());
+    path
+  };
+
+  match opts.format {
+    ExportFormat::Pgvector => {
+      if !is_sql_identifier(&opts.table) {
+        anyhow::bail!("--table `{}` must be a plain (optionally schema-qualified) identifier", opts.table);
+      }
+      let copy_name = format!("{}.copy", opts.table);
+      let mut schema = create(&register("schema.sql"))?;
+      schema.write_all(pgvector_schema(index, &opts.table, &fields, &copy_name, docs.len()).as_bytes())?;
+      schema.flush()?;
+
+      let mut copy = create(&register(&copy_name))?;
+      for doc in &docs {
+        let mut line = format!(
+          "{}\t{}\t{}\t{}",
+          copy_text(&doc.doc_id),
+          doc.manga_id.map_or("\\N".to_string(), |id| id.to_string()),
+          doc.title.as_deref().map_or("\\N".to_string(), copy_text),
+          doc.text.as_deref().map_or("\\N".to_string(), copy_text),
+        );
+        for field in &fields {
+          line.push('\t');
+          line.push_str(&vector_literal(&vector(field, doc)?));
+        }
+        line.push('\n');
+        copy.write_all(line.as_bytes())?;
+      }
+      copy.flush()?;
+    }
+    ExportFormat::Npy => {
+      let mut npy = NpyWriter::create(&register("vectors.npy"), docs.len(), index.dims)?;
+      for doc in &docs {
+        npy.push(&vector(&opts.field, doc)?)?;
+      }
+      npy.finish()?;
+      write_doc_ids(&mut create(&register("doc_ids.txt"))?, &docs)?;
+    }
+    ExportFormat::Fvecs => {
+      let mut base = create(&register("base.fvecs"))?;
+      for doc in &docs {
+        write_fvecs_row(&mut base, &vector(&opts.field, doc)?)?;
+      }
+      base.flush()?;
+      write_doc_ids(&mut create(&register("doc_ids.txt"))?, &docs)?;
+
+      let queries = opts.ground_truth_queries.min(docs.len());
+      if queries > 0 {
+        // Evenly spaced docs keep the sample reproducible without a seed.
+        let positions: HashMap<usize, usize> = docs.iter().enumerate().map(|(i, d)| (d.row, i)).collect();
+        let mut query_file = create(&register("query.fvecs"))?;
+        let mut truth = create(&register("groundtruth.ivecs"))?;
+        let k = opts.ground_truth_k.clamp(1, docs.len());
+        for q in 0..queries {
+          let doc = &docs[q * docs.len() / queries];
+          let v = vector(&opts.field, doc)?;
+          write_fvecs_row(&mut query_file, &v)?;
+          let neighbors: Vec<i32> = index
+            .search(&[(opts.field.clone(), 1.0)], &v, k)?
+            .into_iter()
+            .filter_map(|(row, _)| positions.get(&row).map(|&i| i as i32))
+            .collect();
+          write_ivecs_row(&mut truth, &neighbors)?;
+        }
+        query_file.flush()?;
+        truth.flush()?;
+      }
+    }
+  }
+
+  Ok(ExportSummary {
+    format: opts.format,
+    docs: docs.len(),
+    dims: index.dims,
+    files,
+  })
+}
+
+fn create(path: &Path) -> anyhow::Result<BufWriter<File>> {
+  Ok(BufWriter::new(File::create(path)?))
+}
+
+fn pgvector_schema(index: &Index, table: &str, fields: &[String], copy_name: &str, docs: usize) -> String {
+  let dims = index.dims;
+  let model = index.manifest.as_ref().map_or("unknown", |m| m.model.id.as_str());
+  let columns: Vec<String> = fields.iter().map(|f| vector_column(f)).collect();
+  let mut lines = vec![
+    format!("-- Exported by litomi-local-search: {docs} docs, model {model}, {dims} dims."),
+    "-- Load with:".to_string(),
+    "--   psql \"$POSTGRES_URL\" -f schema.sql".to_string(),
+    format!(
+      "--   psql \"$POSTGRES_URL\" -c \"\\copy {table} (doc_id, manga_id, title, text, {}) FROM '{copy_name}'\"",
+      columns.join(", ")
+    ),
+    String::new(),
+    "CREATE EXTENSION IF NOT EXISTS vector;".to_string(),
+    String::new(),
+    format!("CREATE TABLE IF NOT EXISTS {table} ("),
+    "  doc_id text PRIMARY KEY,".to_string(),
+    "  manga_id bigint,".to_string(),
+    "  title text,".to_string(),
+    "  text text,".to_string(),
+  ];
+  let vector_columns: Vec<String> = columns.iter().map(|c| format!("  {c} vector({dims}) NOT NULL")).collect();
+  lines.push(vector_columns.join(",\n"));
+  lines.push(");".to_string());
+  lines.push(String::new());
+  lines.push("-- Vectors are L2-normalized, so inner product = cosine similarity:".to_string());
+  lines.push(format!(
+    "--   SELECT doc_id, title, -(embedding <#> $1) AS score FROM {table} ORDER BY embedding <#> $1 LIMIT 10;"
+  ));
+  lines.push("-- Create ANN indexes after loading the data:".to_string());
+  for column in &columns {
+    lines.push(format!("--   CREATE INDEX ON {table} USING hnsw ({column} vector_ip_ops);"));
+  }
+  lines.join("\n") + "\n"
+}
+
+/// `embedding` for the default field, `embedding_<name>` for the others.
+fn vector_column(field: &str) -> String {
+  if field == DEFAULT_VECTOR_FIELD {
+    "embedding".to_string()
+  } else {
+    format!("\"embedding_{}\"", field.replace('"', "\"\""))
+  }
+}
+
+fn is_sql_identifier(s: &str) -> bool {
+  s.split('.').count() <= 2
+    && s.split('.').all(|part| {
+      part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Escapes a value for the `COPY` text format.
+fn copy_text(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '\\' => out.push_str("\\\\"),
+      '\t' => out.push_str("\\t"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// pgvector input syntax, e.g. `[0.1,-0.2]`.
+fn vector_literal(v: &[f32]) -> String {
+  let items: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+  format!("[{}]", items.join(","))
+}
+
+fn write_doc_ids(out: &mut BufWriter<File>, docs: &[RowDoc]) -> anyhow::Result<()> {
+  for doc in docs {
+    writeln!(out, "{}", doc.doc_id)?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// `fvecs` row: `i32` dims, then the values (little-endian).
+fn write_fvecs_row(out: &mut impl Write, v: &[f32]) -> anyhow::Result<()> {
+  out.write_all(&(v.len() as i32).to_le_bytes())?;
+  for x in v {
+    out.write_all(&x.to_le_bytes())?;
+  }
+  Ok(())
+}
+
+/// `ivecs` row: `i32` length, then the values (little-endian).
+fn write_ivecs_row(out: &mut impl Write, v: &[i32]) -> anyhow::Result<()> {
+  out.write_all(&(v.len() as i32).to_le_bytes())?;
+  for x in v {
+    out.write_all(&x.to_le_bytes())?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::{
+    external_vectors::EmbeddingFile,
+    index_builder::{
+      build_index,
+      tests::{config, model, write_corpus},
+    },
+  };
+
+  fn opts(format: ExportFormat, ground_truth_queries: usize) -> ExportOptions {
+    ExportOptions {
+      format,
+      table: "public.manga_docs".to_string(),
+      field: DEFAULT_VECTOR_FIELD.to_string(),
+      ground_truth_queries,
+      ground_truth_k: 2,
+    }
+  }
+
+  fn read_all(path: &Path) -> Vec<Vec<f32>> {
+    let mut file = EmbeddingFile::open(path).unwrap();
+    std::iter::from_fn(|| file.next_embedding().unwrap().map(|e| e.vector)).collect()
+  }
+
+  #[test]
+  fn copy_text_escapes_the_copy_specials() {
+    assert_eq!(copy_text("a\tb\nc\rd\\e"), "a\\tb\\nc\\rd\\\\e");
+    assert_eq!(copy_text("원피스 \"1\""), "원피스 \"1\"");
+    assert_eq!(vector_literal(&[0.5, -0.25]), "[0.5,-0.25]");
+  }
+
+  #[test]
+  fn table_names_and_vector_columns() {
+    assert!(is_sql_identifier("manga_docs") && is_sql_identifier("public._docs2"));
+    for bad in ["", "1docs", "a.b.c", "docs; DROP TABLE x", "\"docs\""] {
+      assert!(!is_sql_identifier(bad), "{bad}");
+    }
+    assert_eq!(vector_column(DEFAULT_VECTOR_FIELD), "embedding");
+    assert_eq!(vector_column("ti\"tle"), "\"embedding_ti\"\"tle\"");
+  }
+
+  #[test]
+  fn exports_every_format_in_row_order() {
+    let dir = std::env::temp_dir().join(format!("local-search-export-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (corpus, index_dir) = (dir.join("corpus.jsonl"), dir.join("index"));
+    write_corpus(
+      &corpus,
+      &[("a", "tab\there", [1.0, 0.0]), ("b", "line\nbreak\\", [0.0, 1.0]), ("c", "c", [0.6, 0.8])],
+    );
+    build_index(config(&corpus, &index_dir)).unwrap();
+    let index = Index::open(&index_dir, &model()).unwrap();
+
+    let out = dir.join("pgvector");
+    let summary = export_index(&index, &out, &opts(ExportFormat::Pgvector, 0)).unwrap();
+    assert_eq!((summary.docs, summary.dims, summary.files.len()), (3, 2, 2));
+    let copy = fs::read_to_string(out.join("public.manga_docs.copy")).unwrap();
+    let lines: Vec<&str> = copy.lines().collect();
+    assert_eq!(lines, ["a\t0\ttab\\there\t\t[1,0]", "b\t1\tline\\nbreak\\\\\t\t[0,1]", "c\t2\tc\t\t[0.6,0.8]"]);
+    let schema = fs::read_to_string(out.join("schema.sql")).unwrap();
+    assert!(schema.contains("CREATE TABLE IF NOT EXISTS public.manga_docs ("), "{schema}");
+    assert!(schema.contains("embedding vector(2) NOT NULL"), "{schema}");
+    let bad_table = ExportOptions {
+      table: "x;y".to_string(),
+      ..opts(ExportFormat::Pgvector, 0)
+    };
+    assert!(export_index(&index, &out, &bad_table).is_err());
+
+    let vectors = [vec![1.0, 0.0], vec![0.0, 1.0], vec![0.6, 0.8]];
+    let out = dir.join("npy");
+    export_index(&index, &out, &opts(ExportFormat::Npy, 0)).unwrap();
+    assert_eq!(read_all(&out.join("vectors.npy")), vectors);
+    assert_eq!(fs::read_to_string(out.join("doc_ids.txt")).unwrap(), "a\nb\nc\n");
+
+    let out = dir.join("fvecs");
+    let summary = export_index(&index, &out, &opts(ExportFormat::Fvecs, 2)).unwrap();
+    assert_eq!(summary.files.len(), 4);
+    assert_eq!(read_all(&out.join("base.fvecs")), vectors);
+    // Queries are docs 0 and 1; each is its own nearest neighbor, then `c`.
+    assert_eq!(read_all(&out.join("query.fvecs")), vectors[..2]);
+    let truth = fs::read(out.join("groundtruth.ivecs")).unwrap();
+    let ints: Vec<i32> = truth.chunks_exact(4).map(|b| i32::from_le_bytes(b.try_into().unwrap())).collect();
+    assert_eq!(ints, [2, 0, 2, 2, 1, 2]);
+    drop(index);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
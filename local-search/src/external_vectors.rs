@@ -0,0 +1,112 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader, Lines, Read},
+  path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::npy::NpyReader;
+
+/// Embeddings computed elsewhere, read row by row in corpus order.
+pub enum EmbeddingFile {
+  /// `[docs, dims]` float array.
+  Npy(NpyReader),
+  /// `fvecs` (as in the texmex/ANN benchmarks): per row an `i32` dims then `dims` `f32`s.
+  Fvecs { input: BufReader<File>, path: PathBuf },
+  /// Lines with an `embedding` array and optionally the `docId` it belongs to;
+  /// may be the corpus file itself.
+  Jsonl {
+    lines: Lines<BufReader<File>>,
+    path: PathBuf,
+    line: usize,
+  },
+}
+
+/// One precomputed vector.
+pub struct Embedding {
+  /// Set when the source names the doc (JSONL `docId`), to catch misaligned files.
+  pub doc_id: Option<String>,
+  pub vector: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmbeddingLine {
+  #[serde(default)]
+  doc_id: Option<String>,
+  embedding: Vec<f32>,
+}
+
+impl EmbeddingFile {
+  /// Picks the format from the extension (`.npy`, `.fvecs`, otherwise JSONL).
+  pub fn open(path: &Path) -> anyhow::Result<Self> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let file = || File::open(path).map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()));
+    Ok(match ext {
+      "npy" => Self::Npy(NpyReader::open(path)?),
+      "fvecs" => Self::Fvecs {
+        input: BufReader::new(file()?),
+        path: path.to_path_buf(),
+      },
+      _ => Self::Jsonl {
+        lines: BufReader::new(file()?).lines(),
+        path: path.to_path_buf(),
+        line: 0,
+      },
+    })
+  }
+
+  /// Row count, when the format records it up front.
+  pub fn len(&self) -> Option<usize> {
+    match self {
+      Self::Npy(npy) => Some(npy.rows),
+      _ => None,
+    }
+  }
+
+  pub fn next_embedding(&mut self) -> anyhow::Result<Option<Embedding>> {
+    let vector = match self {
+      Self::Npy(npy) => npy.next_row()?,
+      Self::Fvecs { input, path } => {
+        let mut dims = [0u8; 4];
+        match input.read_exact(&mut dims) {
+          Ok(()) => {}
+          Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+          Err(e) => return Err(e.into()),
+        }
+        let dims = i32::from_le_bytes(dims);
+        if dims <= 0 {
+          anyhow::bail!("{}: invalid row dims {dims}", path.display());
+        }
+        let mut bytes = vec![0u8; dims as usize * 4];
+        input
+          .read_exact(&mut bytes)
+          .map_err(|e| anyhow::anyhow!("{}: truncated row: {e}", path.display()))?;
+        Some(
+          bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().expect("4-byte chunk")))
+            .collect(),
+        )
+      }
+      Self::Jsonl { lines, path, line } => loop {
+        let Some(text) = lines.next() else {
+          return Ok(None);
+        };
+        *line += 1;
+        let text = text?;
+        if text.trim().is_empty() {
+          continue;
+        }
+        let parsed: EmbeddingLine = serde_json::from_str(&text)
+          .map_err(|e| anyhow::anyhow!("{}:{line}: {e}", path.display()))?;
+        return Ok(Some(Embedding {
+          doc_id: parsed.doc_id,
+          vector: parsed.embedding,
+        }));
+      },
+    };
+    Ok(vector.map(|vector| Embedding { doc_id: None, vector }))
+  }
+}
@@ -1,73 +1,134 @@
 use std::{
   fs::{self, File},
-  io::{BufRead, BufReader, BufWriter, Read, Write},
+  io::{BufRead, BufReader, Read},
   path::{Path, PathBuf},
 };
 
-use bytemuck::cast_slice;
 use rusqlite::{params, Connection};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
 
-use crate::embedder::BgeM3Embedder;
+use crate::{
+  collection::WriteLock,
+  doc_template::DocTemplate,
+  embedder::{init_ort, Embedder, ModelSpec, OnnxEmbedder},
+  external_vectors::EmbeddingFile,
+  long_doc::LongDocOptions,
+  manifest::{vector_field_file, BuildReport, IndexManifest, Shard, VectorField, MANIFEST_FILE},
+  pg_sync::SYNC_STATE_FILE,
+  segment::SEGMENTS_FILE,
+  session_options::SessionOptions,
+  vector_file::{VectorWriter, NORM_TOLERANCE},
+  wal::WAL_FILE,
+};
+
+/// `doc_meta.sqlite` is written under this name and renamed into place as the
+/// last step, so a served generation still holding the old (unlinked) file
+/// never runs into the new file's rollback journal.
+const SQLITE_BUILD_FILE: &str = "doc_meta.sqlite.build";
 
 #[derive(Debug)]
 pub struct BuildIndexConfig {
   pub input: PathBuf,
   pub out_dir: PathBuf,
-  pub model_path: PathBuf,
-  pub tokenizer_path: PathBuf,
+  pub model_dir: PathBuf,
+  pub model_spec: ModelSpec,
   pub ort_dylib_path: Option<PathBuf>,
+  pub session_options: SessionOptions,
   pub doc_max_length: usize,
+  pub long_doc: LongDocOptions,
+  pub doc_template: DocTemplate,
+  /// Extra named vectors, each embedded from its own template.
+  pub vector_fields: Vec<(String, DocTemplate)>,
+  /// Document vectors computed elsewhere (`.npy`, `.fvecs` or JSONL with
+  /// `embedding`), row-aligned with the corpus; the model isn't run.
+  pub embeddings: Option<PathBuf>,
+  /// Rescale non-unit precomputed vectors instead of rejecting them.
+  pub normalize_embeddings: bool,
+  /// `(i, n)`: build only the `i`-th (1-based) of `n` contiguous corpus slices.
+  pub shard: Option<(usize, usize)>,
+}
+
+/// Where document vectors come from.
+enum DocVectors {
+  Model(Box<OnnxEmbedder>),
+  Precomputed(EmbeddingFile),
 }
 
 #[derive(Debug, serde::Deserialize)]
-struct CorpusLine {
+pub struct CorpusLine {
   #[serde(rename = "docId")]
-  doc_id: Option<String>,
+  pub doc_id: Option<String>,
   #[serde(rename = "mangaId")]
-  manga_id: Option<i64>,
-  title: Option<String>,
-  text: Option<String>,
+  pub manga_id: Option<i64>,
+  pub title: Option<String>,
+  pub text: Option<String>,
+  /// Every field of the line, for `--doc-template`.
+  #[serde(skip)]
+  pub fields: Map<String, Value>,
 }
 
-pub fn build_index(cfg: BuildIndexConfig) -> anyhow::Result<()> {
-  fs::create_dir_all(&cfg.out_dir)?;
-
-  let sqlite_path = cfg.out_dir.join("doc_meta.sqlite");
-  let vectors_path = cfg.out_dir.join("vectors.f32");
-
-  if sqlite_path.exists() {
-    fs::remove_file(&sqlite_path)?;
-  }
-  if vectors_path.exists() {
-    fs::remove_file(&vectors_path)?;
+impl CorpusLine {
+  pub fn from_fields(fields: Map<String, Value>) -> anyhow::Result<Self> {
+    let mut item: CorpusLine = serde_json::from_value(Value::Object(fields.clone()))?;
+    item.fields = fields;
+    Ok(item)
   }
+}
 
-  let mut embedder = BgeM3Embedder::new(
-    &cfg.model_path,
-    &cfg.tokenizer_path,
-    cfg.ort_dylib_path.as_deref(),
-  )?;
+pub fn build_index(cfg: BuildIndexConfig) -> anyhow::Result<()> {
+  if cfg.embeddings.is_some() && !cfg.vector_fields.is_empty() {
+    anyhow::bail!("--vector-field needs the model; it can't be combined with --embeddings");
+  }
+  let corpus = read_corpus(&cfg.input)?;
+  let corpus_docs = corpus.len();
+  let rows = match cfg.shard {
+    Some((index, count)) => shard_rows(corpus_docs, index, count),
+    None => 0..corpus_docs,
+  };
+  let mut vectors = match &cfg.embeddings {
+    Some(path) => {
+      let file = EmbeddingFile::open(path)?;
+      if let Some(rows) = file.len().filter(|rows| *rows != corpus.len()) {
+        anyhow::bail!("{} has {rows} rows, the corpus has {} docs", path.display(), corpus.len());
+      }
+      DocVectors::Precomputed(file)
+    }
+    None => {
+      init_ort(cfg.ort_dylib_path.as_deref())?;
+      DocVectors::Model(Box::new(OnnxEmbedder::new(&cfg.model_dir, cfg.model_spec.clone(), &cfg.session_options)?))
+    }
+  };
 
+  let (_lock, sqlite_path, vectors_path) = reset_index_dir(&cfg.out_dir)?;
   let conn = Connection::open(&sqlite_path)?;
-  conn.execute_batch(
-    r#"
-CREATE TABLE doc (
-  doc_id TEXT PRIMARY KEY,
-  manga_id INTEGER,
-  title TEXT,
-  text TEXT
-);
-CREATE TABLE vec_map (
-  row INTEGER PRIMARY KEY,
-  doc_id TEXT NOT NULL UNIQUE
-);
-"#,
-  )?;
+  create_tables(&conn)?;
+
+  let dims = cfg.model_spec.dims;
+  let mut vec_writer = VectorWriter::create(&vectors_path, dims)?;
+  let mut field_writers = cfg
+    .vector_fields
+    .iter()
+    .map(|(name, _)| VectorWriter::create(&cfg.out_dir.join(vector_field_file(name)), dims))
+    .collect::<anyhow::Result<Vec<_>>>()?;
 
-  let mut vec_writer = BufWriter::new(File::create(&vectors_path)?);
+  let mut docs = 0usize;
+  let mut report = BuildReport::default();
+  let template_fields: Vec<&str> = cfg.doc_template.fields().collect();
+  let mut field_hits = vec![0usize; template_fields.len()];
+  let mut renormalized = 0usize;
+  if let DocVectors::Precomputed(file) = &mut vectors {
+    skip_embeddings(file, rows.start)?;
+  }
+  let shard_len = rows.len();
+  for (row, item) in (0_i64..).zip(corpus).skip(rows.start).take(shard_len) {
+    for (hits, name) in field_hits.iter_mut().zip(&template_fields) {
+      if item.fields.get(*name).is_some_and(|v| !v.is_null()) {
+        *hits += 1;
+      }
+    }
+    let embed_text = cfg.doc_template.render(|name| item.fields.get(name));
 
-  let mut row: i64 = 0;
-  for item in read_corpus(&cfg.input)? {
     let doc_id = item
       .doc_id
       .unwrap_or_else(|| format!("manga:{row}"));
@@ -75,24 +136,231 @@ CREATE TABLE vec_map (
     let title = item.title.unwrap_or_else(|| "(no title)".to_string());
     let text = item.text.unwrap_or_else(|| "".to_string());
 
-    let emb = embedder.embed_dense_cls_normalized(&text, cfg.doc_max_length)?;
+    let vector = match &mut vectors {
+      DocVectors::Model(embedder) => {
+        let doc = embedder.embed_long_document(&embed_text, cfg.doc_max_length, &cfg.long_doc)?;
+        if doc.tokens > cfg.doc_max_length {
+          report.long_docs += 1;
+        }
+        if doc.truncated {
+          report.truncated_docs += 1;
+        }
+        report.windows += doc.windows;
+        report.max_tokens = report.max_tokens.max(doc.tokens);
+        doc.vector
+      }
+      DocVectors::Precomputed(file) => {
+        let (vector, rescaled) = precomputed_vector(file, &doc_id, row, dims, cfg.normalize_embeddings)?;
+        renormalized += rescaled as usize;
+        vector
+      }
+    };
 
     conn.execute(
       "INSERT INTO doc (doc_id, manga_id, title, text) VALUES (?1, ?2, ?3, ?4)",
       params![doc_id, manga_id, title, text],
     )?;
+    // Rows are numbered within the shard; ids and `mangaId` fallbacks use the corpus row.
     conn.execute(
       "INSERT INTO vec_map (row, doc_id) VALUES (?1, ?2)",
-      params![row, doc_id],
+      params![docs as i64, doc_id],
     )?;
 
-    let bytes: &[u8] = cast_slice(&emb);
-    vec_writer.write_all(bytes)?;
-    row += 1;
+    vec_writer.push(&vector)?;
+
+    if let DocVectors::Model(embedder) = &mut vectors {
+      for ((_, template), writer) in cfg.vector_fields.iter().zip(field_writers.iter_mut()) {
+        let field_text = template.render(|name| item.fields.get(name));
+        let field_doc = embedder.embed_long_document(&field_text, cfg.doc_max_length, &cfg.long_doc)?;
+        writer.push(&field_doc.vector)?;
+      }
+    }
+    docs += 1;
+  }
+
+  if let DocVectors::Precomputed(file) = &mut vectors {
+    skip_embeddings(file, corpus_docs - rows.end)?;
+    if file.next_embedding()?.is_some() {
+      anyhow::bail!("the embeddings file has more rows than the corpus ({corpus_docs} docs)");
+    }
+    if renormalized > 0 {
+      warn!(renormalized, "rescaled precomputed vectors to unit length");
+    }
+    report.precomputed = cfg.embeddings.as_ref().map(|p| p.display().to_string());
+  }
+
+  let header = vec_writer.finish()?;
+  if !header.normalized && docs > 0 {
+    warn!("some document vectors are not unit length; scores won't be cosine similarities");
+  }
+  for writer in field_writers {
+    writer.finish()?;
+  }
+
+  for (hits, name) in field_hits.iter().zip(&template_fields) {
+    if *hits == 0 && docs > 0 {
+      warn!(field = %name, "doc template field is missing in every doc (typo?)");
+    }
+  }
+
+  info!(
+    docs,
+    rows = ?rows,
+    strategy = ?cfg.long_doc.strategy,
+    long_docs = report.long_docs,
+    truncated_docs = report.truncated_docs,
+    windows = report.windows,
+    max_tokens = report.max_tokens,
+    "build report"
+  );
+
+  IndexManifest {
+    model: cfg.model_spec,
+    docs,
+    doc_max_length: cfg.doc_max_length,
+    long_doc: cfg.long_doc,
+    doc_template: cfg.doc_template.source().to_string(),
+    fields: cfg
+      .vector_fields
+      .iter()
+      .map(|(name, template)| VectorField {
+        name: name.clone(),
+        template: template.source().to_string(),
+      })
+      .collect(),
+    report,
+    shard: cfg.shard.map(|(index, count)| Shard {
+      index,
+      count,
+      corpus_docs,
+    }),
+  }
+  .save(&cfg.out_dir)?;
+  publish_sqlite(conn, &cfg.out_dir)?;
+
+  Ok(())
+}
+
+/// Corpus rows of shard `index` (1-based) of `count`: contiguous, so merging
+/// the shards in order restores the corpus order.
+pub fn shard_rows(corpus_docs: usize, index: usize, count: usize) -> std::ops::Range<usize> {
+  corpus_docs * (index - 1) / count..corpus_docs * index / count
+}
+
+/// Creates `out_dir`, locks it against `serve`/`sync` writers and other
+/// builds, and removes the files of an earlier index. Returns the lock (hold
+/// it until `publish_sqlite`) and where to write `doc_meta.sqlite` and
+/// `vectors.f32`.
+pub fn reset_index_dir(out_dir: &Path) -> anyhow::Result<(WriteLock, PathBuf, PathBuf)> {
+  fs::create_dir_all(out_dir)?;
+  let lock = WriteLock::acquire(out_dir)?;
+
+  let sqlite_path = out_dir.join(SQLITE_BUILD_FILE);
+  let vectors_path = out_dir.join("vectors.f32");
+
+  // Unlinked, not truncated: a running generation keeps its open handles.
+  // The manifest goes first so an interrupted build isn't taken for a
+  // finished one, and the sync watermark belongs to the old rows.
+  for name in [
+    MANIFEST_FILE,
+    SYNC_STATE_FILE,
+    "doc_meta.sqlite",
+    SQLITE_BUILD_FILE,
+    "doc_meta.sqlite.build-journal",
+  ] {
+    let path = out_dir.join(name);
+    if path.exists() {
+      fs::remove_file(&path)?;
+    }
+  }
+  if vectors_path.exists() {
+    fs::remove_file(&vectors_path)?;
+  }
+  // Stale field files, segments and WAL from an earlier build would be
+  // picked up by `serve`.
+  for entry in fs::read_dir(out_dir)? {
+    let path = entry?.path();
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let stale = (name.starts_with("vectors.") && name != "vectors.f32" && name.ends_with(".f32"))
+      || name.starts_with("seg-")
+      || name == SEGMENTS_FILE
+      || name == WAL_FILE;
+    if stale {
+      fs::remove_file(&path)?;
+    }
+  }
+  Ok((lock, sqlite_path, vectors_path))
+}
+
+/// Closes the finished sqlite file and renames it to `doc_meta.sqlite`.
+pub fn publish_sqlite(conn: Connection, out_dir: &Path) -> anyhow::Result<()> {
+  conn.close().map_err(|(_, e)| e)?;
+  fs::rename(out_dir.join(SQLITE_BUILD_FILE), out_dir.join("doc_meta.sqlite"))?;
+  Ok(())
+}
+
+/// Reads past `n` rows of other shards.
+fn skip_embeddings(file: &mut EmbeddingFile, n: usize) -> anyhow::Result<()> {
+  for _ in 0..n {
+    if file.next_embedding()?.is_none() {
+      anyhow::bail!("the embeddings file has fewer rows than the corpus");
+    }
   }
+  Ok(())
+}
 
-  vec_writer.flush()?;
+/// Next precomputed vector, checked against the doc it is written for.
+/// Returns whether it had to be rescaled to unit length.
+fn precomputed_vector(
+  file: &mut EmbeddingFile,
+  doc_id: &str,
+  row: i64,
+  dims: usize,
+  normalize: bool,
+) -> anyhow::Result<(Vec<f32>, bool)> {
+  let Some(embedding) = file.next_embedding()? else {
+    anyhow::bail!("the embeddings file ends at row {row}; the corpus has more docs");
+  };
+  if let Some(id) = embedding.doc_id.as_deref().filter(|id| *id != doc_id) {
+    anyhow::bail!("embeddings row {row} is for {id}, the corpus has {doc_id} there");
+  }
+  let mut vector = embedding.vector;
+  if vector.len() != dims {
+    anyhow::bail!("embeddings row {row} ({doc_id}) has {} dims, the model has {dims}", vector.len());
+  }
+  if vector.iter().any(|x| !x.is_finite()) {
+    anyhow::bail!("embeddings row {row} ({doc_id}) has NaN or infinite values");
+  }
+  let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+  if norm == 0.0 {
+    anyhow::bail!("embeddings row {row} ({doc_id}) is all zeros");
+  }
+  if (norm - 1.0).abs() <= NORM_TOLERANCE {
+    return Ok((vector, false));
+  }
+  if !normalize {
+    anyhow::bail!("embeddings row {row} ({doc_id}) has norm {norm:.4}; pass --normalize-embeddings to rescale");
+  }
+  vector.iter_mut().for_each(|x| *x /= norm);
+  Ok((vector, true))
+}
 
+/// `doc` holds the metadata, `vec_map` maps vector rows to doc ids.
+pub fn create_tables(conn: &Connection) -> anyhow::Result<()> {
+  conn.execute_batch(
+    r#"
+CREATE TABLE doc (
+  doc_id TEXT PRIMARY KEY,
+  manga_id INTEGER,
+  title TEXT,
+  text TEXT
+);
+CREATE TABLE vec_map (
+  row INTEGER PRIMARY KEY,
+  doc_id TEXT NOT NULL UNIQUE
+);
+"#,
+  )?;
   Ok(())
 }
 
@@ -124,8 +392,7 @@ fn read_corpus(input: &Path) -> anyhow::Result<Vec<CorpusLine>> {
       if line.is_empty() {
         continue;
       }
-      let item: CorpusLine = serde_json::from_str(line)?;
-      out.push(item);
+      out.push(CorpusLine::from_fields(serde_json::from_str(line)?)?);
     }
     return Ok(out);
   }
@@ -133,11 +400,136 @@ fn read_corpus(input: &Path) -> anyhow::Result<Vec<CorpusLine>> {
   // Plain text fallback: one doc containing the entire file.
   let mut s = String::new();
   File::open(input)?.read_to_string(&mut s)?;
+  let mut fields = Map::new();
+  fields.insert("title".to_string(), Value::from("summary"));
+  fields.insert("text".to_string(), Value::from(s.as_str()));
   Ok(vec![CorpusLine {
     doc_id: Some("manga:summary".to_string()),
     manga_id: Some(0),
     title: Some("summary".to_string()),
     text: Some(s),
+    fields,
   }])
 }
 
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::{
+    sync::{
+      atomic::{AtomicBool, Ordering},
+      Arc,
+    },
+    thread,
+  };
+
+  use super::*;
+  use crate::{collection::Index, doc_template::DEFAULT_DOC_TEMPLATE, session_options::SessionOptions};
+
+  pub(crate) fn model() -> ModelSpec {
+    ModelSpec {
+      dims: 2,
+      ..ModelSpec::bge_m3()
+    }
+  }
+
+  /// Writes a corpus whose lines carry their own `embedding`.
+  pub(crate) fn write_corpus(path: &Path, docs: &[(&str, &str, [f32; 2])]) {
+    let lines: Vec<String> = docs
+      .iter()
+      .map(|(doc_id, title, v)| {
+        serde_json::json!({ "docId": doc_id, "title": title, "text": "", "embedding": v }).to_string() + "\n"
+      })
+      .collect();
+    fs::write(path, lines.concat()).unwrap();
+  }
+
+  /// Builds `corpus` (see `write_corpus`) into `out_dir` without the model.
+  pub(crate) fn config(corpus: &Path, out_dir: &Path) -> BuildIndexConfig {
+    BuildIndexConfig {
+      input: corpus.to_path_buf(),
+      out_dir: out_dir.to_path_buf(),
+      model_dir: PathBuf::new(),
+      model_spec: model(),
+      ort_dylib_path: None,
+      session_options: SessionOptions::default(),
+      doc_max_length: 64,
+      long_doc: LongDocOptions::default(),
+      doc_template: DocTemplate::parse(DEFAULT_DOC_TEMPLATE).unwrap(),
+      vector_fields: Vec::new(),
+      embeddings: Some(corpus.to_path_buf()),
+      normalize_embeddings: false,
+      shard: None,
+    }
+  }
+
+  fn lookup(index: &Index, doc_id: &str) -> (String, Vec<f32>) {
+    let doc = index.get_doc(doc_id).unwrap().unwrap();
+    (doc.title, index.vector(doc.row).unwrap())
+  }
+
+  #[test]
+  fn rebuilding_leaves_a_loaded_generation_intact() {
+    let dir = std::env::temp_dir().join(format!("local-search-rebuild-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (old_corpus, new_corpus, index_dir) = (dir.join("old.jsonl"), dir.join("new.jsonl"), dir.join("index"));
+    write_corpus(&old_corpus, &[("a", "old a", [1.0, 0.0]), ("b", "old b", [0.0, 1.0])]);
+    write_corpus(
+      &new_corpus,
+      &[("c", "new c", [0.6, 0.8]), ("a", "new a", [0.0, 1.0]), ("b", "new b", [1.0, 0.0])],
+    );
+    build_index(config(&old_corpus, &index_dir)).unwrap();
+
+    let old = Arc::new(Index::open(&index_dir, &model()).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+    let reader = {
+      let (old, done) = (old.clone(), done.clone());
+      thread::spawn(move || {
+        // Every connection gets borrowed at once at least once.
+        while !done.load(Ordering::Relaxed) {
+          let conns: Vec<_> = (0..4).map(|_| old.conn().unwrap()).collect();
+          drop(conns);
+          assert_eq!(lookup(&old, "a"), ("old a".to_string(), vec![1.0, 0.0]));
+          assert_eq!(lookup(&old, "b"), ("old b".to_string(), vec![0.0, 1.0]));
+          assert!(old.get_doc("c").unwrap().is_none());
+        }
+      })
+    };
+    for _ in 0..3 {
+      build_index(config(&new_corpus, &index_dir)).unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+
+    assert_eq!(old.docs_by_row().unwrap().len(), 2);
+    let new = Index::open(&index_dir, &model()).unwrap();
+    assert_eq!(lookup(&new, "a"), ("new a".to_string(), vec![0.0, 1.0]));
+    assert_eq!(new.docs(), 3);
+    drop((old, new));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn rebuild_waits_for_the_writer_and_forgets_the_sync_watermark() {
+    let dir = std::env::temp_dir().join(format!("local-search-rebuild-lock-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (corpus, index_dir) = (dir.join("corpus.jsonl"), dir.join("index"));
+    write_corpus(&corpus, &[("a", "a", [1.0, 0.0])]);
+    build_index(config(&corpus, &index_dir)).unwrap();
+    fs::write(index_dir.join(SYNC_STATE_FILE), "{}").unwrap();
+
+    let writer = WriteLock::acquire(&index_dir).unwrap();
+    let err = build_index(config(&corpus, &index_dir)).unwrap_err();
+    assert!(err.to_string().contains("already open for writing"), "{err}");
+    assert!(index_dir.join(MANIFEST_FILE).exists());
+    drop(writer);
+
+    build_index(config(&corpus, &index_dir)).unwrap();
+    assert!(!index_dir.join(SYNC_STATE_FILE).exists());
+    assert!(!index_dir.join(SQLITE_BUILD_FILE).exists());
+    assert_eq!(Index::open(&index_dir, &model()).unwrap().docs(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,494 @@
+use std::{fs, path::Path};
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+
+use crate::{
+  bench::XorShift,
+  collection::Index,
+  embedder::ModelSpec,
+  manifest::{BuildReport, IndexManifest, Shard, DEFAULT_VECTOR_FIELD},
+  segment::{Segment, SegmentsFile, BASE_SEGMENT},
+  vector_file::{VectorFile, NORM_TOLERANCE},
+  vector_store::dot,
+  wal::Wal,
+};
+
+/// Offending rows listed per problem; the rest are only counted.
+const MAX_EXAMPLES: usize = 10;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+  pub problem: &'static str,
+  pub count: usize,
+  /// The first few offending rows or doc ids.
+  pub examples: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckReport {
+  pub index_dir: String,
+  pub dims: usize,
+  /// Rows of the `doc` table.
+  pub docs: usize,
+  /// Rows of the `vec_map` table.
+  pub mapped_rows: usize,
+  /// Vectors read (mapped rows × vector fields).
+  pub vectors_checked: usize,
+  /// Whether norms were checked (`vectors.f32` is flagged normalized).
+  pub normalized: bool,
+  pub problems: Vec<Finding>,
+  pub passed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectReport {
+  pub index_dir: String,
+  pub model: String,
+  pub dims: usize,
+  pub fields: Vec<String>,
+  /// Searchable docs.
+  pub docs: usize,
+  /// Docs written by `build-index` (`manifest.json`).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub built_docs: Option<usize>,
+  pub segments: usize,
+  pub wal_rows: usize,
+  /// `vectors.f32` header flag (`None` for legacy raw files).
+  pub normalized: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub doc_max_length: Option<usize>,
+  /// Long/truncated doc counts from the build.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub build: Option<BuildReport>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub shard: Option<Shard>,
+  pub files: Vec<IndexFile>,
+  pub total_bytes: u64,
+  /// Default-field scores of random doc pairs (`None` below 2 docs).
+  pub random_pair_scores: Option<ScoreStats>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexFile {
+  pub name: String,
+  pub bytes: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreStats {
+  pub pairs: usize,
+  pub min: f32,
+  pub p1: f32,
+  pub p5: f32,
+  pub p25: f32,
+  pub p50: f32,
+  pub p75: f32,
+  pub p95: f32,
+  pub p99: f32,
+  pub max: f32,
+  pub mean: f32,
+}
+
+/// The manifest (if any) and a model spec `Index::open` accepts; `dims` is
+/// needed for indexes built before manifests existed.
+fn index_model(dir: &Path, dims: Option<usize>) -> anyhow::Result<(Option<IndexManifest>, ModelSpec)> {
+  let manifest = IndexManifest::load(dir)?;
+  let model = match (&manifest, dims) {
+    (Some(manifest), Some(dims)) if manifest.model.dims != dims => {
+      anyhow::bail!("--dims {dims} disagrees with manifest.json ({} dims)", manifest.model.dims)
+    }
+    (Some(manifest), _) => manifest.model.clone(),
+    (None, Some(dims)) => ModelSpec {
+      dims,
+      ..ModelSpec::bge_m3()
+    },
+    (None, None) => anyhow::bail!("{} has no manifest.json; pass --dims", dir.display()),
+  };
+  Ok((manifest, model))
+}
+
+fn open_sqlite(dir: &Path) -> anyhow::Result<Connection> {
+  let path = dir.join("doc_meta.sqlite");
+  Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    .map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))
+}
+
+fn add(problems: &mut Vec<Finding>, problem: &'static str, example: String) {
+  match problems.iter_mut().find(|f| f.problem == problem) {
+    Some(finding) => {
+      finding.count += 1;
+      if finding.examples.len() < MAX_EXAMPLES {
+        finding.examples.push(example);
+      }
+    }
+    None => problems.push(Finding {
+      problem,
+      count: 1,
+      examples: vec![example],
+    }),
+  }
+}
+
+/// Checks that `doc`, `vec_map` and the vector files agree and that every
+/// mapped vector is finite, non-zero and (for normalized indexes) unit length.
+/// Problems are collected, not returned as errors. Everything is opened
+/// read-only; nothing in `dir` is created or repaired.
+pub fn check_index(dir: &Path, dims: Option<usize>) -> anyhow::Result<CheckReport> {
+  let (manifest, model) = index_model(dir, dims)?;
+  let conn = open_sqlite(dir)?;
+  let mut problems = Vec::new();
+
+  let count = |table: &str| -> anyhow::Result<usize> {
+    Ok(conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |r| r.get::<_, i64>(0))? as usize)
+  };
+  let docs = count("doc")?;
+  let mapped_rows = count("vec_map")?;
+  if docs != mapped_rows {
+    add(
+      &mut problems,
+      "row count mismatch",
+      format!("doc has {docs} rows, vec_map has {mapped_rows}"),
+    );
+  }
+  {
+    let mut stmt = conn.prepare(
+      "SELECT v.row, v.doc_id FROM vec_map v LEFT JOIN doc d ON d.doc_id = v.doc_id WHERE d.doc_id IS NULL ORDER BY v.row",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+      add(
+        &mut problems,
+        "vec_map row without a doc",
+        format!("row {} ({})", r.get::<_, i64>(0)?, r.get::<_, String>(1)?),
+      );
+    }
+    let mut stmt = conn.prepare(
+      "SELECT d.doc_id FROM doc d LEFT JOIN vec_map v ON v.doc_id = d.doc_id WHERE v.doc_id IS NULL ORDER BY d.doc_id",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+      add(&mut problems, "doc without a vec_map row", r.get(0)?);
+    }
+  }
+
+  // The build's rows stay in `vectors.f32`; later docs go to other segments.
+  // An unreadable file is reported with the checksums below.
+  let mut normalized = true;
+  let mut base_rows = 0;
+  if let Ok(base) = VectorFile::open(&dir.join("vectors.f32"), model.dims) {
+    base_rows = base.len();
+    if let Some(manifest) = manifest.as_ref().filter(|m| m.docs != base.len()) {
+      add(
+        &mut problems,
+        "row count mismatch",
+        format!("vectors.f32 has {} rows, manifest.json says {} docs", base.len(), manifest.docs),
+      );
+    }
+    normalized = base.header().is_none_or(|h| h.normalized || h.count == 0);
+  }
+
+  // Opening doesn't read whole files, so checksums are verified here.
+  let mut vector_files: Vec<_> = fs::read_dir(dir)?
+    .map(|entry| entry.map(|e| e.path()))
+    .collect::<Result<_, _>>()?;
+  vector_files.retain(|path| path.extension().is_some_and(|ext| ext == "f32"));
+  vector_files.sort();
+  for path in &vector_files {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match VectorFile::open(path, model.dims) {
+      Ok(file) => {
+        if let Err(e) = file.verify_checksum() {
+          add(&mut problems, "checksum mismatch", format!("{name}: {e:#}"));
+        }
+      }
+      Err(e) => add(&mut problems, "unreadable vector file", format!("{e:#}")),
+    }
+  }
+
+  // The parts `Index::open` would load, each opened read-only on its own so
+  // one bad file doesn't hide problems in the rest.
+  let dims = model.dims;
+  let mut fields: Vec<String> = manifest.iter().flat_map(|m| &m.fields).map(|f| f.name.clone()).collect();
+  fields.sort();
+  let columns: Vec<String> = std::iter::once(DEFAULT_VECTOR_FIELD.to_string()).chain(fields).collect();
+  let segments_file = match SegmentsFile::load(dir) {
+    Ok(Some(file)) => Some(file),
+    Ok(None) => None,
+    Err(e) => {
+      add(&mut problems, "unreadable segments.json", format!("{e:#}"));
+      None
+    }
+  }
+  .unwrap_or_else(|| SegmentsFile {
+    next_id: 1,
+    wal_start: base_rows as u64,
+    segments: vec![BASE_SEGMENT.to_string()],
+  });
+  let segments: Vec<Segment> = segments_file
+    .segments
+    .iter()
+    .filter_map(|name| match Segment::open(dir, name, dims, &columns) {
+      Ok(segment) => Some(segment),
+      Err(e) => {
+        add(&mut problems, "unreadable segment", format!("{name}: {e:#}"));
+        None
+      }
+    })
+    .collect();
+  let wal = match Wal::open(dir, dims, columns.len(), segments_file.wal_start) {
+    Ok(wal) => Some(wal),
+    Err(e) => {
+      add(&mut problems, "unreadable WAL", format!("{e:#}"));
+      None
+    }
+  };
+  let end_row = segments
+    .iter()
+    .filter_map(Segment::last_row)
+    .chain(wal.as_ref().and_then(Wal::last_row))
+    .map(|row| row + 1)
+    .chain(std::iter::once(segments_file.wal_start))
+    .max()
+    .unwrap_or(0);
+  let stored = |column: usize, row: u64| -> Option<&[f32]> {
+    if let Some(i) = wal.as_ref().and_then(|w| w.position(row)) {
+      return wal.as_ref()?.vector(column, i);
+    }
+    segments
+      .iter()
+      .find_map(|s| s.position(row).and_then(|i| s.vector(column, i)))
+  };
+
+  let mut vectors_checked = 0;
+  let mut stmt = conn.prepare("SELECT row, doc_id FROM vec_map ORDER BY row")?;
+  let mut rows = stmt.query([])?;
+  while let Some(r) = rows.next()? {
+    let row: i64 = r.get(0)?;
+    let doc_id: String = r.get(1)?;
+    if row < 0 || row as u64 >= end_row {
+      add(
+        &mut problems,
+        "vec_map row out of range",
+        format!("row {row} ({doc_id}); stored rows end at {end_row}"),
+      );
+      continue;
+    }
+    if stored(0, row as u64).is_none() {
+      add(&mut problems, "vec_map row without a vector", format!("row {row} ({doc_id})"));
+      continue;
+    }
+    for (column, field) in columns.iter().enumerate() {
+      let at = || format!("row {row} ({doc_id}) {field}");
+      let Some(v) = stored(column, row as u64) else {
+        add(&mut problems, "vec_map row without a vector", at());
+        continue;
+      };
+      vectors_checked += 1;
+      if v.iter().any(|x| !x.is_finite()) {
+        add(&mut problems, "NaN or infinite vector", at());
+        continue;
+      }
+      let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+      if norm == 0.0 {
+        add(&mut problems, "zero vector", at());
+      } else if normalized && (norm - 1.0).abs() > NORM_TOLERANCE {
+        add(&mut problems, "vector not unit length", format!("{}: norm {norm:.4}", at()));
+      }
+    }
+  }
+
+  Ok(CheckReport {
+    index_dir: dir.display().to_string(),
+    dims: model.dims,
+    docs,
+    mapped_rows,
+    vectors_checked,
+    normalized,
+    passed: problems.is_empty(),
+    problems,
+  })
+}
+
+/// Sizes, counts and build stats of an index, plus the scores of `pairs`
+/// random doc pairs (what an unrelated hit scores).
+pub fn inspect_index(dir: &Path, dims: Option<usize>, pairs: usize, seed: u64) -> anyhow::Result<InspectReport> {
+  let (manifest, model) = index_model(dir, dims)?;
+  let index = Index::open(dir, &model)?;
+  let normalized = VectorFile::open(&dir.join("vectors.f32"), model.dims)?
+    .header()
+    .map(|h| h.normalized);
+
+  let mut files = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    if entry.file_type()?.is_file() {
+      files.push(IndexFile {
+        name: entry.file_name().to_string_lossy().into_owned(),
+        bytes: entry.metadata()?.len(),
+      });
+    }
+  }
+  files.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let rows: Vec<usize> = {
+    let conn = index.conn()?;
+    let mut stmt = conn.prepare("SELECT row FROM vec_map ORDER BY row")?;
+    let rows = stmt
+      .query_map([], |r| r.get::<_, i64>(0))?
+      .map(|r| r.map(|row| row as usize))
+      .collect::<Result<_, _>>()?;
+    rows
+  };
+  let random_pair_scores = if rows.len() < 2 || pairs == 0 {
+    None
+  } else {
+    let mut rng = XorShift(seed.max(1));
+    let mut pick = || rows[(rng.next_u64() % rows.len() as u64) as usize];
+    let mut scores = Vec::with_capacity(pairs);
+    while scores.len() < pairs {
+      let (a, b) = (pick(), pick());
+      if a == b {
+        continue;
+      }
+      if let (Some(a), Some(b)) = (index.vector(a), index.vector(b)) {
+        scores.push(dot(&a, &b));
+      }
+    }
+    Some(ScoreStats::new(scores))
+  };
+
+  Ok(InspectReport {
+    index_dir: dir.display().to_string(),
+    model: model.id,
+    dims: model.dims,
+    fields: index.fields.clone(),
+    docs: index.docs(),
+    built_docs: manifest.as_ref().map(|m| m.docs),
+    segments: index.segments(),
+    wal_rows: index.wal_rows(),
+    normalized,
+    doc_max_length: manifest.as_ref().map(|m| m.doc_max_length),
+    build: manifest.as_ref().map(|m| m.report.clone()),
+    shard: manifest.as_ref().and_then(|m| m.shard),
+    total_bytes: files.iter().map(|f| f.bytes).sum(),
+    files,
+    random_pair_scores,
+  })
+}
+
+impl ScoreStats {
+  fn new(mut scores: Vec<f32>) -> Self {
+    scores.sort_by(|a, b| a.total_cmp(b));
+    let at = |q: f64| scores[((scores.len() - 1) as f64 * q).round() as usize];
+    Self {
+      pairs: scores.len(),
+      min: scores[0],
+      p1: at(0.01),
+      p5: at(0.05),
+      p25: at(0.25),
+      p50: at(0.5),
+      p75: at(0.75),
+      p95: at(0.95),
+      p99: at(0.99),
+      max: scores[scores.len() - 1],
+      mean: scores.iter().sum::<f32>() / scores.len() as f32,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    index_builder::{
+      build_index,
+      tests::{config, write_corpus},
+    },
+    vector_file::VectorWriter,
+  };
+
+  fn problems(dir: &Path) -> Vec<&'static str> {
+    check_index(dir, None).unwrap().problems.iter().map(|f| f.problem).collect()
+  }
+
+  fn write_vectors(path: &Path, rows: &[[f32; 2]]) {
+    let mut w = VectorWriter::create(path, 2).unwrap();
+    for v in rows {
+      w.push(v).unwrap();
+    }
+    w.finish().unwrap();
+  }
+
+  fn listing(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+      .unwrap()
+      .map(|e| {
+        let path = e.unwrap().path();
+        (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read(&path).unwrap())
+      })
+      .collect();
+    files.sort();
+    files
+  }
+
+  #[test]
+  fn finds_every_kind_of_damage_without_touching_the_index() {
+    let dir = std::env::temp_dir().join(format!("local-search-check-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let (corpus, index_dir) = (dir.join("corpus.jsonl"), dir.join("index"));
+    let docs: Vec<(String, [f32; 2])> = (0..6).map(|i| (format!("d{i}"), [0.6, 0.8])).collect();
+    let docs: Vec<(&str, &str, [f32; 2])> = docs.iter().map(|(id, v)| (id.as_str(), "", *v)).collect();
+    write_corpus(&corpus, &docs);
+    build_index(config(&corpus, &index_dir)).unwrap();
+    let report = check_index(&index_dir, None).unwrap();
+    assert!(report.passed, "{:?}", report.problems.iter().map(|f| f.problem).collect::<Vec<_>>());
+    assert_eq!((report.docs, report.mapped_rows, report.vectors_checked), (6, 6, 6));
+
+    // Orphans on both sides of vec_map, plus bad vectors.
+    let conn = Connection::open(index_dir.join("doc_meta.sqlite")).unwrap();
+    conn
+      .execute_batch("DELETE FROM doc WHERE doc_id = 'd1'; INSERT INTO doc VALUES ('orphan', 9, 't', '');")
+      .unwrap();
+    drop(conn);
+    let vectors = index_dir.join("vectors.f32");
+    write_vectors(&vectors, &[[0.6, 0.8], [0.6, 0.8], [f32::NAN, 0.0], [0.0, 0.0], [0.6, 0.8], [0.6, 0.8]]);
+    let found = problems(&index_dir);
+    for problem in ["vec_map row without a doc", "doc without a vec_map row", "NaN or infinite vector", "zero vector"] {
+      assert!(found.contains(&problem), "{problem}: {found:?}");
+    }
+
+    // A flipped payload byte fails the checksum.
+    let mut bytes = fs::read(&vectors).unwrap();
+    *bytes.last_mut().unwrap() ^= 0x40;
+    fs::write(&vectors, bytes).unwrap();
+    assert!(problems(&index_dir).contains(&"checksum mismatch"));
+
+    // Truncated vectors and a broken segment list: the rest is still checked.
+    write_vectors(&vectors, &[[0.6, 0.8]; 4]);
+    fs::write(
+      index_dir.join("segments.json"),
+      r#"{"nextId":2,"walStart":4,"segments":["vectors","seg-000001"]}"#,
+    )
+    .unwrap();
+    fs::write(index_dir.join("wal.log"), [1u8, 2, 3]).unwrap();
+    let before = listing(&index_dir);
+    let report = check_index(&index_dir, None).unwrap();
+    let found: Vec<_> = report.problems.iter().map(|f| f.problem).collect();
+    for problem in ["row count mismatch", "unreadable segment", "vec_map row out of range"] {
+      assert!(found.contains(&problem), "{problem}: {found:?}");
+    }
+    let out_of_range = report.problems.iter().find(|f| f.problem == "vec_map row out of range").unwrap();
+    assert_eq!(out_of_range.count, 2);
+    assert_eq!(report.vectors_checked, 4);
+    assert!(!report.passed);
+    assert_eq!(listing(&index_dir), before);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// How documents longer than the model's max length are embedded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LongDocStrategy {
+  /// Keep the first `max_length` tokens (everything after is dropped).
+  #[default]
+  Head,
+  /// Keep the first and last half of the budget, dropping the middle.
+  HeadTail,
+  /// Embed overlapping windows over the whole text and mean-pool them.
+  SlidingWindow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LongDocOptions {
+  pub strategy: LongDocStrategy,
+  /// Content tokens between window starts (`None` = half a window).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub window_stride: Option<usize>,
+  /// Windows beyond this are dropped (and the doc counts as truncated).
+  pub max_windows: usize,
+}
+
+impl Default for LongDocOptions {
+  fn default() -> Self {
+    Self {
+      strategy: LongDocStrategy::Head,
+      window_stride: None,
+      max_windows: 16,
+    }
+  }
+}
+
+/// A normalized document vector plus what had to be done to produce it.
+pub struct DocEmbedding {
+  pub vector: Vec<f32>,
+  /// Token count of the whole text, including special tokens.
+  pub tokens: usize,
+  /// Whether some of the text never reached the model.
+  pub truncated: bool,
+  /// Forward-pass inputs used (1 unless sliding windows kicked in).
+  pub windows: usize,
+}
+
+/// Splits `content_len` content tokens into windows of at most `budget`
+/// tokens. Each window is a list of ranges that get concatenated; only
+/// head+tail uses more than one. Returns the windows and whether any content
+/// was left out.
+// Windows are lists of ranges, so single-range `vec![a..b]` is intended.
+#[allow(clippy::single_range_in_vec_init)]
+pub fn plan_windows(content_len: usize, budget: usize, opts: &LongDocOptions) -> (Vec<Vec<Range<usize>>>, bool) {
+  if content_len <= budget {
+    return (vec![vec![0..content_len]], false);
+  }
+
+  match opts.strategy {
+    LongDocStrategy::Head => (vec![vec![0..budget]], true),
+    LongDocStrategy::HeadTail => {
+      let head = budget.div_ceil(2);
+      let tail = budget - head;
+      (vec![vec![0..head, content_len - tail..content_len]], true)
+    }
+    LongDocStrategy::SlidingWindow => {
+      let stride = opts.window_stride.unwrap_or(budget / 2).clamp(1, budget);
+      let mut windows = Vec::new();
+      let mut start = 0;
+      loop {
+        let end = (start + budget).min(content_len);
+        windows.push(vec![start..end]);
+        if end == content_len {
+          return (windows, false);
+        }
+        if windows.len() == opts.max_windows.max(1) {
+          return (windows, true);
+        }
+        start += stride;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+// Windows are lists of ranges, as in `plan_windows`.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+  use super::*;
+
+  fn opts(strategy: LongDocStrategy, window_stride: Option<usize>, max_windows: usize) -> LongDocOptions {
+    LongDocOptions {
+      strategy,
+      window_stride,
+      max_windows,
+    }
+  }
+
+  #[test]
+  fn short_docs_are_one_window_for_every_strategy() {
+    for strategy in [LongDocStrategy::Head, LongDocStrategy::HeadTail, LongDocStrategy::SlidingWindow] {
+      assert_eq!(plan_windows(5, 5, &opts(strategy, None, 16)), (vec![vec![0..5]], false));
+    }
+  }
+
+  #[test]
+  fn head_and_head_tail_keep_the_budget() {
+    assert_eq!(plan_windows(10, 4, &opts(LongDocStrategy::Head, None, 16)), (vec![vec![0..4]], true));
+    // An odd budget gives the extra token to the head.
+    assert_eq!(
+      plan_windows(10, 5, &opts(LongDocStrategy::HeadTail, None, 16)),
+      (vec![vec![0..3, 8..10]], true)
+    );
+  }
+
+  #[test]
+  fn sliding_windows_cover_the_whole_text() {
+    let (windows, truncated) = plan_windows(10, 4, &opts(LongDocStrategy::SlidingWindow, None, 16));
+    assert_eq!(windows, [vec![0..4], vec![2..6], vec![4..8], vec![6..10]]);
+    assert!(!truncated);
+
+    // The stride is clamped to 1..=budget, so windows never leave gaps.
+    let (windows, _) = plan_windows(10, 4, &opts(LongDocStrategy::SlidingWindow, Some(9), 16));
+    assert_eq!(windows, [vec![0..4], vec![4..8], vec![8..10]]);
+    let (windows, _) = plan_windows(5, 4, &opts(LongDocStrategy::SlidingWindow, Some(0), 16));
+    assert_eq!(windows, [vec![0..4], vec![1..5]]);
+  }
+
+  #[test]
+  fn sliding_windows_stop_at_max_windows() {
+    let (windows, truncated) = plan_windows(10, 4, &opts(LongDocStrategy::SlidingWindow, Some(4), 2));
+    assert_eq!(windows, [vec![0..4], vec![4..8]]);
+    assert!(truncated);
+  }
+}
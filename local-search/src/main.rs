@@ -1,22 +1,58 @@
+mod bench;
+mod collection;
+mod doc_template;
+mod embed_texts;
 mod embedder;
+mod embedder_pool;
+mod eval;
+mod export;
+mod external_vectors;
 mod index_builder;
+mod index_check;
+mod long_doc;
+mod manifest;
+mod merge_index;
+mod metrics;
+mod model_io;
+mod npy;
+mod pg_sync;
+mod query_cache;
+mod rank_diff;
+mod segment;
+mod session_options;
+mod vector_file;
 mod vector_store;
-
-use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+mod verify_model;
+mod wal;
+
+use std::{
+  collections::BTreeMap,
+  fs,
+  net::SocketAddr,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
 
 use axum::{
-  extract::State,
+  extract::{Path as UrlPath, Query, State},
   http::{HeaderValue, Method},
   routing::{get, post},
   Json, Router,
 };
 use clap::{Parser, Subcommand};
-use embedder::BgeM3Embedder;
+use collection::{Collection, Index, NewDoc, SharedModel, StoredDoc, Upserted, WriteLock, DEFAULT_COLLECTION};
+use embedder::{Embedder, InputKind, ModelSpec, OnnxEmbedder};
+use embedder_pool::{BatchConfig, EmbedderPool, PoolBusy, PoolStats};
+use manifest::DEFAULT_VECTOR_FIELD;
+use metrics::{LatencyRecorder, LatencySummary};
+use query_cache::{normalize_query, CacheFingerprint, QueryCache, QueryCacheStats};
+use rusqlite::OptionalExtension;
 use serde::{Deserialize, Serialize};
+use session_options::{OptimizationLevel, SessionOptions};
 use tokio::signal;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tracing::{info, warn};
-use vector_store::VectorStore;
 
 #[derive(Parser, Debug)]
 #[command(name = "litomi-local-search")]
@@ -30,13 +66,91 @@ struct Cli {
 enum Command {
   Serve(ServeArgs),
   BuildIndex(BuildIndexArgs),
+  /// Upgrade an index's raw vector files to the headered format in place.
+  Convert(ConvertArgs),
+  /// Measure retrieval quality (recall@k, MRR, nDCG@k) against relevance judgements.
+  Eval(EvalArgs),
+  /// Compare the top-k of two data directories over a query list.
+  DiffRankings(DiffRankingsArgs),
+  /// Measure search latency and QPS at several concurrency levels.
+  Bench(BenchArgs),
+  /// Check an exported model against reference vectors from the Python pipeline.
+  VerifyModel(VerifyModelArgs),
+  /// Run one search against a data directory without starting the server.
+  Search(SearchArgs),
+  /// Embed a file of texts into JSONL or `.npy`.
+  Embed(EmbedArgs),
+  /// Write an index's docs and vectors for Postgres (pgvector) or ANN tooling (npy, fvecs).
+  Export(ExportArgs),
+  /// Upsert docs changed in Postgres since the last sync into a collection.
+  Sync(SyncArgs),
+  /// Combine index directories (e.g. `build-index --shard` outputs) into one index.
+  MergeIndex(MergeIndexArgs),
+  /// Verify that an index's docs, row map and vectors agree.
+  CheckIndex(CheckIndexArgs),
+  /// Print an index's sizes, build stats and random-pair score distribution.
+  InspectIndex(InspectIndexArgs),
+}
+
+// onnxruntime settings shared by `serve` and `build-index`.
+#[derive(clap::Args, Debug, Clone)]
+struct OrtArgs {
+  /// Optional onnxruntime dynamic library path (recommended for `ort` load-dynamic).
+  #[arg(long)]
+  ort_dylib: Option<String>,
+
+  /// Threads per operator, per session (default: auto for `serve`, all cores for `build-index`).
+  #[arg(long, env = "LITOMI_ORT_INTRA_THREADS")]
+  ort_intra_threads: Option<usize>,
+
+  /// Threads for running independent operators in parallel (enables parallel execution).
+  #[arg(long, env = "LITOMI_ORT_INTER_THREADS")]
+  ort_inter_threads: Option<usize>,
+
+  /// Disable the CPU arena allocator (lower steady memory, slightly slower).
+  #[arg(long, env = "LITOMI_ORT_DISABLE_ARENA")]
+  ort_disable_arena: bool,
+
+  /// Graph optimization level.
+  #[arg(long, value_enum, default_value = "layout", env = "LITOMI_ORT_OPT_LEVEL")]
+  ort_opt_level: OptimizationLevel,
+
+  /// Directory for the cached optimized model (`serve` defaults to `<data-dir>/cache/ort`).
+  #[arg(long, env = "LITOMI_ORT_CACHE_DIR")]
+  ort_cache_dir: Option<String>,
+
+  /// Don't save or load the optimized model cache.
+  #[arg(long)]
+  no_ort_cache: bool,
+}
+
+impl OrtArgs {
+  fn session_options(&self, default_cache_dir: Option<PathBuf>) -> SessionOptions {
+    let optimized_cache_dir = if self.no_ort_cache {
+      None
+    } else {
+      self.ort_cache_dir.as_ref().map(PathBuf::from).or(default_cache_dir)
+    };
+
+    SessionOptions {
+      intra_threads: self.ort_intra_threads,
+      inter_threads: self.ort_inter_threads,
+      memory_arena: !self.ort_disable_arena,
+      optimization_level: self.ort_opt_level,
+      optimized_cache_dir,
+    }
+  }
 }
 
 #[derive(Parser, Debug)]
 struct ServeArgs {
-  /// Data directory containing `model/` and `index/`.
-  #[arg(long, default_value = "data")]
-  data_dir: String,
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Number of embedder sessions run in parallel. Each session holds its own
+  /// copy of the model weights (about 2 GB for bge-m3).
+  #[arg(long, default_value_t = 1, value_parser = parse_nonzero)]
+  embedder_sessions: usize,
 
   /// Base port to try (falls back to 17777). Overrides `LITOMI_PORT` if provided.
   #[arg(long, env = "LITOMI_PORT")]
@@ -46,13 +160,53 @@ struct ServeArgs {
   #[arg(long, default_value_t = 17877)]
   max_port: u16,
 
-  /// Optional onnxruntime dynamic library path (recommended for `ort` load-dynamic).
+  /// Optional file to load the query cache from at startup and save it to on shutdown.
   #[arg(long)]
-  ort_dylib: Option<String>,
+  query_cache_path: Option<String>,
+
+  /// How often the `/api/docs` WAL is flushed into a segment and small segments are merged (seconds, 0 = never).
+  #[arg(long, default_value_t = 300)]
+  merge_interval_secs: u64,
+}
+
+/// Model and collection loading shared by `serve` and the offline subcommands.
+#[derive(clap::Args, Debug, Clone)]
+struct LoadArgs {
+  /// Data directory containing `model/`, `index/` and optionally `collections/<name>/`.
+  #[arg(long, default_value = "data")]
+  data_dir: String,
+
+  /// Extra collection as NAME=DIR (an index directory, optionally with its own `model/`); repeatable.
+  #[arg(long = "collection", value_name = "NAME=DIR")]
+  collections: Vec<String>,
+
+  #[command(flatten)]
+  ort: OrtArgs,
 
   /// Max token length for queries.
   #[arg(long, default_value_t = 512)]
   query_max_length: usize,
+
+  /// Embedder sessions run in parallel. Only `serve` and `bench` take
+  /// `--embedder-sessions`; one-shot commands always load one.
+  #[arg(skip = 1usize)]
+  embedder_sessions: usize,
+
+  /// Max embed requests waiting for a free session before answering 503.
+  #[arg(long, default_value_t = 64, value_parser = parse_nonzero)]
+  embed_queue: usize,
+
+  /// Max queries embedded together in one padded forward pass (1 disables batching).
+  #[arg(long, default_value_t = 8)]
+  batch_max_size: usize,
+
+  /// How long a session waits to fill a batch once a query arrives (milliseconds).
+  #[arg(long, default_value_t = 2)]
+  batch_max_wait_ms: u64,
+
+  /// Max cached query vectors (0 disables the query cache).
+  #[arg(long, default_value_t = 1024)]
+  query_cache_size: usize,
 }
 
 #[derive(Parser, Debug)]
@@ -65,28 +219,453 @@ struct BuildIndexArgs {
   #[arg(long, default_value = "data/index")]
   out: String,
 
-  /// Model directory containing `bge-m3.onnx` and `tokenizer.json`.
+  /// Model directory (`model.json` describes the model; defaults to `bge-m3.onnx` + `tokenizer.json`).
   #[arg(long, default_value = "data/model")]
   model_dir: String,
 
-  /// Optional onnxruntime dynamic library path (recommended for `ort` load-dynamic).
-  #[arg(long)]
-  ort_dylib: Option<String>,
+  #[command(flatten)]
+  ort: OrtArgs,
 
   /// Max token length for documents during indexing.
   #[arg(long, default_value_t = 1024)]
   doc_max_length: usize,
+
+  /// How docs longer than `--doc-max-length` are embedded.
+  #[arg(long, value_enum, default_value = "head")]
+  long_doc_strategy: long_doc::LongDocStrategy,
+
+  /// Sliding-window stride in tokens (default: half a window).
+  #[arg(long)]
+  window_stride: Option<usize>,
+
+  /// Max sliding windows per doc; the rest of the text is dropped.
+  #[arg(long, default_value_t = 16)]
+  max_windows: usize,
+
+  /// Text to embed per doc, composed from corpus fields (e.g. "{title}\n태그: {tags}\n{text}").
+  #[arg(long, default_value = doc_template::DEFAULT_DOC_TEMPLATE)]
+  doc_template: String,
+
+  /// Extra named vector as NAME=TEMPLATE (e.g. "title={title}"); repeatable.
+  #[arg(long = "vector-field", value_name = "NAME=TEMPLATE")]
+  vector_fields: Vec<String>,
+
+  /// Use document vectors computed elsewhere instead of running the model: `.npy`,
+  /// `.fvecs`, or JSONL with `embedding` (and optionally `docId`), one row per corpus doc.
+  /// `model.json` in `--model-dir` must still describe the model that produced them.
+  #[arg(long)]
+  embeddings: Option<String>,
+
+  /// Rescale `--embeddings` rows that aren't unit length instead of failing.
+  #[arg(long)]
+  normalize_embeddings: bool,
+
+  /// Build only slice I of N (1-based, e.g. `2/4`) of the corpus; combine the
+  /// outputs with `merge-index`.
+  #[arg(long, value_name = "I/N")]
+  shard: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct CheckIndexArgs {
+  /// Index directory to check.
+  #[arg(long, default_value = "data/index")]
+  index_dir: String,
+
+  /// Vector dims, for indexes built before `manifest.json` existed.
+  #[arg(long)]
+  dims: Option<usize>,
+
+  /// Write the JSON report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct InspectIndexArgs {
+  /// Index directory to inspect.
+  #[arg(long, default_value = "data/index")]
+  index_dir: String,
+
+  /// Vector dims, for indexes built before `manifest.json` existed.
+  #[arg(long)]
+  dims: Option<usize>,
+
+  /// Random doc pairs scored for the score distribution (0 = skip).
+  #[arg(long, default_value_t = 10000)]
+  pairs: usize,
+
+  /// Seed for picking the pairs.
+  #[arg(long, default_value_t = 42)]
+  seed: u64,
+
+  /// Write the JSON report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct MergeIndexArgs {
+  /// Index directory to merge, in order (e.g. every `build-index --shard` output); repeatable.
+  #[arg(long = "input", value_name = "DIR", required = true)]
+  inputs: Vec<String>,
+
+  /// Output directory (must not be one of the inputs).
+  #[arg(long, default_value = "data/index")]
+  out: String,
+
+  /// Which doc to keep when several inputs hold the same doc id.
+  #[arg(long, value_enum, default_value = "error")]
+  on_conflict: merge_index::OnConflict,
+}
+
+#[derive(Parser, Debug)]
+struct ConvertArgs {
+  /// Index directory to upgrade (vectors, field vectors and segments).
+  #[arg(long, default_value = "data/index")]
+  index_dir: String,
+
+  /// Vector dims, for indexes built before `manifest.json` existed.
+  #[arg(long)]
+  dims: Option<usize>,
+}
+
+#[derive(Parser, Debug)]
+struct EvalArgs {
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Queries JSONL: `{"queryId", "query"}` plus any `/api/search` request fields.
+  #[arg(long)]
+  queries: String,
+
+  /// Relevance judgements JSONL: `{"queryId", "docId", "relevance"}` (relevance defaults to 1).
+  #[arg(long)]
+  qrels: String,
+
+  /// Cutoff for recall@k and nDCG@k (1..=50, the `topK` limit).
+  #[arg(long, default_value_t = 10)]
+  k: usize,
+
+  /// Collection to search.
+  #[arg(long, default_value = DEFAULT_COLLECTION)]
+  search_collection: String,
+
+  /// Write the JSON report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct DiffRankingsArgs {
+  /// Baseline: `--data-dir` and the other loading options.
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Data directory to compare against the baseline (same loading options, no extra `--collection`s).
+  #[arg(long)]
+  candidate_data_dir: String,
+
+  /// Queries JSONL, same format as `eval --queries`.
+  #[arg(long)]
+  queries: String,
+
+  /// Depth compared (1..=50).
+  #[arg(long, default_value_t = 10)]
+  k: usize,
+
+  /// Rank-biased overlap persistence (0 < p < 1; higher weighs deeper ranks more).
+  #[arg(long, default_value_t = 0.9)]
+  rbo_p: f64,
+
+  /// Collection to search on both sides.
+  #[arg(long, default_value = DEFAULT_COLLECTION)]
+  search_collection: String,
+
+  #[arg(long, value_enum, default_value = "json")]
+  format: ReportFormat,
+
+  /// Write the report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct BenchArgs {
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Embedder sessions, as for `serve --embedder-sessions`.
+  #[arg(long, default_value_t = 1, value_parser = parse_nonzero)]
+  embedder_sessions: usize,
+
+  /// Benchmark a generated index of N random unit vectors instead of a collection
+  /// (still embeds queries with `<data-dir>/model`).
+  #[arg(long, value_name = "N")]
+  synthetic_docs: Option<usize>,
+
+  /// Seed for `--synthetic-docs`.
+  #[arg(long, default_value_t = 42)]
+  seed: u64,
+
+  /// Queries JSONL (same format as `eval --queries`; default: a few built-in queries).
+  #[arg(long)]
+  queries: Option<String>,
+
+  /// Concurrency levels to run, comma separated.
+  #[arg(long, value_delimiter = ',', default_value = "1,4,16")]
+  concurrency: Vec<usize>,
+
+  /// Searches per concurrency level (percentiles cover the last 4096).
+  #[arg(long, default_value_t = 200)]
+  requests: usize,
+
+  /// Untimed searches before the first level (session warm-up).
+  #[arg(long, default_value_t = 10)]
+  warmup: usize,
+
+  #[arg(long, default_value_t = 10)]
+  top_k: usize,
+
+  /// Collection to search (ignored with `--synthetic-docs`).
+  #[arg(long, default_value = DEFAULT_COLLECTION)]
+  search_collection: String,
+
+  /// Write the JSON report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct VerifyModelArgs {
+  /// Model directory (`model.json`, tokenizer and ONNX files).
+  #[arg(long, default_value = "data/model")]
+  model_dir: String,
+
+  #[command(flatten)]
+  ort: OrtArgs,
+
+  /// References JSONL: `{"text", "embedding", "kind"?, "maxLength"?, "id"?}`.
+  #[arg(long)]
+  references: String,
+
+  /// ONNX file(s) in the model directory to check, e.g. an fp32 and an int8 export;
+  /// later files are also compared with the first (default: the model's own file).
+  #[arg(long = "model-file")]
+  model_files: Vec<String>,
+
+  /// Max token length for references without `maxLength`.
+  #[arg(long, default_value_t = 512)]
+  max_length: usize,
+
+  #[arg(long, default_value_t = 16)]
+  batch_size: usize,
+
+  /// Fail when any text's cosine to its reference is below this.
+  #[arg(long, default_value_t = 0.98)]
+  min_cosine: f64,
+
+  /// Fail when the mean cosine to the references is below this.
+  #[arg(long, default_value_t = 0.995)]
+  min_mean_cosine: f64,
+
+  /// Lowest-agreement texts listed per model file.
+  #[arg(long, default_value_t = 5)]
+  worst: usize,
+
+  /// Write the JSON report to this file instead of stdout.
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct SearchArgs {
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Query text.
+  query: Option<String>,
+
+  /// Whole `/api/search` request body as JSON (e.g. for `components`); the flags below override its fields.
+  #[arg(long)]
+  request: Option<String>,
+
+  /// Results to return (1..=50, default 10).
+  #[arg(long)]
+  top_k: Option<u32>,
+
+  /// Include the matched chunk text of each hit.
+  #[arg(long)]
+  include_snippet: bool,
+
+  /// Named vector to search.
+  #[arg(long)]
+  field: Option<String>,
+
+  /// Per-field weight as NAME=WEIGHT; repeatable (overrides `--field`).
+  #[arg(long = "field-weight", value_name = "NAME=WEIGHT")]
+  field_weights: Vec<String>,
+
+  /// Indexed doc to search like (negative weight: unlike) as DOC_ID[=WEIGHT]; repeatable.
+  #[arg(long = "like", value_name = "DOC_ID[=WEIGHT]")]
+  like: Vec<String>,
+
+  /// Collection to search.
+  #[arg(long, default_value = DEFAULT_COLLECTION)]
+  search_collection: String,
+
+  #[arg(long, value_enum, default_value = "text")]
+  format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+struct EmbedArgs {
+  /// Data directory; the model is read from `<data-dir>/model`.
+  #[arg(long, default_value = "data")]
+  data_dir: String,
+
+  /// Model directory (default: `<data-dir>/model`).
+  #[arg(long)]
+  model_dir: Option<String>,
+
+  #[command(flatten)]
+  ort: OrtArgs,
+
+  /// Texts: one per line, or `.jsonl` lines with `text` and optionally `id` and `kind`.
+  #[arg(long)]
+  input: String,
+
+  /// Kind of texts without their own `kind` (selects the model's instruction prefix).
+  #[arg(long, value_enum, default_value = "query")]
+  kind: InputKind,
+
+  /// Max token length per text.
+  #[arg(long, default_value_t = 512)]
+  max_length: usize,
+
+  #[arg(long, default_value_t = 16)]
+  batch_size: usize,
+
+  /// Keep the raw model output instead of L2-normalizing (`"normalize": false` in `/api/embed`).
+  #[arg(long)]
+  no_normalize: bool,
+
+  #[arg(long, value_enum, default_value = "jsonl")]
+  format: EmbedFormat,
+
+  /// Output file (required for `npy`; JSONL goes to stdout by default).
+  #[arg(long)]
+  output: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct ExportArgs {
+  /// Index directory to export (its `manifest.json` describes the model).
+  #[arg(long, default_value = "data/index")]
+  index_dir: String,
+
+  /// Output directory.
+  #[arg(long, default_value = "data/export")]
+  out: String,
+
+  #[arg(long, value_enum, default_value = "pgvector")]
+  format: export::ExportFormat,
+
+  /// Postgres table for `pgvector` (may be schema-qualified).
+  #[arg(long, default_value = "local_search_doc")]
+  table: String,
+
+  /// Vector field for `npy`/`fvecs` (`pgvector` exports every field as a column).
+  #[arg(long, default_value = DEFAULT_VECTOR_FIELD)]
+  field: String,
+
+  /// For `fvecs`: sample this many docs as queries and write `query.fvecs` plus
+  /// exact nearest neighbors in `groundtruth.ivecs`.
+  #[arg(long, default_value_t = 0)]
+  ground_truth_queries: usize,
+
+  /// Neighbors per query in `groundtruth.ivecs`.
+  #[arg(long, default_value_t = 100)]
+  ground_truth_k: usize,
+}
+
+#[derive(Parser, Debug)]
+struct SyncArgs {
+  #[command(flatten)]
+  load: LoadArgs,
+
+  /// Postgres connection URL (no TLS; e.g. the local test container).
+  #[arg(long, env = "POSTGRES_URL", hide_env_values = true)]
+  postgres_url: String,
+
+  /// Collection to upsert into.
+  #[arg(long, default_value = DEFAULT_COLLECTION)]
+  target_collection: String,
+
+  /// SQL returning corpus-shaped columns (`docId`, `mangaId`, `title`, `text`, template
+  /// fields) plus `watermark`, ordered by it; `$1` is the last watermark as text (NULL
+  /// at first). `@FILE` reads it from a file. Default: the web app's `manga` table.
+  #[arg(long)]
+  query: Option<String>,
+
+  /// Start after this watermark instead of the one saved in `<index>/sync.json`.
+  #[arg(long)]
+  since: Option<String>,
+
+  /// Rows fetched and upserted per round trip.
+  #[arg(long, default_value_t = MAX_UPSERT_DOCS)]
+  page_size: usize,
+
+  /// Print the mapped docs as JSONL instead of upserting them (the watermark isn't saved).
+  #[arg(long)]
+  dry_run: bool,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum OutputFormat {
+  Text,
+  Json,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum EmbedFormat {
+  Jsonl,
+  Npy,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ReportFormat {
+  Json,
+  Html,
 }
 
 struct AppState {
   version: &'static str,
-  model_id: &'static str,
-  dims: usize,
-  docs: usize,
   query_max_length: usize,
-  embedder: std::sync::Mutex<BgeM3Embedder>,
-  vectors: Arc<VectorStore>,
-  sqlite_path: PathBuf,
+  /// `<data-dir>/model` first, then any other model a collection needed.
+  models: Vec<Arc<SharedModel>>,
+  collections: BTreeMap<String, Collection>,
+  search_latency: LatencyRecorder,
+  embed_latency: LatencyRecorder,
+  /// Vector scan and sqlite hit lookup inside each search.
+  scan_latency: LatencyRecorder,
+  sqlite_latency: LatencyRecorder,
+}
+
+impl AppState {
+  fn primary_model(&self) -> &SharedModel {
+    &self.models[0]
+  }
+
+  /// Collection behind the unprefixed routes (`/api/search`, `/api/docs`,
+  /// `/healthz`): `default` when `<data-dir>/index` exists, otherwise the
+  /// first collection by name.
+  fn default_collection(&self) -> &str {
+    if self.collections.contains_key(DEFAULT_COLLECTION) {
+      return DEFAULT_COLLECTION;
+    }
+    self.collections.keys().next().map_or(DEFAULT_COLLECTION, String::as_str)
+  }
 }
 
 #[derive(Serialize)]
@@ -95,6 +674,7 @@ struct HealthzResponse {
   version: String,
   model: HealthzModel,
   index: HealthzIndex,
+  collections: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -103,149 +683,1141 @@ struct HealthzModel {
   dims: u32,
 }
 
-#[derive(Serialize)]
-struct HealthzIndex {
-  r#type: String,
-  docs: u64,
+#[derive(Serialize)]
+struct HealthzIndex {
+  r#type: String,
+  docs: u64,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  fields: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CollectionInfo {
+  name: String,
+  index_dir: String,
+  model: String,
+  generation: u64,
+  dims: usize,
+  docs: usize,
+  /// Rows appended through `/api/docs` since the last flush.
+  wal_rows: usize,
+  segments: usize,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  fields: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct UpsertResponse {
+  upserted: Vec<Upserted>,
+  docs: usize,
+}
+
+#[derive(Deserialize)]
+struct ReloadParams {
+  /// Only reload this collection (default: all).
+  #[serde(default)]
+  collection: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReloadResult {
+  name: String,
+  ok: bool,
+  generation: u64,
+  docs: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+  collections: Vec<ReloadResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatsResponse {
+  embedder: PoolStats,
+  query_cache: QueryCacheStats,
+  search_ms: LatencySummary,
+  embed_ms: LatencySummary,
+  scan_ms: LatencySummary,
+  sqlite_ms: LatencySummary,
+}
+
+#[derive(Deserialize)]
+struct EmbedRequest {
+  text: String,
+  #[serde(default = "default_true")]
+  normalize: bool,
+  /// `query` (default) or `document`; selects the model's instruction prefix.
+  #[serde(default)]
+  kind: InputKind,
+}
+
+fn default_true() -> bool {
+  true
+}
+
+#[derive(Serialize)]
+struct EmbedResponse {
+  dims: u32,
+  normalized: bool,
+  embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchRequest {
+  #[serde(default)]
+  query: String,
+  /// Extra weighted texts/docs combined with `query` in embedding space.
+  #[serde(default)]
+  components: Vec<QueryComponent>,
+  #[serde(default = "default_top_k")]
+  top_k: u32,
+  #[serde(default)]
+  include_snippet: bool,
+  /// Named vector to search (`"default"` = the `--doc-template` vector).
+  #[serde(default)]
+  field: Option<String>,
+  /// Per-field weights; scores are the weighted sum over fields. Overrides `field`.
+  #[serde(default)]
+  fields: BTreeMap<String, f32>,
+}
+
+fn default_top_k() -> u32 {
+  10
+}
+
+/// One term of a compositional query: either a text or an indexed doc, with a
+/// signed weight (negative means "less like this").
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryComponent {
+  #[serde(default)]
+  text: Option<String>,
+  #[serde(default)]
+  doc_id: Option<String>,
+  #[serde(default = "default_weight")]
+  weight: f32,
+}
+
+fn default_weight() -> f32 {
+  1.0
+}
+
+const MAX_QUERY_COMPONENTS: usize = 16;
+
+/// Docs per `/api/docs` request; each is embedded before anything is written.
+const MAX_UPSERT_DOCS: usize = 256;
+
+enum QueryPart {
+  Text(String),
+  Doc(String),
+}
+
+/// Client-side mistake detected while running a search (maps to 400).
+#[derive(Debug)]
+struct BadQuery(String);
+
+impl std::fmt::Display for BadQuery {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for BadQuery {}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchResponse {
+  query: String,
+  top_k: u32,
+  took_ms: u64,
+  hits: Vec<SearchHit>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchHit {
+  rank: u32,
+  doc_id: String,
+  score: f32,
+  manga: MangaMeta,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  chunk: Option<ChunkMeta>,
+}
+
+#[derive(Serialize)]
+struct MangaMeta {
+  id: i64,
+  title: String,
+  source: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChunkMeta {
+  id: u32,
+  text: String,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  tracing_subscriber::fmt()
+    .with_env_filter(
+      tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "info,litomi_local_search=info".into()),
+    )
+    .init();
+
+  let cli = Cli::parse();
+
+  match cli.command {
+    Command::Serve(args) => serve(args).await?,
+    Command::BuildIndex(args) => {
+      let model_dir = PathBuf::from(args.model_dir);
+      let model_spec = ModelSpec::load(&model_dir)?;
+      let out_dir = PathBuf::from(args.out);
+
+      index_builder::build_index(index_builder::BuildIndexConfig {
+        input: PathBuf::from(args.input),
+        out_dir,
+        model_dir,
+        model_spec,
+        ort_dylib_path: args.ort.ort_dylib.as_ref().map(PathBuf::from),
+        session_options: args.ort.session_options(None),
+        doc_max_length: args.doc_max_length,
+        long_doc: long_doc::LongDocOptions {
+          strategy: args.long_doc_strategy,
+          window_stride: args.window_stride,
+          max_windows: args.max_windows,
+        },
+        doc_template: doc_template::DocTemplate::parse(&args.doc_template)?,
+        vector_fields: parse_vector_fields(&args.vector_fields)?,
+        embeddings: args.embeddings.map(PathBuf::from),
+        normalize_embeddings: args.normalize_embeddings,
+        shard: args.shard.as_deref().map(parse_shard).transpose()?,
+      })?;
+
+      warn!("build-index completed");
+    }
+    Command::Convert(args) => convert_index(args)?,
+    Command::Eval(args) => run_eval(args).await?,
+    Command::DiffRankings(args) => diff_rankings(args).await?,
+    Command::Bench(args) => run_bench(args).await?,
+    Command::VerifyModel(args) => verify_model(args)?,
+    Command::Search(args) => search_once(args).await?,
+    Command::Embed(args) => embed_file(args)?,
+    Command::Export(args) => export_index(args)?,
+    Command::Sync(args) => sync_postgres(args).await?,
+    Command::MergeIndex(args) => {
+      let inputs: Vec<PathBuf> = args.inputs.iter().map(PathBuf::from).collect();
+      let summary = merge_index::merge_indexes(&inputs, Path::new(&args.out), args.on_conflict)?;
+      info!(
+        inputs = inputs.len(),
+        docs = summary.docs,
+        duplicates = summary.duplicates,
+        out = %args.out,
+        "merge-index completed"
+      );
+    }
+    Command::CheckIndex(args) => {
+      let report = index_check::check_index(Path::new(&args.index_dir), args.dims)?;
+      write_json_report(args.output.as_deref(), &report)?;
+      if !report.passed {
+        let problems: Vec<String> = report.problems.iter().map(|f| format!("{} ({})", f.problem, f.count)).collect();
+        anyhow::bail!("{} failed the check: {}", args.index_dir, problems.join(", "));
+      }
+      info!(docs = report.docs, vectors = report.vectors_checked, "check-index passed");
+    }
+    Command::InspectIndex(args) => {
+      let report = index_check::inspect_index(Path::new(&args.index_dir), args.dims, args.pairs, args.seed)?;
+      write_json_report(args.output.as_deref(), &report)?;
+    }
+  }
+
+  Ok(())
+}
+
+async fn sync_postgres(args: SyncArgs) -> anyhow::Result<()> {
+  let query = match &args.query {
+    Some(q) => match q.strip_prefix('@') {
+      Some(path) => fs::read_to_string(path).map_err(|e| anyhow::anyhow!("failed to read {path}: {e}"))?,
+      None => q.clone(),
+    },
+    None => pg_sync::DEFAULT_SYNC_QUERY.to_string(),
+  };
+  if !(1..=MAX_UPSERT_DOCS).contains(&args.page_size) {
+    anyhow::bail!("--page-size must be between 1 and {MAX_UPSERT_DOCS}");
+  }
+
+  let state = load_state(&args.load, Writers::Only(&args.target_collection))?;
+  let Some(collection) = state.collections.get(&args.target_collection) else {
+    anyhow::bail!("unknown collection: {}", args.target_collection);
+  };
+  let index_dir = collection.index().dir.clone();
+  let mut sync_state = pg_sync::SyncState::load(&index_dir)?;
+  let since = args.since.clone().or_else(|| sync_state.watermark.clone());
+  info!(collection = %args.target_collection, since = ?since, "pulling changed rows from Postgres");
+
+  let started = std::time::Instant::now();
+  let (watermark, docs) = pg_sync::pull_changes(&args.postgres_url, &query, since.as_deref(), args.page_size, |docs| {
+    let state = state.clone();
+    let target = args.target_collection.clone();
+    let dry_run = args.dry_run;
+    async move {
+      if dry_run {
+        for doc in docs {
+          println!("{}", serde_json::Value::Object(doc));
+        }
+        return Ok(());
+      }
+      let body = serde_json::Value::Array(docs.into_iter().map(serde_json::Value::Object).collect());
+      let Json(res) = upsert_docs(state, target, body, "sync").await.map_err(problem_error)?;
+      let replaced = res.upserted.iter().filter(|u| u.replaced).count();
+      info!(upserted = res.upserted.len(), replaced, total = res.docs, "upserted");
+      Ok(())
+    }
+  })
+  .await?;
+
+  if args.dry_run {
+    info!(docs, watermark = ?watermark, "dry run: nothing written");
+    return Ok(());
+  }
+  if docs > 0 {
+    // Flush the WAL so a reload (or the next start) opens a compact index.
+    collection.compact()?;
+  }
+  sync_state.watermark = watermark;
+  sync_state.docs += docs as u64;
+  sync_state.save(&index_dir)?;
+  info!(
+    docs,
+    watermark = ?sync_state.watermark,
+    took_ms = started.elapsed().as_millis() as u64,
+    "sync completed"
+  );
+  Ok(())
+}
+
+fn export_index(args: ExportArgs) -> anyhow::Result<()> {
+  let dir = PathBuf::from(&args.index_dir);
+  let Some(manifest) = manifest::IndexManifest::load(&dir)? else {
+    anyhow::bail!("{} has no manifest.json; rebuild the index first", dir.display());
+  };
+  if args.format != export::ExportFormat::Fvecs && args.ground_truth_queries > 0 {
+    anyhow::bail!("--ground-truth-queries is only written with --format fvecs");
+  }
+  let index = Index::open(&dir, &manifest.model)?;
+  let summary = export::export_index(
+    &index,
+    Path::new(&args.out),
+    &export::ExportOptions {
+      format: args.format,
+      table: args.table,
+      field: args.field,
+      ground_truth_queries: args.ground_truth_queries,
+      ground_truth_k: args.ground_truth_k,
+    },
+  )?;
+  info!(format = ?summary.format, docs = summary.docs, dims = summary.dims, files = ?summary.files, "export completed");
+  Ok(())
+}
+
+fn convert_index(args: ConvertArgs) -> anyhow::Result<()> {
+  let dir = PathBuf::from(args.index_dir);
+  let dims = match (manifest::IndexManifest::load(&dir)?, args.dims) {
+    (Some(manifest), Some(dims)) if manifest.model.dims != dims => {
+      anyhow::bail!("--dims {dims} disagrees with manifest.json ({} dims)", manifest.model.dims)
+    }
+    (Some(manifest), _) => manifest.model.dims,
+    (None, Some(dims)) => dims,
+    (None, None) => anyhow::bail!("{} has no manifest.json; pass --dims", dir.display()),
+  };
+
+  let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+    .map(|entry| entry.map(|e| e.path()))
+    .collect::<Result<_, _>>()?;
+  files.retain(|path| path.extension().is_some_and(|ext| ext == "f32"));
+  files.sort();
+  if files.is_empty() {
+    anyhow::bail!("no vector files in {}", dir.display());
+  }
+
+  for path in files {
+    match vector_file::convert_vector_file(&path, dims)? {
+      vector_file::Converted::Upgraded(header) => info!(
+        file = %path.display(),
+        rows = header.count,
+        normalized = header.normalized,
+        "converted to format v{}",
+        header.version
+      ),
+      vector_file::Converted::AlreadyCurrent => info!(file = %path.display(), "already in the current format"),
+    }
+  }
+  Ok(())
+}
+
+async fn run_eval(args: EvalArgs) -> anyhow::Result<()> {
+  if !(1..=50).contains(&args.k) {
+    anyhow::bail!("--k must be between 1 and 50");
+  }
+  let queries = eval::load_queries(Path::new(&args.queries))?;
+  let qrels = eval::load_qrels(Path::new(&args.qrels))?;
+  let state = load_state(&args.load, Writers::None)?;
+  if !state.collections.contains_key(&args.search_collection) {
+    anyhow::bail!("unknown collection: {}", args.search_collection);
+  }
+
+  let mut per_query = Vec::new();
+  let mut unjudged = 0;
+  for q in queries {
+    let Some(judged) = qrels.get(&q.id) else {
+      unjudged += 1;
+      continue;
+    };
+    let result = match search_request(q.request, args.k) {
+      Ok(req) => run_search(&state, &args.search_collection, req).await,
+      Err(e) => Err(e),
+    };
+    let (top, error) = match result {
+      Ok(res) => (res.hits.into_iter().map(|h| h.doc_id).collect::<Vec<_>>(), None),
+      Err(e) => (Vec::new(), Some(format!("{e}"))),
+    };
+    let missing = judged
+      .iter()
+      .filter(|(doc, rel)| **rel > 0.0 && !top.contains(doc))
+      .map(|(doc, _)| doc.clone())
+      .collect();
+    per_query.push(eval::QueryReport {
+      scores: eval::score(&top, judged, args.k),
+      query_id: q.id,
+      query: q.query,
+      missing,
+      top,
+      error,
+    });
+  }
+  if per_query.is_empty() {
+    anyhow::bail!("no query in {} has judgements in {}", args.queries, args.qrels);
+  }
+
+  let report = eval::EvalReport::new(args.search_collection, args.k, unjudged, per_query);
+  info!(
+    queries = report.queries,
+    unjudged = report.unjudged,
+    recall = format!("{:.4}", report.mean.recall),
+    mrr = format!("{:.4}", report.mean.mrr),
+    ndcg = format!("{:.4}", report.mean.ndcg),
+    failures = report.failures.len(),
+    "eval@{}",
+    report.k
+  );
+  write_json_report(args.output.as_deref(), &report)
+}
+
+async fn diff_rankings(args: DiffRankingsArgs) -> anyhow::Result<()> {
+  if !(1..=50).contains(&args.k) {
+    anyhow::bail!("--k must be between 1 and 50");
+  }
+  if !(args.rbo_p > 0.0 && args.rbo_p < 1.0) {
+    anyhow::bail!("--rbo-p must be between 0 and 1 (exclusive)");
+  }
+  let queries = eval::load_queries(Path::new(&args.queries))?;
+
+  let baseline = load_state(&args.load, Writers::None)?;
+  let candidate = load_state(
+    &LoadArgs {
+      data_dir: args.candidate_data_dir.clone(),
+      collections: Vec::new(),
+      ..args.load.clone()
+    },
+    Writers::None,
+  )?;
+  for (state, dir) in [(&baseline, &args.load.data_dir), (&candidate, &args.candidate_data_dir)] {
+    if !state.collections.contains_key(&args.search_collection) {
+      anyhow::bail!("{dir} has no collection `{}`", args.search_collection);
+    }
+  }
+
+  let ranked = |res: SearchResponse| -> Vec<rank_diff::RankedDoc> {
+    res
+      .hits
+      .into_iter()
+      .map(|h| rank_diff::RankedDoc {
+        doc_id: h.doc_id,
+        title: h.manga.title,
+        score: h.score,
+      })
+      .collect()
+  };
+  let mut per_query = Vec::with_capacity(queries.len());
+  for q in queries {
+    let run = |state: Arc<AppState>, request: serde_json::Map<String, serde_json::Value>| {
+      let collection = args.search_collection.clone();
+      async move { run_search(&state, &collection, search_request(request, args.k)?).await }
+    };
+    let (a, b) = (
+      run(baseline.clone(), q.request.clone()).await,
+      run(candidate.clone(), q.request).await,
+    );
+    per_query.push(match (a, b) {
+      (Ok(a), Ok(b)) => rank_diff::QueryDiff::new(q.id, q.query, ranked(a), ranked(b), args.rbo_p),
+      (Err(e), _) => rank_diff::QueryDiff::failed(q.id, q.query, format!("baseline: {e}")),
+      (_, Err(e)) => rank_diff::QueryDiff::failed(q.id, q.query, format!("candidate: {e}")),
+    });
+  }
+
+  let report = rank_diff::DiffReport::new(
+    args.load.data_dir,
+    args.candidate_data_dir,
+    args.search_collection,
+    args.k,
+    args.rbo_p,
+    per_query,
+  );
+  info!(
+    queries = report.queries,
+    changed = report.changed,
+    mean_overlap = format!("{:.4}", report.mean_overlap),
+    mean_rbo = format!("{:.4}", report.mean_rbo),
+    "rankings compared"
+  );
+  match args.format {
+    ReportFormat::Json => write_json_report(args.output.as_deref(), &report),
+    ReportFormat::Html => write_report(args.output.as_deref(), report.to_html()),
+  }
+}
+
+async fn search_once(args: SearchArgs) -> anyhow::Result<()> {
+  let mut body = match &args.request {
+    Some(json) => match serde_json::from_str(json)? {
+      serde_json::Value::Object(map) => map,
+      _ => anyhow::bail!("--request must be a JSON object"),
+    },
+    None => serde_json::Map::new(),
+  };
+  if let Some(query) = &args.query {
+    body.insert("query".to_string(), query.clone().into());
+  }
+  if let Some(top_k) = args.top_k {
+    body.insert("topK".to_string(), top_k.into());
+  }
+  if args.include_snippet {
+    body.insert("includeSnippet".to_string(), true.into());
+  }
+  if let Some(field) = &args.field {
+    body.insert("field".to_string(), field.clone().into());
+  }
+  if !args.field_weights.is_empty() {
+    let mut fields = serde_json::Map::new();
+    for spec in &args.field_weights {
+      let (name, weight) = parse_weighted(spec, "--field-weight")?;
+      fields.insert(name.to_string(), weight.into());
+    }
+    body.insert("fields".to_string(), fields.into());
+  }
+  if !args.like.is_empty() {
+    let components = body
+      .entry("components")
+      .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    let serde_json::Value::Array(components) = components else {
+      anyhow::bail!("--request: components must be an array");
+    };
+    for spec in &args.like {
+      let (doc_id, weight) = match spec.rsplit_once('=') {
+        Some(_) => parse_weighted(spec, "--like")?,
+        None => (spec.as_str(), 1.0),
+      };
+      components.push(serde_json::json!({ "docId": doc_id, "weight": weight }));
+    }
+  }
+  let req: SearchRequest = serde_json::from_value(serde_json::Value::Object(body))?;
+
+  let state = load_state(&args.load, Writers::None)?;
+  let res = run_search(&state, &args.search_collection, req).await?;
+  match args.format {
+    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&res)?),
+    OutputFormat::Text => print!("{}", format_hits(&args.search_collection, &res)),
+  }
+  Ok(())
+}
+
+/// `NAME=WEIGHT` with a finite weight.
+fn parse_weighted<'a>(spec: &'a str, flag: &str) -> anyhow::Result<(&'a str, f32)> {
+  let Some((name, weight)) = spec.rsplit_once('=') else {
+    anyhow::bail!("{flag} `{spec}`: expected NAME=WEIGHT");
+  };
+  match weight.trim().parse::<f32>() {
+    Ok(w) if w.is_finite() => Ok((name, w)),
+    _ => anyhow::bail!("{flag} `{spec}`: weight must be a number"),
+  }
+}
+
+/// Terminal-friendly rendering of a search response.
+fn format_hits(collection: &str, res: &SearchResponse) -> String {
+  let mut out = format!(
+    "{} hits for {:?} in {collection} ({} ms)\n",
+    res.hits.len(),
+    res.query,
+    res.took_ms
+  );
+  for hit in &res.hits {
+    out.push_str(&format!(
+      "{:>3}. {:.4}  {}  {} [{} #{}]\n",
+      hit.rank, hit.score, hit.doc_id, hit.manga.title, hit.manga.source, hit.manga.id
+    ));
+    if let Some(chunk) = &hit.chunk {
+      let snippet: String = chunk.text.split_whitespace().collect::<Vec<_>>().join(" ");
+      let snippet: String = snippet.chars().take(160).collect();
+      out.push_str(&format!("       chunk {}: {snippet}\n", chunk.id));
+    }
+  }
+  out
+}
+
+fn embed_file(args: EmbedArgs) -> anyhow::Result<()> {
+  let data_dir = PathBuf::from(&args.data_dir);
+  let model_dir = args.model_dir.as_ref().map_or_else(|| data_dir.join("model"), PathBuf::from);
+  let texts = embed_texts::load_texts(Path::new(&args.input))?;
+  if texts.is_empty() {
+    anyhow::bail!("{} has no texts", args.input);
+  }
+  let output = match (args.format, args.output.as_deref()) {
+    (EmbedFormat::Npy, None) => anyhow::bail!("--format npy needs --output"),
+    (_, output) => output,
+  };
+
+  embedder::init_ort(args.ort.ort_dylib.as_deref().map(Path::new))?;
+  let spec = ModelSpec::load(&model_dir)?;
+  let session_options = args.ort.session_options(Some(data_dir.join("cache").join("ort")));
+  let mut embedder = OnnxEmbedder::new(&model_dir, spec, &session_options)?;
+  let dims = embedder.dims();
+
+  let mut npy = match (args.format, output) {
+    (EmbedFormat::Npy, Some(path)) => Some(npy::NpyWriter::create(Path::new(path), texts.len(), dims)?),
+    _ => None,
+  };
+  let mut jsonl: Option<Box<dyn std::io::Write>> = match (args.format, output) {
+    (EmbedFormat::Jsonl, Some(path)) => Some(Box::new(std::io::BufWriter::new(fs::File::create(path)?))),
+    (EmbedFormat::Jsonl, None) => Some(Box::new(std::io::BufWriter::new(std::io::stdout().lock()))),
+    _ => None,
+  };
+
+  let started = std::time::Instant::now();
+  for chunk in texts.chunks(args.batch_size.max(1)) {
+    let inputs: Vec<embedder::EmbedInput<'_>> = chunk
+      .iter()
+      .map(|t| embedder::EmbedInput {
+        text: &t.text,
+        max_length: args.max_length,
+        kind: t.kind.unwrap_or(args.kind),
+      })
+      .collect();
+    let vectors = embedder.embed_batch(&inputs)?;
+    for ((text, input), mut v) in chunk.iter().zip(&inputs).zip(vectors) {
+      if !args.no_normalize {
+        l2_normalize_in_place(&mut v);
+      }
+      if let Some(npy) = npy.as_mut() {
+        npy.push(&v)?;
+      }
+      if let Some(out) = jsonl.as_mut() {
+        let line = embed_texts::EmbeddedText {
+          id: text.id.as_ref(),
+          text: &text.text,
+          kind: input.kind,
+          max_length: args.max_length,
+          embedding: &v,
+        };
+        serde_json::to_writer(&mut *out, &line)?;
+        out.write_all(b"\n")?;
+      }
+    }
+  }
+  if let Some(npy) = npy {
+    npy.finish()?;
+  }
+  if let Some(mut out) = jsonl {
+    out.flush()?;
+  }
+  info!(
+    texts = texts.len(),
+    dims,
+    took_ms = started.elapsed().as_millis() as u64,
+    "embed completed"
+  );
+  Ok(())
+}
+
+fn verify_model(args: VerifyModelArgs) -> anyhow::Result<()> {
+  let model_dir = PathBuf::from(&args.model_dir);
+  let spec = ModelSpec::load(&model_dir)?;
+  let refs = verify_model::load_references(Path::new(&args.references), spec.dims)?;
+  embedder::init_ort(args.ort.ort_dylib.as_deref().map(Path::new))?;
+  let session_options = args.ort.session_options(None);
+
+  let model_files = if args.model_files.is_empty() {
+    vec![spec.model_file.clone()]
+  } else {
+    args.model_files.clone()
+  };
+  let mut baseline: Option<Vec<Vec<f32>>> = None;
+  let mut variants = Vec::with_capacity(model_files.len());
+  for model_file in model_files {
+    let variant = ModelSpec {
+      model_file: model_file.clone(),
+      ..spec.clone()
+    };
+    let mut embedder = OnnxEmbedder::new(&model_dir, variant, &session_options)?;
+    let (vectors, took) = verify_model::embed_references(&mut embedder, &refs, args.max_length, args.batch_size)?;
+
+    let cosines: Vec<f64> = refs
+      .iter()
+      .zip(&vectors)
+      .map(|(r, v)| verify_model::cosine(&r.embedding, v))
+      .collect();
+    let reference = verify_model::CosineStats::new(&cosines, args.min_cosine);
+    let passed = reference.below_threshold == 0 && reference.mean >= args.min_mean_cosine;
+    let against_baseline = baseline.as_ref().map(|base| {
+      let cosines: Vec<f64> = base.iter().zip(&vectors).map(|(a, b)| verify_model::cosine(a, b)).collect();
+      verify_model::CosineStats::new(&cosines, args.min_cosine)
+    });
+    info!(
+      model_file,
+      min = format!("{:.5}", reference.min),
+      mean = format!("{:.5}", reference.mean),
+      below = reference.below_threshold,
+      passed,
+      "model file checked"
+    );
+
+    variants.push(verify_model::VariantReport {
+      model_file,
+      ms_per_text: took.as_secs_f64() * 1000.0 / refs.len() as f64,
+      worst: verify_model::worst_texts(&refs, &cosines, args.worst),
+      reference,
+      baseline: against_baseline,
+      passed,
+    });
+    if baseline.is_none() {
+      baseline = Some(vectors);
+    }
+  }
+
+  let failed: Vec<&str> = variants.iter().filter(|v| !v.passed).map(|v| v.model_file.as_str()).collect();
+  let failed = failed.join(", ");
+  let report = verify_model::VerifyReport {
+    model: spec.id,
+    references: refs.len(),
+    min_cosine: args.min_cosine,
+    min_mean_cosine: args.min_mean_cosine,
+    passed: failed.is_empty(),
+    variants,
+  };
+  write_json_report(args.output.as_deref(), &report)?;
+  if !report.passed {
+    anyhow::bail!(
+      "{failed}: agreement with the references is below --min-cosine {} / --min-mean-cosine {}",
+      args.min_cosine,
+      args.min_mean_cosine
+    );
+  }
+  Ok(())
+}
+
+async fn run_bench(args: BenchArgs) -> anyhow::Result<()> {
+  if args.concurrency.is_empty() || args.concurrency.contains(&0) {
+    anyhow::bail!("--concurrency levels must be > 0");
+  }
+  if !(1..=50).contains(&args.top_k) {
+    anyhow::bail!("--top-k must be between 1 and 50");
+  }
+  let queries: Vec<String> = match &args.queries {
+    Some(path) => eval::load_queries(Path::new(path))?
+      .into_iter()
+      .map(|q| q.query)
+      .filter(|q| !q.trim().is_empty())
+      .collect(),
+    None => bench::DEFAULT_QUERIES.iter().map(|q| q.to_string()).collect(),
+  };
+  if queries.is_empty() {
+    anyhow::bail!("no queries to run");
+  }
+
+  let mut load = args.load.clone();
+  load.embedder_sessions = args.embedder_sessions;
+  // Every request should reach the model, and the queue must hold every worker.
+  load.query_cache_size = 0;
+  load.embed_queue = load.embed_queue.max(args.concurrency.iter().copied().max().unwrap_or(1));
+  let mut collection = args.search_collection.clone();
+  let synthetic_dir = match args.synthetic_docs {
+    Some(docs) => {
+      let dir = std::env::temp_dir().join(format!("litomi-bench-{}", std::process::id()));
+      let spec = ModelSpec::load(&Path::new(&load.data_dir).join("model"))?;
+      info!(docs, dims = spec.dims, dir = %dir.display(), "writing synthetic index");
+      bench::synthetic_index(&dir, &spec, docs, args.seed)?;
+      collection = "bench".to_string();
+      load.collections = vec![format!("{collection}={}", dir.display())];
+      Some(dir)
+    }
+    None => None,
+  };
+
+  let result = bench_levels(&args, load, collection, queries, synthetic_dir.is_some()).await;
+  if let Some(dir) = synthetic_dir {
+    let _ = fs::remove_dir_all(dir);
+  }
+  write_json_report(args.output.as_deref(), &result?)
 }
 
-#[derive(Deserialize)]
-struct EmbedRequest {
-  text: String,
-  #[serde(default = "default_true")]
-  normalize: bool,
+async fn bench_levels(
+  args: &BenchArgs,
+  load: LoadArgs,
+  collection: String,
+  queries: Vec<String>,
+  synthetic: bool,
+) -> anyhow::Result<bench::BenchReport> {
+  let state = load_state(&load, Writers::None)?;
+  let Some(target) = state.collections.get(&collection) else {
+    anyhow::bail!("unknown collection: {collection}");
+  };
+  let index = target.index();
+  let model = target.model.clone();
+  let queries = Arc::new(queries);
+  for i in 0..args.warmup {
+    run_search(&state, &collection, bench_request(&queries, i, args.top_k)).await?;
+  }
+
+  let mut levels = Vec::with_capacity(args.concurrency.len());
+  for &concurrency in &args.concurrency {
+    for recorder in [&state.search_latency, &state.scan_latency, &state.sqlite_latency] {
+      recorder.reset();
+    }
+    model.embedder.reset_stats();
+
+    let next = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let total = Arc::new(LatencyRecorder::new());
+    let started = std::time::Instant::now();
+    let workers: Vec<_> = (0..concurrency)
+      .map(|_| {
+        let (state, queries, next, total, collection) =
+          (state.clone(), queries.clone(), next.clone(), total.clone(), collection.clone());
+        let (requests, top_k) = (args.requests, args.top_k);
+        tokio::spawn(async move {
+          let mut errors = 0;
+          loop {
+            let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            if i >= requests {
+              return errors;
+            }
+            let t = std::time::Instant::now();
+            match run_search(&state, &collection, bench_request(&queries, i, top_k)).await {
+              Ok(_) => total.record(t.elapsed()),
+              Err(_) => errors += 1,
+            }
+          }
+        })
+      })
+      .collect();
+    let mut errors = 0;
+    for worker in workers {
+      errors += worker.await?;
+    }
+    let wall = started.elapsed().as_secs_f64();
+
+    let pool = model.embedder.stats();
+    let level = bench::BenchLevel {
+      concurrency,
+      requests: args.requests,
+      errors,
+      qps: (args.requests - errors) as f64 / wall.max(f64::EPSILON),
+      total_ms: total.summary(),
+      queue_wait_ms: pool.queue_wait_ms,
+      tokenize_ms: pool.tokenize_ms,
+      onnx_ms: pool.onnx_ms,
+      scan_ms: state.scan_latency.summary(),
+      sqlite_ms: state.sqlite_latency.summary(),
+    };
+    info!(
+      concurrency,
+      qps = format!("{:.1}", level.qps),
+      p50_ms = level.total_ms.p50_ms,
+      p95_ms = level.total_ms.p95_ms,
+      p99_ms = level.total_ms.p99_ms,
+      onnx_p50_ms = level.onnx_ms.p50_ms,
+      scan_p50_ms = level.scan_ms.p50_ms,
+      errors,
+      "bench level done"
+    );
+    levels.push(level);
+  }
+
+  Ok(bench::BenchReport {
+    collection,
+    docs: index.docs(),
+    dims: index.dims,
+    synthetic,
+    top_k: args.top_k,
+    levels,
+  })
 }
 
-fn default_true() -> bool {
-  true
+/// The `i`th bench request: a plain query, cycling through `queries`.
+fn bench_request(queries: &[String], i: usize, top_k: usize) -> SearchRequest {
+  SearchRequest {
+    query: queries[i % queries.len()].clone(),
+    components: Vec::new(),
+    top_k: top_k as u32,
+    include_snippet: false,
+    field: None,
+    fields: BTreeMap::new(),
+  }
 }
 
-#[derive(Serialize)]
-struct EmbedResponse {
-  dims: u32,
-  normalized: bool,
-  embedding: Vec<f32>,
+/// A search request from a JSON object (e.g. a queries-file line) with `topK` forced.
+fn search_request(fields: serde_json::Map<String, serde_json::Value>, top_k: usize) -> anyhow::Result<SearchRequest> {
+  let mut req: SearchRequest = serde_json::from_value(serde_json::Value::Object(fields))?;
+  req.top_k = top_k as u32;
+  Ok(req)
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct SearchRequest {
-  query: String,
-  #[serde(default = "default_top_k")]
-  top_k: u32,
-  #[serde(default)]
-  include_snippet: bool,
+/// Runs `req` through the same path as `/api/collections/{name}/search`.
+async fn run_search(state: &AppState, collection: &str, req: SearchRequest) -> anyhow::Result<SearchResponse> {
+  search_collection(state, collection, req, "cli")
+    .await
+    .map(|Json(res)| res)
+    .map_err(problem_error)
 }
 
-fn default_top_k() -> u32 {
-  10
+/// A problem+json response as an error for the offline subcommands.
+fn problem_error((status, Json(body)): (axum::http::StatusCode, Json<serde_json::Value>)) -> anyhow::Error {
+  let detail = body.get("detail").and_then(|d| d.as_str()).unwrap_or_default();
+  anyhow::anyhow!("{status}: {detail}")
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct SearchResponse {
-  query: String,
-  top_k: u32,
-  took_ms: u64,
-  hits: Vec<SearchHit>,
+/// Pretty JSON to `path`, or stdout when `None`.
+fn write_json_report(path: Option<&str>, report: &impl Serialize) -> anyhow::Result<()> {
+  write_report(path, serde_json::to_string_pretty(report)?)
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct SearchHit {
-  rank: u32,
-  doc_id: String,
-  score: f32,
-  manga: MangaMeta,
-  #[serde(skip_serializing_if = "Option::is_none")]
-  chunk: Option<ChunkMeta>,
+fn write_report(path: Option<&str>, report: String) -> anyhow::Result<()> {
+  match path {
+    Some(path) => {
+      fs::write(path, report + "\n")?;
+      info!(path, "report written");
+    }
+    None => println!("{report}"),
+  }
+  Ok(())
 }
 
-#[derive(Serialize)]
-struct MangaMeta {
-  id: i64,
-  title: String,
-  source: String,
+fn parse_vector_fields(specs: &[String]) -> anyhow::Result<Vec<(String, doc_template::DocTemplate)>> {
+  let mut out: Vec<(String, doc_template::DocTemplate)> = Vec::with_capacity(specs.len());
+  for spec in specs {
+    let Some((name, template)) = spec.split_once('=') else {
+      anyhow::bail!("--vector-field `{spec}`: expected NAME=TEMPLATE");
+    };
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+      anyhow::bail!("--vector-field `{spec}`: name must be non-empty [A-Za-z0-9_-]");
+    }
+    if name == DEFAULT_VECTOR_FIELD || out.iter().any(|(n, _)| n == name) {
+      anyhow::bail!("--vector-field `{spec}`: duplicate or reserved name `{name}`");
+    }
+    let template = doc_template::DocTemplate::parse(template)
+      .map_err(|e| anyhow::anyhow!("--vector-field `{spec}`: {e}"))?;
+    out.push((name.to_string(), template));
+  }
+  Ok(out)
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ChunkMeta {
-  id: u32,
-  text: String,
+/// Clap parser for counts that must be at least 1.
+fn parse_nonzero(s: &str) -> Result<usize, String> {
+  match s.parse::<usize>() {
+    Ok(0) => Err("must be at least 1".to_string()),
+    Ok(n) => Ok(n),
+    Err(e) => Err(e.to_string()),
+  }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-  tracing_subscriber::fmt()
-    .with_env_filter(
-      tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| "info,litomi_local_search=info".into()),
-    )
-    .init();
+fn parse_shard(spec: &str) -> anyhow::Result<(usize, usize)> {
+  let parsed = spec
+    .split_once('/')
+    .and_then(|(i, n)| Some((i.trim().parse::<usize>().ok()?, n.trim().parse::<usize>().ok()?)));
+  match parsed {
+    Some((i, n)) if (1..=n).contains(&i) => Ok((i, n)),
+    _ => anyhow::bail!("--shard `{spec}`: expected I/N with 1 <= I <= N"),
+  }
+}
 
-  let cli = Cli::parse();
+/// Which collections `load_state` opens for writing; the rest are read-only.
+#[derive(Clone, Copy)]
+enum Writers<'a> {
+  None,
+  /// `serve`: every collection accepts `/api/docs`.
+  All,
+  /// `sync`: just the target collection.
+  Only(&'a str),
+}
 
-  match cli.command {
-    Command::Serve(args) => serve(args).await?,
-    Command::BuildIndex(args) => {
-      let model_dir = PathBuf::from(args.model_dir);
-      let model_path = model_dir.join("bge-m3.onnx");
-      let tokenizer_path = model_dir.join("tokenizer.json");
-      let out_dir = PathBuf::from(args.out);
+/// Loads every model and collection under `args.data_dir`.
+fn load_state(args: &LoadArgs, writers: Writers<'_>) -> anyhow::Result<Arc<AppState>> {
+  let data_dir = PathBuf::from(&args.data_dir);
+  let model_dir = data_dir.join("model");
 
-      index_builder::build_index(index_builder::BuildIndexConfig {
-        input: PathBuf::from(args.input),
-        out_dir,
-        model_path,
-        tokenizer_path,
-        ort_dylib_path: args.ort_dylib.map(PathBuf::from),
-        doc_max_length: args.doc_max_length,
-      })?;
+  let ort_dylib = args.ort.ort_dylib.as_ref().map(PathBuf::from);
+  embedder::init_ort(ort_dylib.as_deref())?;
+
+  let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+  let sessions = args.embedder_sessions.max(1);
+  let mut session_options = args.ort.session_options(Some(data_dir.join("cache").join("ort")));
+  // Split cores between sessions so parallel runs don't oversubscribe the CPU.
+  session_options.intra_threads = session_options.intra_threads.or(Some((cores / sessions).max(1)));
+
+  let load_model = |model_dir: &Path, spec: ModelSpec| -> anyhow::Result<Arc<SharedModel>> {
+    let embedders = (0..sessions)
+      .map(|_| {
+        OnnxEmbedder::new(model_dir, spec.clone(), &session_options)
+          .map(|e| Box::new(e) as Box<dyn Embedder>)
+      })
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    let embedder = EmbedderPool::new(
+      embedders,
+      args.embed_queue,
+      BatchConfig {
+        max_batch: args.batch_max_size,
+        max_wait: Duration::from_millis(args.batch_max_wait_ms),
+      },
+    )?;
+    Ok(Arc::new(SharedModel {
+      spec,
+      embedder,
+      query_cache: QueryCache::new(args.query_cache_size),
+    }))
+  };
 
-      warn!("build-index completed");
+  let mut models = vec![load_model(&model_dir, ModelSpec::load(&model_dir)?)?];
+
+  let mut collections = BTreeMap::new();
+  for source in collection::discover(&data_dir, &args.collections)? {
+    let model = match &source.model_dir {
+      None => models[0].clone(),
+      Some(dir) => {
+        let spec = ModelSpec::load(dir)?;
+        match models.iter().find(|m| m.spec.is_compatible(&spec)) {
+          Some(m) => m.clone(),
+          None => {
+            info!(collection = %source.name, model = %spec.id, "loading extra model");
+            let m = load_model(dir, spec)?;
+            models.push(m.clone());
+            m
+          }
+        }
+      }
+    };
+    let writable = match writers {
+      Writers::None => false,
+      Writers::All => true,
+      Writers::Only(name) => name == source.name,
+    };
+    let (index, write_lock) = if writable {
+      WriteLock::acquire(&source.index_dir)
+        .and_then(|lock| Ok((Index::open_writer(&source.index_dir, &model.spec)?, Some(lock))))
+    } else {
+      Index::open(&source.index_dir, &model.spec).map(|index| (index, None))
     }
+    .map_err(|e| anyhow::anyhow!("collection `{}`: {e}", source.name))?;
+    info!(
+      collection = %source.name,
+      index_dir = %source.index_dir.display(),
+      model = %model.spec.id,
+      docs = index.docs(),
+      "collection loaded"
+    );
+    collections.insert(
+      source.name.clone(),
+      Collection::new(source.name, model, index, write_lock),
+    );
+  }
+  if collections.is_empty() {
+    anyhow::bail!(
+      "no index found: build one into {} or add collections under {}",
+      data_dir.join("index").display(),
+      data_dir.join("collections").display()
+    );
   }
 
-  Ok(())
+  Ok(Arc::new(AppState {
+    version: "0.1.0",
+    query_max_length: args.query_max_length,
+    models,
+    collections,
+    search_latency: LatencyRecorder::new(),
+    embed_latency: LatencyRecorder::new(),
+    scan_latency: LatencyRecorder::new(),
+    sqlite_latency: LatencyRecorder::new(),
+  }))
 }
 
 async fn serve(args: ServeArgs) -> anyhow::Result<()> {
   let port = args.port.unwrap_or(17777);
   let addr = pick_listen_addr(port, args.max_port)?;
+  let load = LoadArgs {
+    embedder_sessions: args.embedder_sessions,
+    ..args.load.clone()
+  };
+  let state = load_state(&load, Writers::All)?;
 
-  let data_dir = PathBuf::from(&args.data_dir);
-  let model_dir = data_dir.join("model");
-  let index_dir = data_dir.join("index");
-
-  let model_path = model_dir.join("bge-m3.onnx");
-  let tokenizer_path = model_dir.join("tokenizer.json");
-  let sqlite_path = index_dir.join("doc_meta.sqlite");
-  let vectors_path = index_dir.join("vectors.f32");
-
-  let ort_dylib = args.ort_dylib.as_ref().map(PathBuf::from);
-  let embedder = BgeM3Embedder::new(
-    &model_path,
-    &tokenizer_path,
-    ort_dylib.as_deref(),
-  )?;
+  #[cfg(unix)]
+  {
+    let state = state.clone();
+    tokio::spawn(async move {
+      let Ok(mut hangup) = signal::unix::signal(signal::unix::SignalKind::hangup()) else {
+        warn!("failed to install SIGHUP handler; use POST /api/admin/reload instead");
+        return;
+      };
+      while hangup.recv().await.is_some() {
+        info!("SIGHUP received; reloading indexes");
+        reload_collections(state.clone(), None).await;
+      }
+    });
+  }
 
-  let vectors = VectorStore::open(&vectors_path, 1024)?;
-  let docs = vectors.len();
+  if args.merge_interval_secs > 0 {
+    let state = state.clone();
+    let period = Duration::from_secs(args.merge_interval_secs);
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+      loop {
+        ticker.tick().await;
+        compact_collections(state.clone()).await;
+      }
+    });
+  }
 
-  let state = Arc::new(AppState {
-    version: "0.1.0",
-    model_id: "BAAI/bge-m3",
-    dims: 1024,
-    docs,
-    query_max_length: args.query_max_length,
-    embedder: std::sync::Mutex::new(embedder),
-    vectors: Arc::new(vectors),
-    sqlite_path,
-  });
+  let query_cache_path = args.query_cache_path.as_ref().map(PathBuf::from);
+  // Only the primary model's cache is persisted.
+  let cache_fingerprint = CacheFingerprint::new(
+    &state.primary_model().spec,
+    &PathBuf::from(&args.load.data_dir).join("model"),
+    state.query_max_length,
+  )?;
+  if let Some(path) = &query_cache_path {
+    match state.primary_model().query_cache.load(path, &cache_fingerprint) {
+      Ok(n) => info!(entries = n, path = %path.display(), "query cache loaded"),
+      Err(e) => warn!(error = %e, path = %path.display(), "failed to load query cache"),
+    }
+  }
 
   let cors = CorsLayer::new()
-    .allow_methods([Method::GET, Method::POST])
+    .allow_methods([Method::GET, Method::POST, Method::DELETE])
     .allow_headers(tower_http::cors::Any)
     .allow_origin(AllowOrigin::predicate(|origin: &HeaderValue, _| {
       let Ok(origin) = origin.to_str() else {
@@ -260,14 +1832,25 @@ async fn serve(args: ServeArgs) -> anyhow::Result<()> {
 
   let app = Router::new()
     .route("/healthz", get(healthz))
+    .route("/api/stats", get(api_stats))
     .route("/api/embed", post(api_embed))
     .route("/api/search", post(api_search))
-    .with_state(state)
+    .route("/api/collections", get(api_collections))
+    .route("/api/collections/{name}/search", post(api_collection_search))
+    .route("/api/collections/{name}/docs", post(api_collection_docs_upsert))
+    .route(
+      "/api/collections/{name}/docs/{id}",
+      get(api_collection_docs_get).delete(api_collection_docs_delete),
+    )
+    .route("/api/docs", post(api_docs_upsert))
+    .route("/api/docs/{id}", get(api_docs_get).delete(api_docs_delete))
+    .route("/api/admin/reload", post(api_admin_reload))
+    .with_state(state.clone())
     .layer(cors);
 
   info!(
     bind = %addr,
-    data_dir = %args.data_dir,
+    data_dir = %args.load.data_dir,
     "starting local search server"
   );
 
@@ -276,6 +1859,13 @@ async fn serve(args: ServeArgs) -> anyhow::Result<()> {
     .with_graceful_shutdown(shutdown_signal())
     .await?;
 
+  if let Some(path) = &query_cache_path {
+    match state.primary_model().query_cache.save(path, &cache_fingerprint) {
+      Ok(n) => info!(entries = n, path = %path.display(), "query cache saved"),
+      Err(e) => warn!(error = %e, path = %path.display(), "failed to save query cache"),
+    }
+  }
+
   Ok(())
 }
 
@@ -317,17 +1907,325 @@ async fn shutdown_signal() {
 }
 
 async fn healthz(State(state): State<Arc<AppState>>) -> Json<HealthzResponse> {
+  let primary = state.primary_model();
+  let default = state.collections.get(state.default_collection()).map(|c| c.index());
   Json(HealthzResponse {
     ok: true,
     version: state.version.to_string(),
     model: HealthzModel {
-      id: state.model_id.to_string(),
-      dims: state.dims as u32,
+      id: primary.spec.id.clone(),
+      dims: primary.spec.dims as u32,
     },
     index: HealthzIndex {
       r#type: "FlatIP (in-process)".to_string(),
-      docs: state.docs as u64,
+      docs: default.as_ref().map_or(0, |index| index.docs()) as u64,
+      fields: default.map_or_else(Vec::new, |index| index.fields.clone()),
     },
+    collections: state.collections.keys().cloned().collect(),
+  })
+}
+
+async fn api_collections(State(state): State<Arc<AppState>>) -> Json<Vec<CollectionInfo>> {
+  Json(
+    state
+      .collections
+      .values()
+      .map(|c| {
+        let index = c.index();
+        CollectionInfo {
+          name: c.name.clone(),
+          index_dir: index.dir.display().to_string(),
+          model: c.model.spec.id.clone(),
+          generation: c.generation(),
+          dims: index.dims,
+          docs: index.docs(),
+          wal_rows: index.wal_rows(),
+          segments: index.segments(),
+          fields: index.fields.clone(),
+        }
+      })
+      .collect(),
+  )
+}
+
+async fn api_admin_reload(
+  State(state): State<Arc<AppState>>,
+  Query(params): Query<ReloadParams>,
+) -> Result<Json<ReloadResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = "/api/admin/reload";
+  if let Some(name) = &params.collection {
+    if !state.collections.contains_key(name) {
+      return Err(problem(404, "Not Found", &format!("unknown collection: {name}"), instance));
+    }
+  }
+
+  let results = reload_collections(state, params.collection).await;
+  let failed: Vec<String> = results
+    .iter()
+    .filter_map(|r| r.error.as_ref().map(|e| format!("{}: {e}", r.name)))
+    .collect();
+  if !failed.is_empty() {
+    let detail = format!("reload failed, previous index kept serving ({})", failed.join("; "));
+    return Err(problem(500, "Internal Server Error", &detail, instance));
+  }
+  Ok(Json(ReloadResponse { collections: results }))
+}
+
+/// Re-opens every collection (or just `only`) from disk. Each one swaps
+/// independently; a failure leaves that collection on its previous generation.
+async fn reload_collections(state: Arc<AppState>, only: Option<String>) -> Vec<ReloadResult> {
+  let task = tokio::task::spawn_blocking(move || {
+    state
+      .collections
+      .values()
+      .filter(|c| only.as_ref().is_none_or(|name| *name == c.name))
+      .map(|c| {
+        let started = std::time::Instant::now();
+        let (docs, error) = match c.reload() {
+          Ok(index) => {
+            info!(
+              collection = %c.name,
+              generation = c.generation(),
+              docs = index.docs(),
+              took_ms = started.elapsed().as_millis() as u64,
+              "index reloaded"
+            );
+            (index.docs(), None)
+          }
+          Err(e) => {
+            warn!(collection = %c.name, error = %e, "index reload failed; keeping previous generation");
+            (c.index().docs(), Some(format!("{e}")))
+          }
+        };
+        ReloadResult {
+          name: c.name.clone(),
+          ok: error.is_none(),
+          generation: c.generation(),
+          docs,
+          error,
+        }
+      })
+      .collect()
+  });
+  task.await.unwrap_or_default()
+}
+
+/// Flushes each collection's WAL into a segment and merges segments where due.
+async fn compact_collections(state: Arc<AppState>) {
+  let _ = tokio::task::spawn_blocking(move || {
+    for c in state.collections.values() {
+      let started = std::time::Instant::now();
+      match c.compact() {
+        Ok(Some(done)) => info!(
+          collection = %c.name,
+          wal_rows = done.wal_rows,
+          merged_segments = done.merged_segments,
+          rows = done.rows,
+          segments = c.index().segments(),
+          took_ms = started.elapsed().as_millis() as u64,
+          "segments compacted"
+        ),
+        Ok(None) => {}
+        Err(e) => warn!(collection = %c.name, error = %e, "segment compaction failed"),
+      }
+    }
+  })
+  .await;
+}
+
+async fn api_docs_upsert(
+  State(state): State<Arc<AppState>>,
+  Json(body): Json<serde_json::Value>,
+) -> Result<Json<UpsertResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let name = state.default_collection().to_string();
+  upsert_docs(state, name, body, "/api/docs").await
+}
+
+async fn api_collection_docs_upsert(
+  State(state): State<Arc<AppState>>,
+  UrlPath(name): UrlPath<String>,
+  Json(body): Json<serde_json::Value>,
+) -> Result<Json<UpsertResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/collections/{name}/docs");
+  upsert_docs(state, name, body, &instance).await
+}
+
+async fn api_docs_get(
+  State(state): State<Arc<AppState>>,
+  UrlPath(id): UrlPath<String>,
+) -> Result<Json<StoredDoc>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/docs/{id}");
+  let name = state.default_collection().to_string();
+  get_doc(state, name, id, &instance).await
+}
+
+async fn api_collection_docs_get(
+  State(state): State<Arc<AppState>>,
+  UrlPath((name, id)): UrlPath<(String, String)>,
+) -> Result<Json<StoredDoc>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/collections/{name}/docs/{id}");
+  get_doc(state, name, id, &instance).await
+}
+
+async fn api_docs_delete(
+  State(state): State<Arc<AppState>>,
+  UrlPath(id): UrlPath<String>,
+) -> Result<axum::http::StatusCode, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/docs/{id}");
+  let name = state.default_collection().to_string();
+  delete_doc(state, name, id, &instance).await
+}
+
+async fn api_collection_docs_delete(
+  State(state): State<Arc<AppState>>,
+  UrlPath((name, id)): UrlPath<(String, String)>,
+) -> Result<axum::http::StatusCode, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/collections/{name}/docs/{id}");
+  delete_doc(state, name, id, &instance).await
+}
+
+/// Embeds corpus-shaped docs (`docId`, `mangaId`, `title`, `text`, plus any
+/// template fields) the way `build-index` would and upserts them.
+async fn upsert_docs(
+  state: Arc<AppState>,
+  name: String,
+  body: serde_json::Value,
+  instance: &str,
+) -> Result<Json<UpsertResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let bad = |detail: &str| problem(400, "Bad Request", detail, instance);
+  let collection = find_collection(&state, &name, instance)?;
+
+  let items = match body {
+    serde_json::Value::Array(items) => items,
+    item @ serde_json::Value::Object(_) => vec![item],
+    _ => return Err(bad("body must be a doc object or an array of docs")),
+  };
+  if items.is_empty() || items.len() > MAX_UPSERT_DOCS {
+    return Err(bad(&format!("send between 1 and {MAX_UPSERT_DOCS} docs")));
+  }
+
+  // Embed the same way the index was built.
+  let index = collection.index();
+  let (doc_template, doc_max_length, long_doc) = match &index.manifest {
+    Some(m) => (m.doc_template.as_str(), m.doc_max_length, m.long_doc),
+    None => (doc_template::DEFAULT_DOC_TEMPLATE, 1024, long_doc::LongDocOptions::default()),
+  };
+  let internal = |e: anyhow::Error| problem(500, "Internal Server Error", &format!("{e}"), instance);
+  let doc_template = doc_template::DocTemplate::parse(doc_template).map_err(internal)?;
+  let field_templates = index
+    .fields
+    .iter()
+    .map(|field| {
+      let source = index
+        .manifest
+        .iter()
+        .flat_map(|m| &m.fields)
+        .find(|f| f.name == *field)
+        .map(|f| f.template.as_str())
+        .ok_or_else(|| anyhow::anyhow!("manifest has no template for field `{field}`"))?;
+      doc_template::DocTemplate::parse(source)
+    })
+    .collect::<anyhow::Result<Vec<_>>>()
+    .map_err(internal)?;
+
+  let mut docs = Vec::with_capacity(items.len());
+  for (i, item) in items.into_iter().enumerate() {
+    let serde_json::Value::Object(fields) = item else {
+      return Err(bad(&format!("docs[{i}] must be an object")));
+    };
+    let line = index_builder::CorpusLine::from_fields(fields).map_err(|e| bad(&format!("docs[{i}]: {e}")))?;
+    let Some(doc_id) = line.doc_id.clone().filter(|id| !id.trim().is_empty()) else {
+      return Err(bad(&format!("docs[{i}].docId is required")));
+    };
+
+    let embed = |template: &doc_template::DocTemplate| {
+      let text = template.render(|name| line.fields.get(name));
+      collection.model.embedder.embed_document(text, doc_max_length, long_doc)
+    };
+    let vector = embed(&doc_template).await.map_err(|e| embed_problem(e, instance))?.vector;
+    let mut field_vectors = Vec::with_capacity(field_templates.len());
+    for template in &field_templates {
+      field_vectors.push(embed(template).await.map_err(|e| embed_problem(e, instance))?.vector);
+    }
+
+    docs.push(NewDoc {
+      doc_id,
+      manga_id: line.manga_id,
+      title: line.title.unwrap_or_else(|| "(no title)".to_string()),
+      text: line.text.unwrap_or_default(),
+      vector,
+      field_vectors,
+    });
+  }
+
+  let result = tokio::task::spawn_blocking(move || {
+    let collection = &state.collections[&name];
+    let upserted = collection.upsert(docs)?;
+    anyhow::Ok(UpsertResponse {
+      upserted,
+      docs: collection.index().docs(),
+    })
+  })
+  .await
+  .map_err(|_| problem(500, "Internal Server Error", "upsert task failed", instance))?
+  .map_err(internal)?;
+
+  Ok(Json(result))
+}
+
+async fn get_doc(
+  state: Arc<AppState>,
+  name: String,
+  id: String,
+  instance: &str,
+) -> Result<Json<StoredDoc>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let index = find_collection(&state, &name, instance)?.index();
+  let doc = tokio::task::spawn_blocking(move || index.get_doc(&id))
+    .await
+    .map_err(|_| problem(500, "Internal Server Error", "lookup task failed", instance))?
+    .map_err(|e| problem(500, "Internal Server Error", &format!("{e}"), instance))?;
+  doc
+    .map(Json)
+    .ok_or_else(|| problem(404, "Not Found", "doc not found", instance))
+}
+
+async fn delete_doc(
+  state: Arc<AppState>,
+  name: String,
+  id: String,
+  instance: &str,
+) -> Result<axum::http::StatusCode, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  find_collection(&state, &name, instance)?;
+  let deleted = tokio::task::spawn_blocking(move || state.collections[&name].delete(&id))
+    .await
+    .map_err(|_| problem(500, "Internal Server Error", "delete task failed", instance))?
+    .map_err(|e| problem(500, "Internal Server Error", &format!("{e}"), instance))?;
+  if !deleted {
+    return Err(problem(404, "Not Found", "doc not found", instance));
+  }
+  Ok(axum::http::StatusCode::NO_CONTENT)
+}
+
+fn find_collection<'a>(
+  state: &'a AppState,
+  name: &str,
+  instance: &str,
+) -> Result<&'a Collection, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  state
+    .collections
+    .get(name)
+    .ok_or_else(|| problem(404, "Not Found", &format!("unknown collection: {name}"), instance))
+}
+
+async fn api_stats(State(state): State<Arc<AppState>>) -> Json<StatsResponse> {
+  let primary = state.primary_model();
+  Json(StatsResponse {
+    embedder: primary.embedder.stats(),
+    query_cache: primary.query_cache.stats(),
+    search_ms: state.search_latency.summary(),
+    embed_ms: state.embed_latency.summary(),
+    scan_ms: state.scan_latency.summary(),
+    sqlite_ms: state.sqlite_latency.summary(),
   })
 }
 
@@ -335,33 +2233,27 @@ async fn api_embed(
   State(state): State<Arc<AppState>>,
   Json(req): Json<EmbedRequest>,
 ) -> Result<Json<EmbedResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let started = std::time::Instant::now();
+
   let text = req.text.trim();
   if text.is_empty() {
     return Err(problem(400, "Bad Request", "text is required", "/api/embed"));
   }
 
-  let text = text.to_string();
-  let normalize = req.normalize;
-  let max_len = state.query_max_length;
+  let model = state.primary_model();
+  let mut embedding = model
+    .embedder
+    .embed(text.to_string(), state.query_max_length, req.kind)
+    .await
+    .map_err(|e| embed_problem(e, "/api/embed"))?;
+  if req.normalize {
+    l2_normalize_in_place(&mut embedding);
+  }
 
-  let state2 = state.clone();
-  let embedding = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<f32>> {
-    let mut guard = state2
-      .embedder
-      .lock()
-      .map_err(|_| anyhow::anyhow!("embedder lock poisoned"))?;
-    let mut v = guard.embed_dense_cls(&text, max_len)?;
-    if normalize {
-      l2_normalize_in_place(&mut v);
-    }
-    Ok(v)
-  })
-  .await
-  .map_err(|_| problem(500, "Internal Server Error", "embed task failed", "/api/embed"))?
-  .map_err(|e| problem(500, "Internal Server Error", &format!("{e}"), "/api/embed"))?;
+  state.embed_latency.record(started.elapsed());
 
   Ok(Json(EmbedResponse {
-    dims: state.dims as u32,
+    dims: model.embedder.dims() as u32,
     normalized: req.normalize,
     embedding,
   }))
@@ -370,41 +2262,72 @@ async fn api_embed(
 async fn api_search(
   State(state): State<Arc<AppState>>,
   Json(req): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  search_collection(&state, state.default_collection(), req, "/api/search").await
+}
+
+async fn api_collection_search(
+  State(state): State<Arc<AppState>>,
+  UrlPath(name): UrlPath<String>,
+  Json(req): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+  let instance = format!("/api/collections/{name}/search");
+  search_collection(&state, &name, req, &instance).await
+}
+
+async fn search_collection(
+  state: &AppState,
+  name: &str,
+  req: SearchRequest,
+  instance: &str,
 ) -> Result<Json<SearchResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
   let started = std::time::Instant::now();
 
-  let query = req.query.trim();
-  if query.chars().count() < 2 {
-    return Err(problem(
-      400,
-      "Bad Request",
-      "query must be at least 2 chars",
-      "/api/search",
-    ));
-  }
+  let collection = find_collection(state, name, instance)?;
+  let index = collection.index();
+
+  let parts = parse_query_parts(&req).map_err(|detail| problem(400, "Bad Request", &detail, instance))?;
+  let fields = resolve_fields(&index, &req).map_err(|detail| problem(400, "Bad Request", &detail, instance))?;
 
   let top_k = req.top_k.clamp(1, 50);
   let include_snippet = req.include_snippet;
-  let query_str = query.to_string();
-  let max_len = state.query_max_length;
-  let sqlite_path = state.sqlite_path.clone();
-  let vectors = state.vectors.clone();
-
-  let state2 = state.clone();
-  let hits = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<SearchHit>> {
-    let mut guard = state2
-      .embedder
-      .lock()
-      .map_err(|_| anyhow::anyhow!("embedder lock poisoned"))?;
-    let qv = guard.embed_dense_cls_normalized(&query_str, max_len)?;
-
-    let scored = vectors.search_top_k(&qv, top_k as usize)?;
-
-    let conn = rusqlite::Connection::open_with_flags(
-      sqlite_path,
-      rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
-    )?;
 
+  let mut embedded = Vec::with_capacity(parts.len());
+  for (part, weight) in &parts {
+    if let QueryPart::Text(text) = part {
+      let v = embed_query_cached(&collection.model, state.query_max_length, text)
+        .await
+        .map_err(|e| embed_problem(e, instance))?;
+      embedded.push((v.as_ref().clone(), *weight));
+    }
+  }
+
+  let (hits, scan_took, sqlite_took) = tokio::task::spawn_blocking(move || -> anyhow::Result<(Vec<SearchHit>, Duration, Duration)> {
+    let mut excluded_rows = Vec::new();
+    for (part, weight) in &parts {
+      if let QueryPart::Doc(doc_id) = part {
+        let row = lookup_doc_row(&*index.conn()?, doc_id)?
+          .ok_or_else(|| BadQuery(format!("unknown docId: {doc_id}")))?;
+        let v = index
+          .vector(row)
+          .ok_or_else(|| anyhow::anyhow!("vec_map row {row} is out of range"))?;
+        embedded.push((v, *weight));
+        excluded_rows.push(row);
+      }
+    }
+
+    let qv = combine_weighted(&embedded, index.dims)
+      .ok_or_else(|| BadQuery("query components cancel each other out".to_string()))?;
+
+    // Docs used as components would trivially rank first; fetch extra and drop them.
+    let scan_started = std::time::Instant::now();
+    let mut scored = index.search(&fields, &qv, top_k as usize + excluded_rows.len())?;
+    let scan_took = scan_started.elapsed();
+    scored.retain(|(row, _)| !excluded_rows.contains(row));
+    scored.truncate(top_k as usize);
+
+    let sqlite_started = std::time::Instant::now();
+    let conn = index.conn()?;
     let mut stmt = conn.prepare(
       r#"
 SELECT doc.doc_id, doc.manga_id, doc.title, doc.text
@@ -414,12 +2337,15 @@ WHERE vec_map.row = ?1
 "#,
     )?;
 
-    let mut out = Vec::with_capacity(scored.len());
-    for (rank0, (row, score)) in scored.into_iter().enumerate() {
-      let (doc_id, manga_id, title, text): (String, i64, String, String) = stmt.query_row(
-        [row as i64],
-        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
-      )?;
+    let mut out: Vec<SearchHit> = Vec::with_capacity(scored.len());
+    for (row, score) in scored {
+      // The doc may have been replaced or deleted through /api/docs since the scan.
+      let Some((doc_id, manga_id, title, text)): Option<(String, i64, String, String)> = stmt
+        .query_row([row as i64], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .optional()?
+      else {
+        continue;
+      };
 
       let chunk = if include_snippet {
         let snippet: String = text.chars().take(200).collect();
@@ -429,7 +2355,7 @@ WHERE vec_map.row = ?1
       };
 
       out.push(SearchHit {
-        rank: (rank0 as u32) + 1,
+        rank: out.len() as u32 + 1,
         doc_id,
         score,
         manga: MangaMeta {
@@ -441,20 +2367,147 @@ WHERE vec_map.row = ?1
       });
     }
 
-    Ok(out)
+    Ok((out, scan_took, sqlite_started.elapsed()))
   })
   .await
-  .map_err(|_| problem(500, "Internal Server Error", "search task failed", "/api/search"))?
-  .map_err(|e| problem(500, "Internal Server Error", &format!("{e}"), "/api/search"))?;
+  .map_err(|_| problem(500, "Internal Server Error", "search task failed", instance))?
+  .map_err(|e| match e.downcast_ref::<BadQuery>() {
+    Some(bad) => problem(400, "Bad Request", &bad.0, instance),
+    None => problem(500, "Internal Server Error", &format!("{e}"), instance),
+  })?;
+
+  let took = started.elapsed();
+  state.search_latency.record(took);
+  state.scan_latency.record(scan_took);
+  state.sqlite_latency.record(sqlite_took);
 
   Ok(Json(SearchResponse {
-    query: query.to_string(),
+    query: req.query.trim().to_string(),
     top_k,
-    took_ms: started.elapsed().as_millis() as u64,
+    took_ms: took.as_millis() as u64,
     hits,
   }))
 }
 
+/// Unit query vector for `text`, served from the query cache when possible.
+async fn embed_query_cached(model: &SharedModel, max_length: usize, text: &str) -> anyhow::Result<Arc<Vec<f32>>> {
+  let key = normalize_query(text);
+  if let Some(v) = model.query_cache.get(&key) {
+    return Ok(v);
+  }
+
+  let mut v = model.embedder.embed(key.clone(), max_length, InputKind::Query).await?;
+  l2_normalize_in_place(&mut v);
+  let v = Arc::new(v);
+  model.query_cache.insert(key, v.clone());
+  Ok(v)
+}
+
+/// Picks the fields to score against from `field`/`fields`. Empty means the
+/// default vectors. Doc components always use their default vector.
+fn resolve_fields(index: &Index, req: &SearchRequest) -> Result<Vec<(String, f32)>, String> {
+  let lookup = |name: &str| -> Result<String, String> {
+    if name == DEFAULT_VECTOR_FIELD || index.fields.iter().any(|f| f == name) {
+      return Ok(name.to_string());
+    }
+    let known: Vec<&str> = std::iter::once(DEFAULT_VECTOR_FIELD)
+      .chain(index.fields.iter().map(String::as_str))
+      .collect();
+    Err(format!("unknown vector field `{name}` (available: {})", known.join(", ")))
+  };
+
+  if req.fields.is_empty() {
+    return match req.field.as_deref() {
+      None => Ok(Vec::new()),
+      Some(name) => Ok(vec![(lookup(name)?, 1.0)]),
+    };
+  }
+
+  let mut out = Vec::with_capacity(req.fields.len());
+  for (name, weight) in &req.fields {
+    if !weight.is_finite() || *weight < 0.0 {
+      return Err(format!("fields.{name} must be a finite, non-negative weight"));
+    }
+    if *weight > 0.0 {
+      out.push((lookup(name)?, *weight));
+    }
+  }
+  if out.is_empty() {
+    return Err("fields needs at least one positive weight".to_string());
+  }
+  Ok(out)
+}
+
+/// Validates `query` + `components` and flattens them into weighted parts.
+fn parse_query_parts(req: &SearchRequest) -> Result<Vec<(QueryPart, f32)>, String> {
+  let mut parts = Vec::with_capacity(req.components.len() + 1);
+
+  let query = req.query.trim();
+  if !query.is_empty() || req.components.is_empty() {
+    if query.chars().count() < 2 {
+      return Err("query must be at least 2 chars".to_string());
+    }
+    parts.push((QueryPart::Text(query.to_string()), 1.0));
+  }
+
+  if req.components.len() > MAX_QUERY_COMPONENTS {
+    return Err(format!("at most {MAX_QUERY_COMPONENTS} components are allowed"));
+  }
+
+  for (i, c) in req.components.iter().enumerate() {
+    if !c.weight.is_finite() {
+      return Err(format!("components[{i}].weight must be a finite number"));
+    }
+
+    let part = match (c.text.as_deref().map(str::trim), c.doc_id.as_deref()) {
+      (Some(text), None) => {
+        if text.chars().count() < 2 {
+          return Err(format!("components[{i}].text must be at least 2 chars"));
+        }
+        QueryPart::Text(text.to_string())
+      }
+      (None, Some(doc_id)) => QueryPart::Doc(doc_id.to_string()),
+      _ => return Err(format!("components[{i}] needs exactly one of text or docId")),
+    };
+    parts.push((part, c.weight));
+  }
+
+  if !parts.iter().any(|(_, w)| *w > 0.0) {
+    return Err("at least one positive weight is required".to_string());
+  }
+
+  Ok(parts)
+}
+
+fn lookup_doc_row(conn: &rusqlite::Connection, doc_id: &str) -> anyhow::Result<Option<usize>> {
+  let row: Option<i64> = conn
+    .query_row("SELECT row FROM vec_map WHERE doc_id = ?1", [doc_id], |r| r.get(0))
+    .map(Some)
+    .or_else(|e| match e {
+      rusqlite::Error::QueryReturnedNoRows => Ok(None),
+      e => Err(e),
+    })?;
+  Ok(row.map(|r| r as usize))
+}
+
+/// Sums unit vectors scaled by their weights and re-normalizes the result.
+/// Returns `None` when the sum degenerates to (near) zero.
+fn combine_weighted(parts: &[(Vec<f32>, f32)], dims: usize) -> Option<Vec<f32>> {
+  let mut out = vec![0.0_f32; dims];
+  for (v, weight) in parts {
+    for (acc, x) in out.iter_mut().zip(v.iter()) {
+      *acc += weight * x;
+    }
+  }
+
+  let norm_sq: f32 = out.iter().map(|x| x * x).sum();
+  if norm_sq <= 1e-12 {
+    return None;
+  }
+  l2_normalize_in_place(&mut out);
+  Some(out)
+}
+
 fn l2_normalize_in_place(v: &mut [f32]) {
   let mut sum_sq = 0.0_f32;
   for &x in v.iter() {
@@ -469,6 +2522,14 @@ fn l2_normalize_in_place(v: &mut [f32]) {
   }
 }
 
+/// Maps embedder failures to problem+json; a full queue becomes 503.
+fn embed_problem(e: anyhow::Error, instance: &str) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+  if e.downcast_ref::<PoolBusy>().is_some() {
+    return problem(503, "Service Unavailable", &format!("{e}"), instance);
+  }
+  problem(500, "Internal Server Error", &format!("{e}"), instance)
+}
+
 fn problem(
   status: u16,
   title: &str,
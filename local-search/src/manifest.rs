@@ -0,0 +1,99 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  doc_template::DEFAULT_DOC_TEMPLATE,
+  embedder::ModelSpec,
+  long_doc::LongDocOptions,
+};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Written by `build-index` next to `vectors.f32`; records how the index was
+/// produced so `serve` can check it matches the loaded model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexManifest {
+  pub model: ModelSpec,
+  pub docs: usize,
+  pub doc_max_length: usize,
+  #[serde(default)]
+  pub long_doc: LongDocOptions,
+  /// `--doc-template` used to compose the embedded text.
+  #[serde(default = "default_doc_template")]
+  pub doc_template: String,
+  /// Extra named vectors (`vectors.<name>.f32`), row-aligned with `vectors.f32`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub fields: Vec<VectorField>,
+  #[serde(default)]
+  pub report: BuildReport,
+  /// Set by `build-index --shard`; `merge-index` combines the shards.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub shard: Option<Shard>,
+}
+
+/// The corpus slice a `--shard` build holds (see `index_builder::shard_rows`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Shard {
+  /// 1-based.
+  pub index: usize,
+  pub count: usize,
+  /// Docs in the whole corpus.
+  pub corpus_docs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorField {
+  pub name: String,
+  pub template: String,
+}
+
+/// Name for `vectors.f32` (the `--doc-template` vector) where fields are picked.
+pub const DEFAULT_VECTOR_FIELD: &str = "default";
+
+/// The default field is stored as plain `vectors.f32`.
+pub fn vector_field_file(name: &str) -> String {
+  format!("vectors.{name}.f32")
+}
+
+fn default_doc_template() -> String {
+  DEFAULT_DOC_TEMPLATE.to_string()
+}
+
+/// Counters collected while building.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildReport {
+  /// Docs longer than `docMaxLength` tokens.
+  pub long_docs: usize,
+  /// Docs where some text never reached the model.
+  pub truncated_docs: usize,
+  /// Forward-pass inputs used across all docs (> docs with sliding windows).
+  pub windows: usize,
+  pub max_tokens: usize,
+  /// `--embeddings` file the vectors came from (the model wasn't run).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub precomputed: Option<String>,
+}
+
+impl IndexManifest {
+  /// Returns `None` for indexes built before manifests existed.
+  pub fn load(index_dir: &Path) -> anyhow::Result<Option<Self>> {
+    let path = index_dir.join(MANIFEST_FILE);
+    if !path.exists() {
+      return Ok(None);
+    }
+    let manifest = serde_json::from_str(&fs::read_to_string(&path)?)
+      .map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))?;
+    Ok(Some(manifest))
+  }
+
+  pub fn save(&self, index_dir: &Path) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(self)?;
+    fs::write(index_dir.join(MANIFEST_FILE), json)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,352 @@
+use std::{
+  collections::{hash_map::Entry, HashMap},
+  path::{Path, PathBuf},
+};
+
+use rusqlite::{params, Connection};
+use tracing::warn;
+
+use crate::{
+  collection::Index,
+  index_builder::{create_tables, publish_sqlite, reset_index_dir},
+  manifest::{vector_field_file, BuildReport, IndexManifest, Shard, DEFAULT_VECTOR_FIELD},
+  vector_file::VectorWriter,
+};
+
+/// Which doc survives when several inputs hold the same doc id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnConflict {
+  /// Fail, naming the doc and both inputs.
+  Error,
+  /// Keep the doc from the earliest input.
+  First,
+  /// Keep the doc from the latest input (like an upsert).
+  Last,
+}
+
+pub struct MergeSummary {
+  pub docs: usize,
+  /// Docs dropped because another input won their doc id.
+  pub duplicates: usize,
+}
+
+/// Concatenates the searchable docs of `inputs` (in order) into a fresh index
+/// in `out_dir`, renumbering rows from 0. The inputs must have been built with
+/// the same model and settings. `out_dir` is locked like `build-index` does.
+pub fn merge_indexes(inputs: &[PathBuf], out_dir: &Path, on_conflict: OnConflict) -> anyhow::Result<MergeSummary> {
+  let mut manifests = Vec::with_capacity(inputs.len());
+  for dir in inputs {
+    if same_dir(dir, out_dir) {
+      anyhow::bail!("the output directory {} is also an input", out_dir.display());
+    }
+    let Some(manifest) = IndexManifest::load(dir)? else {
+      anyhow::bail!("{} has no manifest.json; rebuild the index first", dir.display());
+    };
+    manifests.push(manifest);
+  }
+  let Some(first) = manifests.first().cloned() else {
+    anyhow::bail!("nothing to merge");
+  };
+  for (dir, manifest) in inputs.iter().zip(&manifests).skip(1) {
+    if let Some(setting) = manifest_mismatch(&first, manifest) {
+      anyhow::bail!(
+        "{} was built with a different {setting} than {}; rebuild it with the same settings",
+        dir.display(),
+        inputs[0].display()
+      );
+    }
+  }
+  check_shards(&manifests)?;
+
+  // Open every input before the output is cleared.
+  let indexes = inputs
+    .iter()
+    .zip(&manifests)
+    .map(|(dir, manifest)| Index::open(dir, &manifest.model))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+
+  // (input, row) each doc id resolves to.
+  let mut winners: HashMap<String, (usize, usize)> = HashMap::new();
+  let mut duplicates = 0;
+  for (i, index) in indexes.iter().enumerate() {
+    // The same rows the copy pass walks: a `vec_map` row without a doc can't win.
+    for doc in index.docs_by_row()? {
+      match winners.entry(doc.doc_id) {
+        Entry::Vacant(e) => {
+          e.insert((i, doc.row));
+        }
+        Entry::Occupied(mut e) => {
+          duplicates += 1;
+          match on_conflict {
+            OnConflict::Error => anyhow::bail!(
+              "{} is in both {} and {}; pass --on-conflict first or last to keep one",
+              e.key(),
+              inputs[e.get().0].display(),
+              inputs[i].display()
+            ),
+            OnConflict::First => {}
+            OnConflict::Last => {
+              e.insert((i, doc.row));
+            }
+          }
+        }
+      }
+    }
+  }
+
+  let (_lock, sqlite_path, vectors_path) = reset_index_dir(out_dir)?;
+  let mut conn = Connection::open(&sqlite_path)?;
+  create_tables(&conn)?;
+  let dims = first.model.dims;
+  let mut vec_writer = VectorWriter::create(&vectors_path, dims)?;
+  let mut field_writers = first
+    .fields
+    .iter()
+    .map(|field| VectorWriter::create(&out_dir.join(vector_field_file(&field.name)), dims))
+    .collect::<anyhow::Result<Vec<_>>>()?;
+
+  let mut docs = 0usize;
+  let tx = conn.transaction()?;
+  {
+    let mut insert_doc = tx.prepare("INSERT INTO doc (doc_id, manga_id, title, text) VALUES (?1, ?2, ?3, ?4)")?;
+    let mut insert_row = tx.prepare("INSERT INTO vec_map (row, doc_id) VALUES (?1, ?2)")?;
+    for (i, index) in indexes.iter().enumerate() {
+      for doc in index.docs_by_row()? {
+        if winners.get(&doc.doc_id) != Some(&(i, doc.row)) {
+          continue;
+        }
+        let vector = |field: &str| {
+          index.field_vector(field, doc.row).ok_or_else(|| {
+            anyhow::anyhow!("{}: no {field} vector for row {} ({})", inputs[i].display(), doc.row, doc.doc_id)
+          })
+        };
+        vec_writer.push(&vector(DEFAULT_VECTOR_FIELD)?)?;
+        for (field, writer) in first.fields.iter().zip(field_writers.iter_mut()) {
+          writer.push(&vector(&field.name)?)?;
+        }
+        insert_doc.execute(params![doc.doc_id, doc.manga_id, doc.title, doc.text])?;
+        insert_row.execute(params![docs as i64, doc.doc_id])?;
+        docs += 1;
+      }
+    }
+  }
+  tx.commit()?;
+
+  let header = vec_writer.finish()?;
+  if !header.normalized && docs > 0 {
+    warn!("some document vectors are not unit length; scores won't be cosine similarities");
+  }
+  for writer in field_writers {
+    writer.finish()?;
+  }
+
+  // Counters are summed over the inputs, so they include dropped duplicates.
+  let mut report = BuildReport::default();
+  let mut precomputed: Vec<&str> = Vec::new();
+  for manifest in &manifests {
+    report.long_docs += manifest.report.long_docs;
+    report.truncated_docs += manifest.report.truncated_docs;
+    report.windows += manifest.report.windows;
+    report.max_tokens = report.max_tokens.max(manifest.report.max_tokens);
+    if let Some(path) = manifest.report.precomputed.as_deref().filter(|p| !precomputed.contains(p)) {
+      precomputed.push(path);
+    }
+  }
+  report.precomputed = (!precomputed.is_empty()).then(|| precomputed.join(", "));
+
+  IndexManifest {
+    docs,
+    report,
+    shard: None,
+    ..first
+  }
+  .save(out_dir)?;
+  publish_sqlite(conn, out_dir)?;
+
+  Ok(MergeSummary { docs, duplicates })
+}
+
+/// The first build setting that differs between two manifests.
+fn manifest_mismatch(a: &IndexManifest, b: &IndexManifest) -> Option<&'static str> {
+  if a.model != b.model {
+    Some("model (model.json)")
+  } else if a.doc_max_length != b.doc_max_length {
+    Some("--doc-max-length")
+  } else if a.long_doc != b.long_doc {
+    Some("long-doc strategy")
+  } else if a.doc_template != b.doc_template {
+    Some("--doc-template")
+  } else if a.fields != b.fields {
+    Some("set of --vector-field")
+  } else {
+    None
+  }
+}
+
+/// `--shard` inputs must come from the same split of the same corpus. Missing
+/// or out-of-order shards are allowed but reported.
+fn check_shards(manifests: &[IndexManifest]) -> anyhow::Result<()> {
+  let shards: Vec<Shard> = manifests.iter().filter_map(|m| m.shard).collect();
+  let Some(first) = shards.first().copied() else {
+    return Ok(());
+  };
+  let mut seen = vec![false; first.count];
+  for shard in &shards {
+    if shard.count != first.count || shard.corpus_docs != first.corpus_docs {
+      anyhow::bail!(
+        "shard {}/{} of a {}-doc corpus doesn't belong with shard {}/{} of a {}-doc corpus",
+        shard.index,
+        shard.count,
+        shard.corpus_docs,
+        first.index,
+        first.count,
+        first.corpus_docs
+      );
+    }
+    if !(1..=shard.count).contains(&shard.index) {
+      anyhow::bail!("invalid shard {}/{} in manifest.json", shard.index, shard.count);
+    }
+    if std::mem::replace(&mut seen[shard.index - 1], true) {
+      anyhow::bail!("shard {}/{} is given twice", shard.index, shard.count);
+    }
+  }
+  let missing: Vec<String> = (1..=first.count)
+    .filter(|i| !seen[i - 1])
+    .map(|i| format!("{i}/{}", first.count))
+    .collect();
+  if !missing.is_empty() {
+    warn!(missing = ?missing, "not every shard is merged");
+  }
+  if shards.windows(2).any(|w| w[0].index > w[1].index) {
+    warn!("shards aren't given in order; merged rows won't follow the corpus order");
+  }
+  if shards.len() != manifests.len() {
+    warn!("merging sharded and unsharded indexes");
+  }
+  Ok(())
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+  match (a.canonicalize(), b.canonicalize()) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::{
+    collection::WriteLock,
+    index_builder::{
+      build_index,
+      tests::{config, model, write_corpus},
+      BuildIndexConfig,
+    },
+  };
+
+  type Doc = (&'static str, &'static str, [f32; 2]);
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("local-search-merge-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  /// Builds `docs` into `<dir>/<name>`, optionally as one shard of them.
+  fn build(dir: &Path, name: &str, docs: &[Doc], shard: Option<(usize, usize)>) -> PathBuf {
+    let corpus = dir.join(format!("{name}.jsonl"));
+    write_corpus(&corpus, docs);
+    let out = dir.join(name);
+    build_index(BuildIndexConfig { shard, ..config(&corpus, &out) }).unwrap();
+    out
+  }
+
+  /// `(row, doc id, title, vector)` of every searchable doc.
+  fn dump(dir: &Path) -> Vec<(usize, String, String, Vec<f32>)> {
+    let index = Index::open(dir, &model()).unwrap();
+    index
+      .docs_by_row()
+      .unwrap()
+      .into_iter()
+      .map(|d| (d.row, d.doc_id, d.title.unwrap(), index.vector(d.row).unwrap()))
+      .collect()
+  }
+
+  const ONE: &[Doc] = &[("a", "a1", [1.0, 0.0]), ("b", "b1", [0.0, 1.0])];
+  const TWO: &[Doc] = &[("c", "c2", [0.6, 0.8]), ("a", "a2", [0.8, 0.6])];
+
+  #[test]
+  fn merged_shards_match_a_single_build() {
+    let dir = temp_dir("shards");
+    let docs: Vec<Doc> = ONE.iter().chain(&TWO[..1]).copied().collect();
+    let whole = build(&dir, "whole", &docs, None);
+    let first = build(&dir, "first", &docs, Some((1, 2)));
+    let second = build(&dir, "second", &docs, Some((2, 2)));
+
+    let out = dir.join("out");
+    let summary = merge_indexes(&[first, second], &out, OnConflict::Error).unwrap();
+    assert_eq!((summary.docs, summary.duplicates), (3, 0));
+    assert_eq!(dump(&out), dump(&whole));
+    let manifest = IndexManifest::load(&out).unwrap().unwrap();
+    assert_eq!((manifest.docs, manifest.shard), (3, None));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn on_conflict_picks_the_first_or_last_copy_or_fails() {
+    let dir = temp_dir("conflict");
+    let inputs = [build(&dir, "one", ONE, None), build(&dir, "two", TWO, None)];
+    let out = dir.join("out");
+
+    let err = merge_indexes(&inputs, &out, OnConflict::Error).err().unwrap();
+    assert!(err.to_string().contains("a is in both"), "{err}");
+
+    let summary = merge_indexes(&inputs, &out, OnConflict::First).unwrap();
+    assert_eq!((summary.docs, summary.duplicates), (3, 1));
+    let titles: Vec<_> = dump(&out).into_iter().map(|(row, _, title, _)| (row, title)).collect();
+    assert_eq!(titles, [(0, "a1".into()), (1, "b1".into()), (2, "c2".into())]);
+
+    let summary = merge_indexes(&inputs, &out, OnConflict::Last).unwrap();
+    assert_eq!((summary.docs, summary.duplicates), (3, 1));
+    let docs = dump(&out);
+    let titles: Vec<_> = docs.iter().map(|(row, _, title, _)| (*row, title.as_str())).collect();
+    assert_eq!(titles, [(0, "b1"), (1, "c2"), (2, "a2")]);
+    assert_eq!(docs[2].3, [0.8, 0.6]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn a_mapped_row_without_a_doc_never_wins() {
+    let dir = temp_dir("orphan");
+    let inputs = [build(&dir, "one", ONE, None), build(&dir, "two", TWO, None)];
+    let conn = Connection::open(inputs[1].join("doc_meta.sqlite")).unwrap();
+    conn.execute("DELETE FROM doc WHERE doc_id = 'a'", []).unwrap();
+    drop(conn);
+
+    let out = dir.join("out");
+    let summary = merge_indexes(&inputs, &out, OnConflict::Last).unwrap();
+    assert_eq!((summary.docs, summary.duplicates), (3, 0));
+    let titles: Vec<_> = dump(&out).into_iter().map(|(_, _, title, _)| title).collect();
+    assert_eq!(titles, ["a1", "b1", "c2"]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn merge_waits_for_the_writer_of_the_output() {
+    let dir = temp_dir("lock");
+    let inputs = [build(&dir, "one", ONE, None)];
+    let out = dir.join("out");
+    fs::create_dir_all(&out).unwrap();
+
+    let writer = WriteLock::acquire(&out).unwrap();
+    let err = merge_indexes(&inputs, &out, OnConflict::Error).err().unwrap();
+    assert!(err.to_string().contains("already open for writing"), "{err}");
+    drop(writer);
+    merge_indexes(&inputs, &out, OnConflict::Error).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
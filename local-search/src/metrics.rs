@@ -0,0 +1,84 @@
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::Duration,
+};
+
+use serde::Serialize;
+
+/// How many recent samples percentiles are computed over.
+const WINDOW: usize = 4096;
+
+/// Rolling latency recorder. Keeps the most recent `WINDOW` samples so
+/// percentiles reflect current load rather than the whole process lifetime.
+pub struct LatencyRecorder {
+  samples_us: Mutex<VecDeque<u64>>,
+  count: AtomicU64,
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencySummary {
+  pub count: u64,
+  pub p50_ms: f64,
+  pub p95_ms: f64,
+  pub p99_ms: f64,
+  pub max_ms: f64,
+}
+
+impl LatencyRecorder {
+  pub fn new() -> Self {
+    Self {
+      samples_us: Mutex::new(VecDeque::with_capacity(WINDOW)),
+      count: AtomicU64::new(0),
+    }
+  }
+
+  pub fn record(&self, elapsed: Duration) {
+    self.count.fetch_add(1, Ordering::Relaxed);
+    let Ok(mut samples) = self.samples_us.lock() else {
+      return;
+    };
+    if samples.len() == WINDOW {
+      samples.pop_front();
+    }
+    samples.push_back(elapsed.as_micros() as u64);
+  }
+
+  /// Drops every sample (e.g. between `bench` runs).
+  pub fn reset(&self) {
+    self.count.store(0, Ordering::Relaxed);
+    if let Ok(mut samples) = self.samples_us.lock() {
+      samples.clear();
+    }
+  }
+
+  pub fn summary(&self) -> LatencySummary {
+    let mut sorted: Vec<u64> = match self.samples_us.lock() {
+      Ok(samples) => samples.iter().copied().collect(),
+      Err(_) => Vec::new(),
+    };
+    sorted.sort_unstable();
+
+    LatencySummary {
+      count: self.count.load(Ordering::Relaxed),
+      p50_ms: percentile_ms(&sorted, 0.50),
+      p95_ms: percentile_ms(&sorted, 0.95),
+      p99_ms: percentile_ms(&sorted, 0.99),
+      max_ms: sorted.last().map(|&us| us as f64 / 1000.0).unwrap_or(0.0),
+    }
+  }
+}
+
+/// Nearest-rank percentile over already sorted microsecond samples.
+fn percentile_ms(sorted_us: &[u64], p: f64) -> f64 {
+  if sorted_us.is_empty() {
+    return 0.0;
+  }
+  let rank = (p * sorted_us.len() as f64).ceil() as usize;
+  let idx = rank.clamp(1, sorted_us.len()) - 1;
+  sorted_us[idx] as f64 / 1000.0
+}
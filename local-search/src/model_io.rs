@@ -0,0 +1,199 @@
+use ort::{
+  session::Session,
+  tensor::TensorElementType,
+  value::{DynValue, Outlet, Tensor},
+};
+
+use crate::embedder::ModelSpec;
+
+/// Output names that already hold one pooled vector per text.
+const POOLED_OUTPUTS: &[&str] = &[
+  "sentence_embedding",
+  "sentence_embeddings",
+  "embeddings",
+  "dense_vecs",
+  "text_embeds",
+];
+
+/// Output names that hold per-token hidden states we pool ourselves.
+const HIDDEN_OUTPUTS: &[&str] = &["last_hidden_state", "token_embeddings", "hidden_states"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedSource {
+  InputIds,
+  AttentionMask,
+  TokenTypeIds,
+  PositionIds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdType {
+  I64,
+  I32,
+}
+
+#[derive(Debug)]
+struct Feed {
+  name: String,
+  source: FeedSource,
+  ty: IdType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+  /// `[batch, dims]`
+  Pooled,
+  /// `[batch, seq, dims]`
+  Hidden,
+}
+
+/// Which session inputs get fed and which output is read, detected from the
+/// model's own metadata at load time instead of assuming the bge-m3 export.
+#[derive(Debug)]
+pub struct ModelIo {
+  feeds: Vec<Feed>,
+  pub output: String,
+  pub output_kind: OutputKind,
+}
+
+impl ModelIo {
+  pub fn detect(session: &Session, spec: &ModelSpec) -> anyhow::Result<Self> {
+    let mut feeds = Vec::with_capacity(session.inputs().len());
+    for input in session.inputs() {
+      let source = feed_source(input.name()).ok_or_else(|| {
+        anyhow::anyhow!(
+          "model input `{}` is not supported (inputs: {}; supported: input_ids, attention_mask, token_type_ids, position_ids)",
+          input.name(),
+          outlet_list(session.inputs())
+        )
+      })?;
+      let ty = match input.dtype().tensor_type() {
+        Some(TensorElementType::Int64) => IdType::I64,
+        Some(TensorElementType::Int32) => IdType::I32,
+        other => anyhow::bail!(
+          "model input `{}` has type {other:?}; only int64/int32 token inputs are supported",
+          input.name()
+        ),
+      };
+      feeds.push(Feed {
+        name: input.name().to_string(),
+        source,
+        ty,
+      });
+    }
+    if !feeds.iter().any(|f| f.source == FeedSource::InputIds) {
+      anyhow::bail!(
+        "model has no input_ids input (inputs: {})",
+        outlet_list(session.inputs())
+      );
+    }
+
+    let outputs = session.outputs();
+    let output = match &spec.output_name {
+      Some(name) => outputs.iter().find(|o| o.name() == name).ok_or_else(|| {
+        anyhow::anyhow!(
+          "model.json outputName `{name}` not found (outputs: {})",
+          outlet_list(outputs)
+        )
+      })?,
+      None => pick_output(outputs).ok_or_else(|| {
+        anyhow::anyhow!(
+          "no float output shaped [batch, dims] or [batch, seq, dims] (outputs: {})",
+          outlet_list(outputs)
+        )
+      })?,
+    };
+
+    if output.dtype().tensor_type() != Some(TensorElementType::Float32) {
+      anyhow::bail!("model output `{}` is not float32", output.name());
+    }
+    let shape = output
+      .dtype()
+      .tensor_shape()
+      .ok_or_else(|| anyhow::anyhow!("model output `{}` is not a tensor", output.name()))?;
+    let output_kind = match shape.len() {
+      2 => OutputKind::Pooled,
+      3 => OutputKind::Hidden,
+      n => anyhow::bail!("model output `{}` has rank {n}; expected 2 or 3", output.name()),
+    };
+    let last = shape[shape.len() - 1];
+    if last > 0 && last as usize != spec.dims {
+      anyhow::bail!(
+        "model output `{}` has {last} dims but model.json says {}",
+        output.name(),
+        spec.dims
+      );
+    }
+
+    Ok(Self {
+      feeds,
+      output: output.name().to_string(),
+      output_kind,
+    })
+  }
+
+  pub fn feed_names(&self) -> Vec<&str> {
+    self.feeds.iter().map(|f| f.name.as_str()).collect()
+  }
+
+  /// Builds the session inputs for a padded `[batch, seq]` batch.
+  pub fn feeds(
+    &self,
+    batch: usize,
+    seq: usize,
+    ids: &[i64],
+    mask: &[i64],
+    type_ids: &[i64],
+  ) -> anyhow::Result<Vec<(String, DynValue)>> {
+    let mut out = Vec::with_capacity(self.feeds.len());
+    for feed in &self.feeds {
+      let values: Vec<i64> = match feed.source {
+        FeedSource::InputIds => ids.to_vec(),
+        FeedSource::AttentionMask => mask.to_vec(),
+        FeedSource::TokenTypeIds => type_ids.to_vec(),
+        FeedSource::PositionIds => (0..batch).flat_map(|_| 0..seq as i64).collect(),
+      };
+      let value = match feed.ty {
+        IdType::I64 => Tensor::from_array(([batch, seq], values))?.into_dyn(),
+        IdType::I32 => {
+          let values: Vec<i32> = values.into_iter().map(|v| v as i32).collect();
+          Tensor::from_array(([batch, seq], values))?.into_dyn()
+        }
+      };
+      out.push((feed.name.clone(), value));
+    }
+    Ok(out)
+  }
+}
+
+fn feed_source(name: &str) -> Option<FeedSource> {
+  match name {
+    "input_ids" | "ids" | "input" => Some(FeedSource::InputIds),
+    "attention_mask" | "mask" | "input_mask" => Some(FeedSource::AttentionMask),
+    "token_type_ids" | "segment_ids" => Some(FeedSource::TokenTypeIds),
+    "position_ids" => Some(FeedSource::PositionIds),
+    _ => None,
+  }
+}
+
+/// Known pooled names first, then known hidden-state names, then the first
+/// float output of rank 2 or 3.
+fn pick_output(outputs: &[Outlet]) -> Option<&Outlet> {
+  let by_name = |names: &[&str]| outputs.iter().find(|o| names.contains(&o.name()));
+  by_name(POOLED_OUTPUTS)
+    .or_else(|| by_name(HIDDEN_OUTPUTS))
+    .or_else(|| {
+      outputs.iter().find(|o| {
+        o.dtype().tensor_type() == Some(TensorElementType::Float32)
+          && matches!(o.dtype().tensor_shape().map(|s| s.len()), Some(2 | 3))
+      })
+    })
+}
+
+fn outlet_list(outlets: &[Outlet]) -> String {
+  outlets
+    .iter()
+    .map(|o| o.name().to_string())
+    .collect::<Vec<_>>()
+    .join(", ")
+}
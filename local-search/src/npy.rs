@@ -0,0 +1,251 @@
+use std::{
+  fs::File,
+  io::{BufReader, BufWriter, Read, Write},
+  path::Path,
+};
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Streams a 2-D little-endian `f32` array (`np.load` gives `[rows, dims]`).
+/// The row count goes into the header, so it must be known up front.
+pub struct NpyWriter {
+  out: BufWriter<File>,
+  dims: usize,
+  rows: usize,
+  written: usize,
+}
+
+impl NpyWriter {
+  pub fn create(path: &Path, rows: usize, dims: usize) -> anyhow::Result<Self> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({rows}, {dims}), }}");
+    // Version 1.0: magic + version + u16 length, header padded so data starts 64-byte aligned.
+    let unpadded = NPY_MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+    out.write_all(NPY_MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    Ok(Self {
+      out,
+      dims,
+      rows,
+      written: 0,
+    })
+  }
+
+  pub fn push(&mut self, v: &[f32]) -> anyhow::Result<()> {
+    if v.len() != self.dims {
+      anyhow::bail!("vector has {} dims, expected {}", v.len(), self.dims);
+    }
+    if self.written == self.rows {
+      anyhow::bail!("more than the {} rows declared in the header", self.rows);
+    }
+    self.out.write_all(bytemuck::cast_slice(v))?;
+    self.written += 1;
+    Ok(())
+  }
+
+  pub fn finish(mut self) -> anyhow::Result<()> {
+    if self.written != self.rows {
+      anyhow::bail!("wrote {} rows, header declares {}", self.written, self.rows);
+    }
+    self.out.flush()?;
+    Ok(())
+  }
+}
+
+/// Reads a 2-D C-order `<f4` (or `<f8`, converted) array row by row.
+pub struct NpyReader {
+  input: BufReader<File>,
+  pub rows: usize,
+  pub dims: usize,
+  f64: bool,
+  read: usize,
+}
+
+impl NpyReader {
+  pub fn open(path: &Path) -> anyhow::Result<Self> {
+    let err = |msg: &str| anyhow::anyhow!("{}: {msg}", path.display());
+    let mut input = BufReader::new(File::open(path)?);
+    let mut prefix = [0u8; 8];
+    input.read_exact(&mut prefix).map_err(|_| err("not a .npy file"))?;
+    if &prefix[..6] != NPY_MAGIC {
+      return Err(err("not a .npy file"));
+    }
+    let header_len = if prefix[6] == 1 {
+      let mut len = [0u8; 2];
+      input.read_exact(&mut len)?;
+      u16::from_le_bytes(len) as usize
+    } else {
+      let mut len = [0u8; 4];
+      input.read_exact(&mut len)?;
+      u32::from_le_bytes(len) as usize
+    };
+    let mut header = vec![0u8; header_len];
+    input.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let f64 = match header_value(&header, "descr") {
+      Some("'<f4'") => false,
+      Some("'<f8'") => true,
+      other => return Err(err(&format!("unsupported dtype {}, expected <f4 or <f8", other.unwrap_or("?")))),
+    };
+    if header_value(&header, "fortran_order") != Some("False") {
+      return Err(err("fortran-order arrays are not supported"));
+    }
+    let shape: Vec<usize> = header_value(&header, "shape")
+      .map(|s| s.trim_matches(|c| c == '(' || c == ')'))
+      .unwrap_or_default()
+      .split(',')
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+      .map(str::parse)
+      .collect::<Result<_, _>>()
+      .map_err(|_| err("invalid shape"))?;
+    let [rows, dims] = shape[..] else {
+      return Err(err(&format!("expected a 2-D array, got shape {shape:?}")));
+    };
+    Ok(Self {
+      input,
+      rows,
+      dims,
+      f64,
+      read: 0,
+    })
+  }
+
+  pub fn next_row(&mut self) -> anyhow::Result<Option<Vec<f32>>> {
+    if self.read == self.rows {
+      return Ok(None);
+    }
+    let width = if self.f64 { 8 } else { 4 };
+    let mut bytes = vec![0u8; self.dims * width];
+    self.input.read_exact(&mut bytes)?;
+    self.read += 1;
+    let row = if self.f64 {
+      bytes
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes(b.try_into().expect("8-byte chunk")) as f32)
+        .collect()
+    } else {
+      bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().expect("4-byte chunk")))
+        .collect()
+    };
+    Ok(Some(row))
+  }
+}
+
+/// Raw value of `'key': value` in a numpy header dict (tuples kept whole).
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+  let start = header.find(&format!("'{key}'"))? + key.len() + 2;
+  let rest = header[start..].trim_start().strip_prefix(':')?.trim_start();
+  let end = if rest.starts_with('(') {
+    rest.find(')')? + 1
+  } else {
+    rest.find([',', '}'])?
+  };
+  Some(rest[..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs, path::PathBuf};
+
+  use super::*;
+
+  fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("local-search-{name}-{}.npy", std::process::id()))
+  }
+
+  /// A `.npy` file with a hand-written header dict (`version` 1 or 2).
+  fn write_npy(path: &Path, version: u8, dict: &str, data: &[u8]) {
+    let mut bytes = NPY_MAGIC.to_vec();
+    bytes.extend([version, 0]);
+    let header = format!("{dict}\n");
+    if version == 1 {
+      bytes.extend((header.len() as u16).to_le_bytes());
+    } else {
+      bytes.extend((header.len() as u32).to_le_bytes());
+    }
+    bytes.extend(header.as_bytes());
+    bytes.extend(data);
+    fs::write(path, bytes).unwrap();
+  }
+
+  fn read_all(path: &Path) -> anyhow::Result<(usize, usize, Vec<Vec<f32>>)> {
+    let mut reader = NpyReader::open(path)?;
+    let mut rows = Vec::new();
+    while let Some(row) = reader.next_row()? {
+      rows.push(row);
+    }
+    Ok((reader.rows, reader.dims, rows))
+  }
+
+  #[test]
+  fn writer_output_reads_back_as_v1() {
+    let path = temp_file("npy-v1");
+    let mut writer = NpyWriter::create(&path, 2, 3).unwrap();
+    writer.push(&[1.0, 2.0, 3.0]).unwrap();
+    writer.push(&[4.0, 5.0, 6.0]).unwrap();
+    assert!(writer.push(&[7.0, 8.0, 9.0]).is_err());
+    writer.finish().unwrap();
+
+    let bytes = fs::read(&path).unwrap();
+    assert_eq!(bytes[6], 1);
+    let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + header_len) % 64, 0);
+    let (rows, dims, data) = read_all(&path).unwrap();
+    assert_eq!((rows, dims), (2, 3));
+    assert_eq!(data, [vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn reads_v2_headers_and_f8_data() {
+    let path = temp_file("npy-v2");
+    let data: Vec<u8> = [0.5f64, -1.0, 2.0, 0.25].iter().flat_map(|x| x.to_le_bytes()).collect();
+    write_npy(&path, 2, "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 2), }", &data);
+    let (rows, dims, data) = read_all(&path).unwrap();
+    assert_eq!((rows, dims), (2, 2));
+    assert_eq!(data, [vec![0.5, -1.0], vec![2.0, 0.25]]);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn shape_with_or_without_trailing_comma() {
+    let path = temp_file("npy-shape");
+    let data: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+    for shape in ["(1, 2)", "(1, 2,)", "(1,2)"] {
+      write_npy(
+        &path,
+        1,
+        &format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {shape}, }}"),
+        &data,
+      );
+      let (rows, dims, data) = read_all(&path).unwrap();
+      assert_eq!((rows, dims, data), (1, 2, vec![vec![1.0, 2.0]]), "{shape}");
+    }
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn rejects_1d_and_fortran_order_arrays() {
+    let path = temp_file("npy-reject");
+    let data = [0u8; 8];
+    write_npy(&path, 1, "{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }", &data);
+    let err = NpyReader::open(&path).err().unwrap();
+    assert!(err.to_string().contains("expected a 2-D array"), "{err}");
+
+    write_npy(&path, 1, "{'descr': '<f4', 'fortran_order': True, 'shape': (1, 2), }", &data);
+    let err = NpyReader::open(&path).err().unwrap();
+    assert!(err.to_string().contains("fortran-order"), "{err}");
+
+    write_npy(&path, 1, "{'descr': '<i4', 'fortran_order': False, 'shape': (1, 2), }", &data);
+    assert!(NpyReader::open(&path).is_err());
+    fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,149 @@
+use std::{fs, future::Future, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio_postgres::{types::Type, NoTls, Row};
+use tracing::warn;
+
+/// Written into the index directory after every successful `sync`.
+pub const SYNC_STATE_FILE: &str = "sync.json";
+
+/// Result column holding the change cursor; stripped from the docs.
+pub const WATERMARK_COLUMN: &str = "watermark";
+
+/// Maps the web app's `manga` table (plus tags) to corpus docs. `$1` is the
+/// previous watermark as text (NULL on the first sync).
+///
+/// Insert-only: `manga` has no update timestamp, so a row is picked up once,
+/// by `created_at`, and later edits or tag changes are not. Rows without a
+/// `created_at` sort first as `-infinity`, so only a sync from the start
+/// (no watermark) sees them.
+pub const DEFAULT_SYNC_QUERY: &str = r#"SELECT
+  'manga:' || m.id AS "docId",
+  m.id::bigint AS "mangaId",
+  m.title,
+  concat_ws(E'\n', m.title, m.description, array_to_string(m.lines, E'\n')) AS text,
+  m.description,
+  m.lines,
+  (SELECT string_agg(t.value, ', ' ORDER BY t.value)
+     FROM manga_tag mt JOIN tag t ON t.id = mt."tagId"
+    WHERE mt."mangaId" = m.id) AS tags,
+  COALESCE(m.created_at, '-infinity')::text AS watermark
+FROM manga m
+WHERE $1::text IS NULL OR COALESCE(m.created_at, '-infinity') > $1::text::timestamptz
+ORDER BY COALESCE(m.created_at, '-infinity'), m.id"#;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncState {
+  /// `watermark` of the last row applied.
+  pub watermark: Option<String>,
+  /// Docs upserted by all syncs so far.
+  #[serde(default)]
+  pub docs: u64,
+}
+
+impl SyncState {
+  pub fn load(index_dir: &Path) -> anyhow::Result<Self> {
+    let path = index_dir.join(SYNC_STATE_FILE);
+    if !path.exists() {
+      return Ok(Self::default());
+    }
+    serde_json::from_str(&fs::read_to_string(&path)?).map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))
+  }
+
+  pub fn save(&self, index_dir: &Path) -> anyhow::Result<()> {
+    fs::write(index_dir.join(SYNC_STATE_FILE), serde_json::to_string_pretty(self)?)?;
+    Ok(())
+  }
+}
+
+/// Streams the rows of `query` changed since `since`, `page` at a time, into
+/// `apply` as corpus-shaped docs. Returns the last watermark seen and the
+/// number of docs applied.
+pub async fn pull_changes<F, Fut>(
+  url: &str,
+  query: &str,
+  since: Option<&str>,
+  page: usize,
+  mut apply: F,
+) -> anyhow::Result<(Option<String>, usize)>
+where
+  F: FnMut(Vec<Map<String, Value>>) -> Fut,
+  Fut: Future<Output = anyhow::Result<()>>,
+{
+  let (mut client, connection) = tokio_postgres::connect(url, NoTls)
+    .await
+    .map_err(|e| anyhow::anyhow!("failed to connect to Postgres: {e}"))?;
+  tokio::spawn(async move {
+    if let Err(e) = connection.await {
+      warn!(error = %e, "postgres connection closed");
+    }
+  });
+
+  // A portal pages through one snapshot without holding every row in memory.
+  let tx = client.transaction().await?;
+  let stmt = tx.prepare(query).await?;
+  match stmt.params() {
+    [] => {}
+    [t] if *t == Type::TEXT || *t == Type::VARCHAR => {}
+    params => anyhow::bail!("the sync query must take at most one parameter, `$1::text` (got {params:?})"),
+  }
+  if !stmt.columns().iter().any(|c| c.name() == WATERMARK_COLUMN) {
+    anyhow::bail!("the sync query must return a `{WATERMARK_COLUMN}` column");
+  }
+  let portal = if stmt.params().is_empty() {
+    tx.bind(&stmt, &[]).await?
+  } else {
+    tx.bind(&stmt, &[&since]).await?
+  };
+
+  let mut watermark = since.map(str::to_string);
+  let mut applied = 0;
+  loop {
+    let rows = tx.query_portal(&portal, page.max(1) as i32).await?;
+    if rows.is_empty() {
+      break;
+    }
+    let mut docs = Vec::with_capacity(rows.len());
+    for row in &rows {
+      let mut doc = row_to_doc(row)?;
+      match doc.remove(WATERMARK_COLUMN) {
+        Some(Value::String(w)) => watermark = Some(w),
+        Some(w) => watermark = Some(w.to_string()),
+        None => {}
+      }
+      docs.push(doc);
+    }
+    applied += docs.len();
+    apply(docs).await?;
+  }
+  tx.commit().await?;
+  Ok((watermark, applied))
+}
+
+/// One row as a JSON object keyed by column name (NULLs are left out).
+fn row_to_doc(row: &Row) -> anyhow::Result<Map<String, Value>> {
+  let mut doc = Map::new();
+  for (i, column) in row.columns().iter().enumerate() {
+    let name = column.name();
+    let t = column.type_();
+    let value = match *t {
+      Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => row.try_get::<_, Option<String>>(i)?.map(Value::from),
+      Type::INT2 => row.try_get::<_, Option<i16>>(i)?.map(Value::from),
+      Type::INT4 => row.try_get::<_, Option<i32>>(i)?.map(Value::from),
+      Type::INT8 => row.try_get::<_, Option<i64>>(i)?.map(Value::from),
+      Type::FLOAT4 => row.try_get::<_, Option<f32>>(i)?.map(Value::from),
+      Type::FLOAT8 => row.try_get::<_, Option<f64>>(i)?.map(Value::from),
+      Type::BOOL => row.try_get::<_, Option<bool>>(i)?.map(Value::from),
+      Type::TEXT_ARRAY | Type::VARCHAR_ARRAY => row
+        .try_get::<_, Option<Vec<Option<String>>>>(i)?
+        .map(|items| Value::from(items.into_iter().flatten().collect::<Vec<_>>())),
+      _ => anyhow::bail!("column `{name}` has type {t}; cast it in the query (e.g. `{name}::text`)"),
+    };
+    if let Some(value) = value {
+      doc.insert(name.to_string(), value);
+    }
+  }
+  Ok(doc)
+}
@@ -0,0 +1,300 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs::File,
+  io::{BufRead, BufReader, BufWriter, Write},
+  path::Path,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::embedder::{ModelSpec, Pooling};
+
+/// LRU cache of normalized query text -> unit query vector.
+///
+/// Entries are keyed by `normalize_query`, so queries that only differ in
+/// surrounding/repeated whitespace share one forward pass.
+pub struct QueryCache {
+  capacity: usize,
+  inner: Mutex<Lru>,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Lru {
+  /// key -> (last-used tick, vector)
+  entries: HashMap<String, (u64, Arc<Vec<f32>>)>,
+  /// last-used tick -> key, oldest first
+  order: BTreeMap<u64, String>,
+  tick: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCacheStats {
+  pub capacity: usize,
+  pub entries: usize,
+  pub hits: u64,
+  pub misses: u64,
+  pub hit_rate: f64,
+}
+
+/// Identifies what produced the cached vectors; a persisted cache is only
+/// reused when this matches the running server.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheFingerprint {
+  pub model: String,
+  pub dims: usize,
+  pub max_length: usize,
+  pub pooling: Pooling,
+  pub query_prefix: String,
+  /// Size and mtime (unix nanos) of the onnx file, so swapping the weights
+  /// under the same `model.json` invalidates the cache.
+  pub model_size: u64,
+  pub model_mtime: u128,
+}
+
+impl CacheFingerprint {
+  pub fn new(spec: &ModelSpec, model_dir: &Path, max_length: usize) -> anyhow::Result<Self> {
+    let meta = std::fs::metadata(model_dir.join(&spec.model_file))?;
+    let model_mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    Ok(Self {
+      model: spec.id.clone(),
+      dims: spec.dims,
+      max_length,
+      pooling: spec.pooling,
+      query_prefix: spec.query_prefix.clone(),
+      model_size: meta.len(),
+      model_mtime,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedEntry {
+  q: String,
+  v: Vec<f32>,
+}
+
+/// Trims and collapses whitespace runs to a single space.
+pub fn normalize_query(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl QueryCache {
+  /// `capacity` of 0 disables caching (every lookup is a miss, nothing is stored).
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      inner: Mutex::new(Lru::default()),
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    }
+  }
+
+  pub fn get(&self, key: &str) -> Option<Arc<Vec<f32>>> {
+    let found = self.inner.lock().ok().and_then(|mut lru| lru.touch(key));
+    match found {
+      Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+      None => self.misses.fetch_add(1, Ordering::Relaxed),
+    };
+    found
+  }
+
+  pub fn insert(&self, key: String, vector: Arc<Vec<f32>>) {
+    if self.capacity == 0 {
+      return;
+    }
+    if let Ok(mut lru) = self.inner.lock() {
+      lru.insert(key, vector, self.capacity);
+    }
+  }
+
+  pub fn stats(&self) -> QueryCacheStats {
+    let hits = self.hits.load(Ordering::Relaxed);
+    let misses = self.misses.load(Ordering::Relaxed);
+    let lookups = hits + misses;
+    QueryCacheStats {
+      capacity: self.capacity,
+      entries: self.inner.lock().map(|lru| lru.entries.len()).unwrap_or(0),
+      hits,
+      misses,
+      hit_rate: if lookups == 0 {
+        0.0
+      } else {
+        hits as f64 / lookups as f64
+      },
+    }
+  }
+
+  /// Loads entries saved by `save`. Returns how many were loaded; a missing
+  /// file or a fingerprint mismatch loads nothing.
+  pub fn load(&self, path: &Path, fingerprint: &CacheFingerprint) -> anyhow::Result<usize> {
+    if self.capacity == 0 || !path.exists() {
+      return Ok(0);
+    }
+
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let Some(header) = lines.next().transpose()? else {
+      return Ok(0);
+    };
+    let saved: CacheFingerprint = serde_json::from_str(&header)?;
+    if &saved != fingerprint {
+      return Ok(0);
+    }
+
+    let Ok(mut lru) = self.inner.lock() else {
+      anyhow::bail!("query cache lock poisoned");
+    };
+    // Saved oldest first, so inserting in order restores recency.
+    let mut loaded = 0;
+    for line in lines {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let entry: PersistedEntry = serde_json::from_str(&line)?;
+      if entry.v.len() != fingerprint.dims {
+        continue;
+      }
+      lru.insert(entry.q, Arc::new(entry.v), self.capacity);
+      loaded += 1;
+    }
+    Ok(loaded.min(self.capacity))
+  }
+
+  /// Writes all entries as JSONL (fingerprint header, then oldest -> newest).
+  pub fn save(&self, path: &Path, fingerprint: &CacheFingerprint) -> anyhow::Result<usize> {
+    let Ok(lru) = self.inner.lock() else {
+      anyhow::bail!("query cache lock poisoned");
+    };
+
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut w, fingerprint)?;
+    w.write_all(b"\n")?;
+    for key in lru.order.values() {
+      let Some((_, v)) = lru.entries.get(key) else {
+        continue;
+      };
+      serde_json::to_writer(
+        &mut w,
+        &PersistedEntry {
+          q: key.clone(),
+          v: v.as_ref().clone(),
+        },
+      )?;
+      w.write_all(b"\n")?;
+    }
+    w.flush()?;
+    drop(w);
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(lru.entries.len())
+  }
+}
+
+impl Lru {
+  fn touch(&mut self, key: &str) -> Option<Arc<Vec<f32>>> {
+    self.tick += 1;
+    let tick = self.tick;
+    let (last_used, v) = self.entries.get_mut(key)?;
+    self.order.remove(last_used);
+    *last_used = tick;
+    self.order.insert(tick, key.to_string());
+    Some(v.clone())
+  }
+
+  fn insert(&mut self, key: String, vector: Arc<Vec<f32>>, capacity: usize) {
+    self.tick += 1;
+    let tick = self.tick;
+    if let Some((last_used, _)) = self.entries.insert(key.clone(), (tick, vector)) {
+      self.order.remove(&last_used);
+    }
+    self.order.insert(tick, key);
+
+    while self.entries.len() > capacity {
+      let Some((_, oldest)) = self.order.pop_first() else {
+        break;
+      };
+      self.entries.remove(&oldest);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fingerprint(max_length: usize) -> CacheFingerprint {
+    CacheFingerprint {
+      model: "test".to_string(),
+      dims: 2,
+      max_length,
+      pooling: Pooling::Cls,
+      query_prefix: String::new(),
+      model_size: 1,
+      model_mtime: 1,
+    }
+  }
+
+  fn vector(x: f32) -> Arc<Vec<f32>> {
+    Arc::new(vec![x, 0.0])
+  }
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let cache = QueryCache::new(2);
+    cache.insert("a".to_string(), vector(1.0));
+    cache.insert("b".to_string(), vector(2.0));
+    assert!(cache.get("a").is_some());
+    cache.insert("c".to_string(), vector(3.0));
+
+    assert!(cache.get("b").is_none());
+    assert_eq!(cache.get("a").unwrap()[0], 1.0);
+    assert_eq!(cache.get("c").unwrap()[0], 3.0);
+    let stats = cache.stats();
+    assert_eq!((stats.entries, stats.hits, stats.misses), (2, 3, 1));
+  }
+
+  #[test]
+  fn save_and_load_round_trip() {
+    let dir = std::env::temp_dir().join(format!("local-search-query-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("query_cache.jsonl");
+
+    let cache = QueryCache::new(3);
+    cache.insert("a".to_string(), vector(1.0));
+    cache.insert("b".to_string(), vector(2.0));
+    cache.insert("c".to_string(), vector(3.0));
+    cache.get("a");
+    assert_eq!(cache.save(&path, &fingerprint(64)).unwrap(), 3);
+
+    // Recency survives the round trip: `b` is now the oldest and goes first.
+    let loaded = QueryCache::new(2);
+    assert_eq!(loaded.load(&path, &fingerprint(64)).unwrap(), 2);
+    assert!(loaded.get("b").is_none());
+    assert_eq!(loaded.get("a").unwrap()[0], 1.0);
+    assert_eq!(loaded.get("c").unwrap()[0], 3.0);
+
+    let stale = QueryCache::new(3);
+    assert_eq!(stale.load(&path, &fingerprint(128)).unwrap(), 0);
+    assert_eq!(stale.stats().entries, 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn normalizes_whitespace() {
+    assert_eq!(normalize_query("  one   piece \n"), "one piece");
+  }
+}
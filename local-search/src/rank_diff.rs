@@ -0,0 +1,317 @@
+use serde::Serialize;
+
+/// One ranked hit as compared by `diff-rankings`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankedDoc {
+  pub doc_id: String,
+  pub title: String,
+  pub score: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankChange {
+  pub doc_id: String,
+  pub title: String,
+  /// 1-based rank in the baseline (`left`) or candidate (`entered`) list.
+  pub rank: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankMove {
+  pub doc_id: String,
+  pub from: usize,
+  pub to: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryDiff {
+  pub query_id: String,
+  pub query: String,
+  /// `|baseline ∩ candidate| / max(|baseline|, |candidate|)` (1 when both
+  /// are empty), so short result lists aren't scored as disagreement.
+  pub overlap: f64,
+  /// Rank-biased overlap (extrapolated), 1 = identical order.
+  pub rbo: f64,
+  /// In the candidate top k but not the baseline's.
+  pub entered: Vec<RankChange>,
+  /// In the baseline top k but not the candidate's.
+  pub left: Vec<RankChange>,
+  /// Docs in both lists at different ranks.
+  pub moved: Vec<RankMove>,
+  pub baseline: Vec<RankedDoc>,
+  pub candidate: Vec<RankedDoc>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+impl QueryDiff {
+  pub fn new(query_id: String, query: String, baseline: Vec<RankedDoc>, candidate: Vec<RankedDoc>, p: f64) -> Self {
+    let base_ids: Vec<&str> = baseline.iter().map(|d| d.doc_id.as_str()).collect();
+    let cand_ids: Vec<&str> = candidate.iter().map(|d| d.doc_id.as_str()).collect();
+    let shared = base_ids.iter().filter(|id| cand_ids.contains(id)).count();
+
+    let changes = |from: &[RankedDoc], other: &[&str]| -> Vec<RankChange> {
+      from
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| !other.contains(&d.doc_id.as_str()))
+        .map(|(i, d)| RankChange {
+          doc_id: d.doc_id.clone(),
+          title: d.title.clone(),
+          rank: i + 1,
+        })
+        .collect()
+    };
+    let moved = base_ids
+      .iter()
+      .enumerate()
+      .filter_map(|(from, id)| {
+        let to = cand_ids.iter().position(|c| c == id)?;
+        (from != to).then(|| RankMove {
+          doc_id: id.to_string(),
+          from: from + 1,
+          to: to + 1,
+        })
+      })
+      .collect();
+
+    Self {
+      query_id,
+      query,
+      overlap: match baseline.len().max(candidate.len()) {
+        0 => 1.0,
+        len => shared as f64 / len as f64,
+      },
+      rbo: rbo(&base_ids, &cand_ids, p),
+      entered: changes(&candidate, &base_ids),
+      left: changes(&baseline, &cand_ids),
+      moved,
+      baseline,
+      candidate,
+      error: None,
+    }
+  }
+
+  pub fn failed(query_id: String, query: String, error: String) -> Self {
+    Self {
+      query_id,
+      query,
+      overlap: 0.0,
+      rbo: 0.0,
+      entered: Vec::new(),
+      left: Vec::new(),
+      moved: Vec::new(),
+      baseline: Vec::new(),
+      candidate: Vec::new(),
+      error: Some(error),
+    }
+  }
+
+  fn changed(&self) -> bool {
+    !self.entered.is_empty() || !self.left.is_empty() || !self.moved.is_empty()
+  }
+}
+
+/// Extrapolated rank-biased overlap (Webber et al. 2010) of two rankings;
+/// `p` is the persistence (higher = deeper ranks matter more).
+pub fn rbo(a: &[&str], b: &[&str], p: f64) -> f64 {
+  let depth = a.len().max(b.len());
+  if depth == 0 {
+    return 1.0;
+  }
+  let mut sum = 0.0;
+  let mut overlap = 0usize;
+  for d in 1..=depth {
+    // Grow the overlap by what the new depth adds on either side.
+    if let Some(x) = a.get(d - 1) {
+      if b[..d.min(b.len())].contains(x) {
+        overlap += 1;
+      }
+    }
+    if let Some(y) = b.get(d - 1) {
+      if a[..(d - 1).min(a.len())].contains(y) {
+        overlap += 1;
+      }
+    }
+    sum += overlap as f64 / d as f64 * p.powi(d as i32);
+  }
+  overlap as f64 / depth as f64 * p.powi(depth as i32) + (1.0 - p) / p * sum
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+  pub baseline: String,
+  pub candidate: String,
+  pub collection: String,
+  pub k: usize,
+  pub rbo_p: f64,
+  pub queries: usize,
+  /// Queries whose top k differ in membership or order.
+  pub changed: usize,
+  pub mean_overlap: f64,
+  pub mean_rbo: f64,
+  pub per_query: Vec<QueryDiff>,
+}
+
+impl DiffReport {
+  pub fn new(baseline: String, candidate: String, collection: String, k: usize, rbo_p: f64, per_query: Vec<QueryDiff>) -> Self {
+    let scored: Vec<&QueryDiff> = per_query.iter().filter(|q| q.error.is_none()).collect();
+    let n = scored.len().max(1) as f64;
+    Self {
+      baseline,
+      candidate,
+      collection,
+      k,
+      rbo_p,
+      queries: per_query.len(),
+      changed: scored.iter().filter(|q| q.changed()).count(),
+      mean_overlap: scored.iter().map(|q| q.overlap).sum::<f64>() / n,
+      mean_rbo: scored.iter().map(|q| q.rbo).sum::<f64>() / n,
+      per_query,
+    }
+  }
+
+  /// Self-contained HTML page: a summary, then one side-by-side table per
+  /// query with entered docs marked `+` and dropped ones `−`.
+  pub fn to_html(&self) -> String {
+    let mut out = String::new();
+    out.push_str("<!doctype html>\n<meta charset=\"utf-8\">\n<title>diff-rankings</title>\n");
+    out.push_str(
+      "<style>body{font-family:sans-serif}table{border-collapse:collapse;margin-bottom:1.5em}\
+td,th{border:1px solid #ccc;padding:2px 6px}.in{background:#dfd}.out{background:#fdd}</style>\n",
+    );
+    out.push_str(&format!(
+      "<h1>{} → {}</h1>\n<p>collection {}, k={}, {} queries, {} changed, mean overlap@k {:.3}, mean RBO(p={}) {:.3}</p>\n",
+      escape(&self.baseline),
+      escape(&self.candidate),
+      escape(&self.collection),
+      self.k,
+      self.queries,
+      self.changed,
+      self.mean_overlap,
+      self.rbo_p,
+      self.mean_rbo
+    ));
+    for q in &self.per_query {
+      out.push_str(&format!(
+        "<h2>{}: {}</h2>\n",
+        escape(&q.query_id),
+        escape(&q.query)
+      ));
+      if let Some(e) = &q.error {
+        out.push_str(&format!("<p class=\"out\">error: {}</p>\n", escape(e)));
+        continue;
+      }
+      out.push_str(&format!("<p>overlap@k {:.3}, RBO {:.3}</p>\n", q.overlap, q.rbo));
+      out.push_str("<table><tr><th>#</th><th>baseline</th><th>candidate</th></tr>\n");
+      let left: Vec<&str> = q.left.iter().map(|c| c.doc_id.as_str()).collect();
+      let entered: Vec<&str> = q.entered.iter().map(|c| c.doc_id.as_str()).collect();
+      let cell = |doc: Option<&RankedDoc>, marked: &[&str], class: &str, mark: &str| match doc {
+        None => "<td></td>".to_string(),
+        Some(d) if marked.contains(&d.doc_id.as_str()) => format!(
+          "<td class=\"{class}\">{mark} {} <small>{} ({:.3})</small></td>",
+          escape(&d.title),
+          escape(&d.doc_id),
+          d.score
+        ),
+        Some(d) => format!(
+          "<td>{} <small>{} ({:.3})</small></td>",
+          escape(&d.title),
+          escape(&d.doc_id),
+          d.score
+        ),
+      };
+      for i in 0..q.baseline.len().max(q.candidate.len()) {
+        out.push_str(&format!(
+          "<tr><td>{}</td>{}{}</tr>\n",
+          i + 1,
+          cell(q.baseline.get(i), &left, "out", "−"),
+          cell(q.candidate.get(i), &entered, "in", "+")
+        ));
+      }
+      out.push_str("</table>\n");
+    }
+    out
+  }
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn docs(ids: &[&str]) -> Vec<RankedDoc> {
+    ids
+      .iter()
+      .map(|id| RankedDoc {
+        doc_id: id.to_string(),
+        title: String::new(),
+        score: 0.0,
+      })
+      .collect()
+  }
+
+  fn diff(baseline: &[&str], candidate: &[&str]) -> QueryDiff {
+    QueryDiff::new("q".into(), "query".into(), docs(baseline), docs(candidate), 0.9)
+  }
+
+  #[test]
+  fn rbo_of_identical_and_disjoint_lists() {
+    assert!((rbo(&["a", "b", "c"], &["a", "b", "c"], 0.9) - 1.0).abs() < 1e-9);
+    assert!((rbo(&[], &[], 0.9) - 1.0).abs() < 1e-9);
+    assert_eq!(rbo(&["a", "b"], &["c", "d"], 0.9), 0.0);
+    assert_eq!(rbo(&["a"], &[], 0.9), 0.0);
+  }
+
+  #[test]
+  fn rbo_of_unequal_lengths_is_partial_and_symmetric() {
+    let short = rbo(&["a", "b"], &["a"], 0.9);
+    assert!((short - 0.55).abs() < 1e-9, "{short}");
+    assert!((rbo(&["a"], &["a", "b"], 0.9) - short).abs() < 1e-9);
+    // Swapping the top two costs more than swapping the last two.
+    assert!(rbo(&["a", "b", "c"], &["b", "a", "c"], 0.9) < rbo(&["a", "b", "c"], &["a", "c", "b"], 0.9));
+  }
+
+  #[test]
+  fn query_diff_of_identical_lists() {
+    let d = diff(&["a", "b", "c"], &["a", "b", "c"]);
+    assert_eq!(d.overlap, 1.0);
+    assert!((d.rbo - 1.0).abs() < 1e-9);
+    assert!(!d.changed());
+  }
+
+  #[test]
+  fn query_diff_of_disjoint_lists() {
+    let d = diff(&["a", "b"], &["c", "d"]);
+    assert_eq!((d.overlap, d.rbo), (0.0, 0.0));
+    assert_eq!(d.entered.iter().map(|c| (c.doc_id.as_str(), c.rank)).collect::<Vec<_>>(), [("c", 1), ("d", 2)]);
+    assert_eq!(d.left.iter().map(|c| (c.doc_id.as_str(), c.rank)).collect::<Vec<_>>(), [("a", 1), ("b", 2)]);
+    assert!(d.moved.is_empty());
+  }
+
+  #[test]
+  fn query_diff_of_unequal_lengths() {
+    // Overlap divides by the longer list, not by k.
+    let d = diff(&["a", "b", "c", "d"], &["b", "a"]);
+    assert_eq!(d.overlap, 0.5);
+    assert_eq!(d.left.iter().map(|c| c.doc_id.as_str()).collect::<Vec<_>>(), ["c", "d"]);
+    assert!(d.entered.is_empty());
+    assert_eq!(
+      d.moved.iter().map(|m| (m.doc_id.as_str(), m.from, m.to)).collect::<Vec<_>>(),
+      [("a", 1, 2), ("b", 2, 1)]
+    );
+    assert_eq!(diff(&[], &[]).overlap, 1.0);
+    assert_eq!(diff(&["a"], &[]).overlap, 0.0);
+  }
+}
@@ -0,0 +1,168 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  manifest::DEFAULT_VECTOR_FIELD,
+  vector_file::{tmp_path, write_vector_file, VectorFile},
+};
+
+/// Lists the segments of an index; replacing it is the commit point of a
+/// flush or merge.
+pub const SEGMENTS_FILE: &str = "segments.json";
+
+/// Segment written by `build-index` (`vectors.f32`); holds rows `0..len`.
+pub const BASE_SEGMENT: &str = "vectors";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentsFile {
+  /// Number for the next `seg-NNNNNN` name.
+  pub next_id: u64,
+  /// WAL records below this row are already in a segment.
+  pub wal_start: u64,
+  pub segments: Vec<String>,
+}
+
+impl SegmentsFile {
+  /// Returns `None` for indexes that were never flushed (base segment only).
+  pub fn load(dir: &Path) -> anyhow::Result<Option<Self>> {
+    let path = dir.join(SEGMENTS_FILE);
+    if !path.exists() {
+      return Ok(None);
+    }
+    let file = serde_json::from_str(&fs::read_to_string(&path)?)
+      .map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))?;
+    Ok(Some(file))
+  }
+
+  pub fn save(&self, dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join(SEGMENTS_FILE);
+    write_synced(&path, serde_json::to_string_pretty(self)?.as_bytes())
+  }
+}
+
+/// Immutable run of rows: one `f32` file per column plus, except for the base
+/// segment, a `.rows` file of the (ascending) index rows they hold.
+pub struct Segment {
+  pub name: String,
+  /// `None` = rows `0..len`.
+  rows: Option<Vec<u64>>,
+  columns: Vec<VectorFile>,
+}
+
+impl Segment {
+  pub fn column_file(segment: &str, column: &str) -> String {
+    if column == DEFAULT_VECTOR_FIELD {
+      format!("{segment}.f32")
+    } else {
+      format!("{segment}.{column}.f32")
+    }
+  }
+
+  fn rows_file(segment: &str) -> String {
+    format!("{segment}.rows")
+  }
+
+  pub fn open(dir: &Path, name: &str, dims: usize, columns: &[String]) -> anyhow::Result<Self> {
+    let files = columns
+      .iter()
+      .map(|c| VectorFile::open(&dir.join(Self::column_file(name, c)), dims))
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    let len = files.first().map_or(0, VectorFile::len);
+    if let Some((c, f)) = columns.iter().zip(&files).find(|(_, f)| f.len() != len) {
+      anyhow::bail!("segment {name}: column {c} has {} rows, expected {len}", f.len());
+    }
+
+    let rows = if name == BASE_SEGMENT {
+      None
+    } else {
+      let bytes = fs::read(dir.join(Self::rows_file(name)))?;
+      let rows: Vec<u64> = bytes
+        .chunks_exact(8)
+        .map(|b| u64::from_le_bytes(b.try_into().expect("8-byte chunk")))
+        .collect();
+      if rows.len() != len || bytes.len() != len * 8 {
+        anyhow::bail!("segment {name}: rows file does not match its {len} vectors");
+      }
+      if rows.windows(2).any(|w| w[0] >= w[1]) {
+        anyhow::bail!("segment {name}: rows are not ascending");
+      }
+      Some(rows)
+    };
+
+    Ok(Self {
+      name: name.to_string(),
+      rows,
+      columns: files,
+    })
+  }
+
+  /// Writes and syncs every file of a new segment, then opens it.
+  pub fn write(
+    dir: &Path,
+    name: &str,
+    dims: usize,
+    columns: &[String],
+    rows: &[u64],
+    vectors: &[Vec<f32>],
+  ) -> anyhow::Result<Self> {
+    for (column, data) in columns.iter().zip(vectors) {
+      write_vector_file(&dir.join(Self::column_file(name, column)), dims, data)?;
+    }
+    let bytes: Vec<u8> = rows.iter().flat_map(|r| r.to_le_bytes()).collect();
+    write_synced(&dir.join(Self::rows_file(name)), &bytes)?;
+    Self::open(dir, name, dims, columns)
+  }
+
+  pub fn remove_files(dir: &Path, name: &str, columns: &[String]) -> anyhow::Result<()> {
+    let files = columns
+      .iter()
+      .map(|c| Self::column_file(name, c))
+      .chain(std::iter::once(Self::rows_file(name)));
+    for file in files {
+      let path = dir.join(file);
+      if path.exists() {
+        fs::remove_file(path)?;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn len(&self) -> usize {
+    self.columns.first().map_or(0, VectorFile::len)
+  }
+
+  /// Index row of the `i`-th vector.
+  pub fn row(&self, i: usize) -> u64 {
+    self.rows.as_ref().map_or(i as u64, |rows| rows[i])
+  }
+
+  pub fn last_row(&self) -> Option<u64> {
+    self.len().checked_sub(1).map(|i| self.row(i))
+  }
+
+  /// Position of index row `row` in this segment.
+  pub fn position(&self, row: u64) -> Option<usize> {
+    match &self.rows {
+      None => (row < self.len() as u64).then_some(row as usize),
+      Some(rows) => rows.binary_search(&row).ok(),
+    }
+  }
+
+  pub fn vector(&self, column: usize, i: usize) -> Option<&[f32]> {
+    self.columns.get(column)?.get(i)
+  }
+}
+
+/// Writes through a temp file and renames it into place after an fsync.
+fn write_synced(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+  let tmp = tmp_path(path);
+  {
+    let f = fs::File::create(&tmp)?;
+    std::io::Write::write_all(&mut &f, bytes)?;
+    f.sync_all()?;
+  }
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
@@ -0,0 +1,155 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::UNIX_EPOCH,
+};
+
+use ort::{
+  ep,
+  session::{builder::GraphOptimizationLevel, builder::SessionBuilder, Session},
+};
+use tracing::{info, warn};
+
+/// Graph optimization level, named after onnxruntime's levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OptimizationLevel {
+  Disable,
+  Basic,
+  Extended,
+  Layout,
+  All,
+}
+
+impl OptimizationLevel {
+  fn to_ort(self) -> GraphOptimizationLevel {
+    match self {
+      Self::Disable => GraphOptimizationLevel::Disable,
+      Self::Basic => GraphOptimizationLevel::Level1,
+      Self::Extended => GraphOptimizationLevel::Level2,
+      Self::Layout => GraphOptimizationLevel::Level3,
+      Self::All => GraphOptimizationLevel::All,
+    }
+  }
+
+  fn as_str(self) -> &'static str {
+    match self {
+      Self::Disable => "disable",
+      Self::Basic => "basic",
+      Self::Extended => "extended",
+      Self::Layout => "layout",
+      Self::All => "all",
+    }
+  }
+}
+
+/// How onnxruntime sessions are created.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+  /// Threads used inside one operator (`None` = onnxruntime default, all physical cores).
+  pub intra_threads: Option<usize>,
+  /// Threads used to run independent operators in parallel. Setting this
+  /// switches the session to parallel execution mode.
+  pub inter_threads: Option<usize>,
+  /// Whether the CPU arena allocator is used (faster, but holds on to peak memory).
+  pub memory_arena: bool,
+  pub optimization_level: OptimizationLevel,
+  /// Directory where the optimized graph is saved and reused on later starts.
+  pub optimized_cache_dir: Option<PathBuf>,
+}
+
+impl Default for SessionOptions {
+  fn default() -> Self {
+    Self {
+      intra_threads: None,
+      inter_threads: None,
+      memory_arena: true,
+      optimization_level: OptimizationLevel::Layout,
+      optimized_cache_dir: None,
+    }
+  }
+}
+
+/// Builds a session for `model_path`. With `optimized_cache_dir` set, the first
+/// start saves the optimized graph there and later starts load it with
+/// optimizations disabled, skipping the (slow) graph rewrite.
+pub fn build_session(model_path: &Path, opts: &SessionOptions) -> anyhow::Result<Session> {
+  let Some(cache_dir) = &opts.optimized_cache_dir else {
+    return commit(builder(opts, opts.optimization_level)?, model_path);
+  };
+  if opts.optimization_level == OptimizationLevel::Disable {
+    return commit(builder(opts, opts.optimization_level)?, model_path);
+  }
+
+  let cached_path = cache_dir.join(format!("{}.onnx", cache_key(model_path, opts.optimization_level)?));
+  if cached_path.exists() {
+    match commit(builder(opts, OptimizationLevel::Disable)?, &cached_path) {
+      Ok(session) => {
+        info!(path = %cached_path.display(), "loaded optimized model from cache");
+        return Ok(session);
+      }
+      Err(e) => {
+        warn!(error = %e, path = %cached_path.display(), "optimized model cache is unusable, rebuilding");
+        let _ = fs::remove_file(&cached_path);
+      }
+    }
+  }
+
+  fs::create_dir_all(cache_dir)?;
+  let tmp_path = cached_path.with_extension("onnx.tmp");
+  let data_name = format!(
+    "{}.data",
+    cached_path.file_stem().and_then(|s| s.to_str()).unwrap_or("optimized")
+  );
+  // Large models (bge-m3 fp32 is >2GB) can't be serialized into a single
+  // protobuf, so weights go to a sibling external data file.
+  let session = commit(
+    builder(opts, opts.optimization_level)?
+      .with_optimized_model_path(&tmp_path)?
+      .with_config_entry("session.optimized_model_external_initializers_file_name", &data_name)?
+      .with_config_entry("session.optimized_model_external_initializers_min_size_in_bytes", "1024")?,
+    model_path,
+  )?;
+
+  match fs::rename(&tmp_path, &cached_path) {
+    Ok(()) => info!(path = %cached_path.display(), "saved optimized model to cache"),
+    Err(e) => warn!(error = %e, path = %tmp_path.display(), "failed to save optimized model"),
+  }
+
+  Ok(session)
+}
+
+fn builder(opts: &SessionOptions, level: OptimizationLevel) -> anyhow::Result<SessionBuilder> {
+  let mut b = Session::builder()?
+    .with_optimization_level(level.to_ort())?
+    .with_execution_providers([ep::CPU::default().with_arena_allocator(opts.memory_arena).build()])?;
+  if let Some(n) = opts.intra_threads {
+    b = b.with_intra_threads(n)?;
+  }
+  if let Some(n) = opts.inter_threads {
+    b = b.with_parallel_execution(true)?.with_inter_threads(n)?;
+  }
+  Ok(b)
+}
+
+fn commit(builder: SessionBuilder, path: &Path) -> anyhow::Result<Session> {
+  builder
+    .commit_from_file(path)
+    .map_err(|e| anyhow::anyhow!("failed to load onnx {}: {e}", path.display()))
+}
+
+/// Cache entries are keyed by model name, size, mtime and optimization level
+/// so a re-exported model never picks up a stale optimized graph.
+fn cache_key(model_path: &Path, level: OptimizationLevel) -> anyhow::Result<String> {
+  let meta = fs::metadata(model_path)?;
+  let mtime = meta
+    .modified()
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let stem = model_path
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or("model");
+  Ok(format!("{stem}-{}-{mtime}-{}", meta.len(), level.as_str()))
+}
@@ -0,0 +1,426 @@
+use std::{
+  fs::{self, File},
+  io::{BufWriter, Seek, SeekFrom, Write},
+  path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+use serde::Serialize;
+
+/// First bytes of every vector file written since format version 1. Files
+/// without it are legacy raw `f32` dumps.
+pub const MAGIC: [u8; 8] = *b"LSVECTOR";
+pub const FORMAT_VERSION: u32 = 1;
+/// Header size; keeps the payload 64-byte aligned inside the mmap.
+pub const HEADER_LEN: usize = 64;
+
+/// Rows whose L2 norm is within this of 1 count as normalized.
+pub const NORM_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dtype {
+  /// Little-endian IEEE 754 single precision.
+  F32,
+}
+
+impl Dtype {
+  fn code(self) -> u8 {
+    match self {
+      Dtype::F32 => 1,
+    }
+  }
+
+  fn from_code(code: u8) -> Option<Self> {
+    match code {
+      1 => Some(Dtype::F32),
+      _ => None,
+    }
+  }
+}
+
+/// Little-endian layout (64 bytes):
+///
+/// | offset | size | field                               |
+/// |--------|------|-------------------------------------|
+/// | 0      | 8    | magic `LSVECTOR`                    |
+/// | 8      | 4    | format version                      |
+/// | 12     | 4    | dims                                |
+/// | 16     | 8    | count (rows)                        |
+/// | 24     | 1    | dtype (1 = f32)                     |
+/// | 25     | 1    | flags (bit 0 = every row normalized)|
+/// | 26     | 6    | reserved (0)                        |
+/// | 32     | 8    | FNV-1a 64 of the payload            |
+/// | 40     | 24   | reserved (0)                        |
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorHeader {
+  pub version: u32,
+  pub dims: usize,
+  pub count: usize,
+  pub dtype: Dtype,
+  pub normalized: bool,
+  pub checksum: u64,
+}
+
+impl VectorHeader {
+  fn encode(&self) -> [u8; HEADER_LEN] {
+    let mut out = [0u8; HEADER_LEN];
+    out[0..8].copy_from_slice(&MAGIC);
+    out[8..12].copy_from_slice(&self.version.to_le_bytes());
+    out[12..16].copy_from_slice(&(self.dims as u32).to_le_bytes());
+    out[16..24].copy_from_slice(&(self.count as u64).to_le_bytes());
+    out[24] = self.dtype.code();
+    out[25] = u8::from(self.normalized);
+    out[32..40].copy_from_slice(&self.checksum.to_le_bytes());
+    out
+  }
+
+  /// `None` when `bytes` doesn't start with the magic (a legacy raw file).
+  fn decode(bytes: &[u8]) -> anyhow::Result<Option<Self>> {
+    if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+      return Ok(None);
+    }
+    if bytes.len() < HEADER_LEN {
+      anyhow::bail!("truncated header");
+    }
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 bytes"));
+    let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().expect("8 bytes"));
+
+    let version = u32_at(8);
+    if version != FORMAT_VERSION {
+      anyhow::bail!("unsupported format version {version} (this build reads {FORMAT_VERSION})");
+    }
+    let dtype = Dtype::from_code(bytes[24]).ok_or_else(|| anyhow::anyhow!("unknown dtype code {}", bytes[24]))?;
+    Ok(Some(Self {
+      version,
+      dims: u32_at(12) as usize,
+      count: u64_at(16) as usize,
+      dtype,
+      normalized: bytes[25] & 1 != 0,
+      checksum: u64_at(32),
+    }))
+  }
+}
+
+/// One mmapped vector file (`rows * dims` floats), either with a
+/// [`VectorHeader`] or a legacy header-less dump.
+pub struct VectorFile {
+  /// Keeps the mapping alive for `vectors`.
+  _mmap: Mmap,
+  dims: usize,
+  header: Option<VectorHeader>,
+  vectors: &'static [f32],
+}
+
+impl VectorFile {
+  /// Maps `path` and checks it holds `dims`-wide vectors: the header (magic,
+  /// version, dims, dtype) and the payload length, or for legacy files only
+  /// the length. The checksum is left to `verify_checksum`, which reads the
+  /// whole payload.
+  pub fn open(path: &Path, dims: usize) -> anyhow::Result<Self> {
+    if dims == 0 {
+      anyhow::bail!("dims must be > 0");
+    }
+    let f = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&f)? };
+    let invalid = |e: anyhow::Error| anyhow::anyhow!("invalid {}: {e}", path.display());
+
+    let header = VectorHeader::decode(&mmap).map_err(invalid)?;
+    let payload = match &header {
+      Some(header) => {
+        if cfg!(target_endian = "big") {
+          anyhow::bail!("{}: little-endian vector files can't be mapped on this host", path.display());
+        }
+        if header.dims != dims {
+          anyhow::bail!("{} holds {}-dim vectors, expected {dims}", path.display(), header.dims);
+        }
+        let payload = &mmap[HEADER_LEN..];
+        if payload.len() != header.count * dims * 4 {
+          anyhow::bail!(
+            "{} header says {} rows but the file holds {} bytes of vectors",
+            path.display(),
+            header.count,
+            payload.len()
+          );
+        }
+        payload
+      }
+      None => &mmap[..],
+    };
+
+    let vectors: &[f32] = bytemuck::try_cast_slice(payload)
+      .map_err(|e| anyhow::anyhow!("invalid {} format: {e}", path.display()))?;
+    if !vectors.len().is_multiple_of(dims) {
+      anyhow::bail!(
+        "{} length {} is not divisible by dims {}",
+        path.display(),
+        vectors.len(),
+        dims
+      );
+    }
+
+    // Safety: mmap lives inside Self; we transmute the slice to 'static so we can
+    // keep it as a field without self-referential borrows. Access is still bounded
+    // by `self` methods.
+    let vectors_static: &'static [f32] =
+      unsafe { std::mem::transmute::<&[f32], &'static [f32]>(vectors) };
+
+    Ok(Self {
+      _mmap: mmap,
+      dims,
+      header,
+      vectors: vectors_static,
+    })
+  }
+
+  /// Compares the payload against the header checksum (legacy files have
+  /// none). Reads every byte, so only `check-index` and `convert` call it.
+  pub fn verify_checksum(&self) -> anyhow::Result<()> {
+    if let Some(header) = &self.header {
+      if fnv1a64(bytemuck::cast_slice(self.vectors)) != header.checksum {
+        anyhow::bail!("checksum mismatch (file is corrupt)");
+      }
+    }
+    Ok(())
+  }
+
+  /// `None` for legacy raw files.
+  pub fn header(&self) -> Option<&VectorHeader> {
+    self.header.as_ref()
+  }
+
+  pub fn len(&self) -> usize {
+    self.vectors.len() / self.dims
+  }
+
+  pub fn get(&self, i: usize) -> Option<&[f32]> {
+    let start = i.checked_mul(self.dims)?;
+    self.vectors.get(start..start + self.dims)
+  }
+}
+
+/// Streams rows into a new vector file; the header (count, checksum,
+/// normalization flag) is filled in by `finish`.
+pub struct VectorWriter {
+  out: BufWriter<File>,
+  dims: usize,
+  count: usize,
+  checksum: u64,
+  normalized: bool,
+}
+
+impl VectorWriter {
+  pub fn create(path: &Path, dims: usize) -> anyhow::Result<Self> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&[0u8; HEADER_LEN])?;
+    Ok(Self {
+      out,
+      dims,
+      count: 0,
+      checksum: FNV64_OFFSET,
+      normalized: true,
+    })
+  }
+
+  pub fn push(&mut self, v: &[f32]) -> anyhow::Result<()> {
+    if v.len() != self.dims {
+      anyhow::bail!("vector has {} dims, expected {}", v.len(), self.dims);
+    }
+    let bytes: Vec<u8> = v.iter().flat_map(|x| x.to_le_bytes()).collect();
+    self.checksum = fnv1a64_update(self.checksum, &bytes);
+    self.out.write_all(&bytes)?;
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    self.normalized &= (norm - 1.0).abs() <= NORM_TOLERANCE;
+    self.count += 1;
+    Ok(())
+  }
+
+  /// Writes the header and syncs the file.
+  pub fn finish(self) -> anyhow::Result<VectorHeader> {
+    let header = VectorHeader {
+      version: FORMAT_VERSION,
+      dims: self.dims,
+      count: self.count,
+      dtype: Dtype::F32,
+      normalized: self.normalized && self.count > 0,
+      checksum: self.checksum,
+    };
+    let mut f = self.out.into_inner().map_err(|e| e.into_error())?;
+    f.seek(SeekFrom::Start(0))?;
+    f.write_all(&header.encode())?;
+    f.sync_all()?;
+    Ok(header)
+  }
+}
+
+/// Writes `vectors` (back to back) through a temp file renamed into place.
+pub fn write_vector_file(path: &Path, dims: usize, vectors: &[f32]) -> anyhow::Result<VectorHeader> {
+  let tmp = tmp_path(path);
+  let mut writer = VectorWriter::create(&tmp, dims)?;
+  for v in vectors.chunks_exact(dims) {
+    writer.push(v)?;
+  }
+  let header = writer.finish()?;
+  fs::rename(&tmp, path)?;
+  Ok(header)
+}
+
+pub fn tmp_path(path: &Path) -> PathBuf {
+  let mut tmp = PathBuf::from(path);
+  tmp.as_mut_os_string().push(".tmp");
+  tmp
+}
+
+/// Result of upgrading one file with `convert`.
+pub enum Converted {
+  Upgraded(VectorHeader),
+  AlreadyCurrent,
+}
+
+/// Rewrites a legacy raw file at `path` in the current format (in place,
+/// through a temp file). Headered files are only checked against their
+/// checksum.
+pub fn convert_vector_file(path: &Path, dims: usize) -> anyhow::Result<Converted> {
+  let file = VectorFile::open(path, dims)?;
+  if file.header().is_some() {
+    file
+      .verify_checksum()
+      .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    return Ok(Converted::AlreadyCurrent);
+  }
+  let tmp = tmp_path(path);
+  let mut writer = VectorWriter::create(&tmp, dims)?;
+  for i in 0..file.len() {
+    writer.push(file.get(i).expect("row in range"))?;
+  }
+  let header = writer.finish()?;
+  drop(file);
+  fs::rename(&tmp, path)?;
+  Ok(Converted::Upgraded(header))
+}
+
+const FNV64_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a64_update(mut hash: u64, bytes: &[u8]) -> u64 {
+  for b in bytes {
+    hash ^= u64::from(*b);
+    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+  }
+  hash
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+  fnv1a64_update(FNV64_OFFSET, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("local-search-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn raw(vectors: &[f32]) -> Vec<u8> {
+    vectors.iter().flat_map(|x| x.to_le_bytes()).collect()
+  }
+
+  #[test]
+  fn header_round_trips() {
+    let header = VectorHeader {
+      version: FORMAT_VERSION,
+      dims: 1024,
+      count: 3_000_000,
+      dtype: Dtype::F32,
+      normalized: true,
+      checksum: 0x0123_4567_89ab_cdef,
+    };
+    let bytes = header.encode();
+    assert_eq!(bytes[..8], MAGIC);
+    let decoded = VectorHeader::decode(&bytes).unwrap().unwrap();
+    assert_eq!(
+      (decoded.version, decoded.dims, decoded.count, decoded.dtype, decoded.normalized, decoded.checksum),
+      (FORMAT_VERSION, 1024, 3_000_000, Dtype::F32, true, 0x0123_4567_89ab_cdef)
+    );
+  }
+
+  #[test]
+  fn truncated_or_unknown_headers_are_rejected() {
+    let dir = temp_dir("vector-file-header");
+    let path = dir.join("vectors.f32");
+    let header = write_vector_file(&path, 2, &[1.0, 0.0]).unwrap();
+    let bytes = fs::read(&path).unwrap();
+
+    assert!(VectorHeader::decode(&bytes[..HEADER_LEN - 1]).is_err());
+    fs::write(&path, &bytes[..40]).unwrap();
+    assert!(VectorFile::open(&path, 2).is_err());
+
+    let mut future = VectorHeader { version: 2, ..header }.encode().to_vec();
+    future.extend(&bytes[HEADER_LEN..]);
+    fs::write(&path, future).unwrap();
+    let err = VectorFile::open(&path, 2).err().unwrap();
+    assert!(format!("{err:#}").contains("unsupported format version 2"));
+
+    // A payload shorter than the header's row count.
+    fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+    assert!(VectorFile::open(&path, 2).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn legacy_raw_files_open_by_length() {
+    let dir = temp_dir("vector-file-legacy");
+    let path = dir.join("vectors.f32");
+    fs::write(&path, raw(&[1.0, 0.0, 0.6, 0.8])).unwrap();
+    let file = VectorFile::open(&path, 2).unwrap();
+    assert!(file.header().is_none());
+    assert_eq!(file.len(), 2);
+    assert_eq!(file.get(1), Some([0.6, 0.8].as_slice()));
+    file.verify_checksum().unwrap();
+    assert!(VectorFile::open(&path, 3).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn flipped_payload_byte_fails_the_checksum() {
+    let dir = temp_dir("vector-file-checksum");
+    let path = dir.join("vectors.f32");
+    write_vector_file(&path, 2, &[1.0, 0.0, 0.6, 0.8]).unwrap();
+    VectorFile::open(&path, 2).unwrap().verify_checksum().unwrap();
+
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[HEADER_LEN + 5] ^= 0x01;
+    fs::write(&path, bytes).unwrap();
+    // Opening only checks the header; the checksum needs a full read.
+    let file = VectorFile::open(&path, 2).unwrap();
+    assert!(file.verify_checksum().is_err());
+    drop(file);
+    assert!(convert_vector_file(&path, 2).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn convert_upgrades_legacy_files_once() {
+    let dir = temp_dir("vector-file-convert");
+    let path = dir.join("vectors.f32");
+    fs::write(&path, raw(&[1.0, 0.0, 0.5, 0.5])).unwrap();
+
+    let Converted::Upgraded(header) = convert_vector_file(&path, 2).unwrap() else {
+      panic!("a legacy file should be upgraded");
+    };
+    assert_eq!((header.count, header.normalized), (2, false));
+    assert!(!tmp_path(&path).exists());
+    let file = VectorFile::open(&path, 2).unwrap();
+    file.verify_checksum().unwrap();
+    assert_eq!(file.header().map(|h| h.count), Some(2));
+    assert_eq!(file.get(1), Some([0.5, 0.5].as_slice()));
+    drop(file);
+
+    assert!(matches!(convert_vector_file(&path, 2).unwrap(), Converted::AlreadyCurrent));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,95 +1,463 @@
-use std::{cmp::Reverse, path::Path};
+use std::{
+  cmp::Reverse,
+  collections::BinaryHeap,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
 
-use memmap2::Mmap;
 use ordered_float::NotNan;
+use tracing::warn;
 
+use crate::{
+  manifest::DEFAULT_VECTOR_FIELD,
+  segment::{Segment, SegmentsFile, BASE_SEGMENT},
+  vector_file::VectorFile,
+  wal::Wal,
+};
+
+/// One bit per row id; set = searchable.
+#[derive(Default)]
+pub struct LiveDocs {
+  words: Vec<u64>,
+  count: usize,
+}
+
+impl LiveDocs {
+  pub fn get(&self, row: usize) -> bool {
+    self.words.get(row / 64).is_some_and(|w| w & (1 << (row % 64)) != 0)
+  }
+
+  pub fn set(&mut self, row: usize, alive: bool) {
+    let word = row / 64;
+    if word >= self.words.len() {
+      if !alive {
+        return;
+      }
+      self.words.resize(word + 1, 0);
+    }
+    let bit = 1 << (row % 64);
+    let was = self.words[word] & bit != 0;
+    if was != alive {
+      self.words[word] ^= bit;
+      if alive {
+        self.count += 1;
+      } else {
+        self.count -= 1;
+      }
+    }
+  }
+
+  pub fn count(&self) -> usize {
+    self.count
+  }
+}
+
+/// Segments to fold together, with their alive rows already copied out so the
+/// new segment can be written without holding the store lock.
+pub struct Compaction {
+  pub name: String,
+  /// Segments replaced by the new one.
+  pub inputs: Vec<String>,
+  /// WAL rows folded in (all of it, or none).
+  pub wal_rows: usize,
+  pub rows: Vec<u64>,
+  /// One `rows.len() * dims` buffer per column.
+  pub columns: Vec<Vec<f32>>,
+}
+
+/// Non-base segments that trigger a merge into one.
+const MERGE_FACTOR: usize = 4;
+
+/// A segment with more than this share of dead rows is rewritten without them.
+const MAX_DEAD_RATIO: f64 = 0.5;
+
+/// All vectors of an index as one row space: the `build-index` base segment
+/// (`vectors.f32`), immutable segments written by flushes and merges, and the
+/// WAL of rows appended since the last flush. `live` decides which rows are
+/// searchable, so replacing or deleting a doc never touches a segment file.
+///
+/// Columns are the default vector plus each named field; every segment and
+/// the WAL store all of them row-aligned.
 pub struct VectorStore {
-  mmap: Mmap,
+  dir: PathBuf,
   dims: usize,
-  vectors: &'static [f32],
+  columns: Vec<String>,
+  segments: Vec<Arc<Segment>>,
+  wal: Wal,
+  live: LiveDocs,
+  next_row: u64,
+  next_id: u64,
 }
 
 impl VectorStore {
-  pub fn open(path: &Path, dims: usize) -> anyhow::Result<Self> {
-    let f = std::fs::File::open(path)?;
-    let mmap = unsafe { Mmap::map(&f)? };
-    let vectors: &[f32] = bytemuck::try_cast_slice(&mmap)
-      .map_err(|e| anyhow::anyhow!("invalid vectors.f32 format: {e}"))?;
+  /// Opens the segments listed in `segments.json` (or just `vectors.f32` for
+  /// indexes built before segments existed) and replays the WAL, without
+  /// writing anything. All rows start dead; the caller marks the ones
+  /// `vec_map` points to. Call `prepare_writes` before appending.
+  pub fn open(dir: &Path, dims: usize, fields: &[String]) -> anyhow::Result<Self> {
+    let columns: Vec<String> = std::iter::once(DEFAULT_VECTOR_FIELD.to_string())
+      .chain(fields.iter().cloned())
+      .collect();
 
-    if dims == 0 {
-      anyhow::bail!("dims must be > 0");
-    }
-    if vectors.len() % dims != 0 {
-      anyhow::bail!(
-        "vectors.f32 length {} is not divisible by dims {}",
-        vectors.len(),
-        dims
-      );
-    }
+    let file = match SegmentsFile::load(dir)? {
+      Some(file) => file,
+      None => {
+        let base = VectorFile::open(&dir.join(Segment::column_file(BASE_SEGMENT, DEFAULT_VECTOR_FIELD)), dims)?;
+        SegmentsFile {
+          next_id: 1,
+          wal_start: base.len() as u64,
+          segments: vec![BASE_SEGMENT.to_string()],
+        }
+      }
+    };
 
-    // Safety: mmap lives inside Self; we transmute the slice to 'static so we can
-    // keep it as a field without self-referential borrows. Access is still bounded
-    // by `self` methods.
-    let vectors_static: &'static [f32] =
-      unsafe { std::mem::transmute::<&[f32], &'static [f32]>(vectors) };
+    let segments = file
+      .segments
+      .iter()
+      .map(|name| Segment::open(dir, name, dims, &columns).map(Arc::new))
+      .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let wal = Wal::open(dir, dims, columns.len(), file.wal_start)?;
+    let segment_end = segments.iter().filter_map(|s| s.last_row()).map(|r| r + 1).max().unwrap_or(0);
+    let next_row = [file.wal_start, segment_end, wal.last_row().map_or(0, |r| r + 1)]
+      .into_iter()
+      .max()
+      .unwrap_or(0);
 
     Ok(Self {
-      mmap,
+      dir: dir.to_path_buf(),
       dims,
-      vectors: vectors_static,
+      columns,
+      segments,
+      wal,
+      live: LiveDocs::default(),
+      next_row,
+      next_id: file.next_id,
     })
   }
 
-  pub fn dims(&self) -> usize {
-    self.dims
+  /// Cuts a torn WAL tail left by a crash so appends can follow.
+  pub fn prepare_writes(&mut self) -> anyhow::Result<()> {
+    self.wal.repair()
+  }
+
+  pub fn docs(&self) -> usize {
+    self.live.count()
+  }
+
+  pub fn wal_rows(&self) -> usize {
+    self.wal.len()
+  }
+
+  pub fn segment_count(&self) -> usize {
+    self.segments.len()
+  }
+
+  /// Name of the segment holding `row` (`"wal"` before it is flushed).
+  pub fn location(&self, row: usize) -> Option<&str> {
+    if self.wal.position(row as u64).is_some() {
+      return Some("wal");
+    }
+    self
+      .segments
+      .iter()
+      .find(|s| s.position(row as u64).is_some())
+      .map(|s| s.name.as_str())
+  }
+
+  pub fn set_alive(&mut self, row: usize, alive: bool) {
+    self.live.set(row, alive);
+  }
+
+  /// Vector of `row` in `column` (0 = default), wherever it is stored.
+  pub fn vector(&self, column: usize, row: usize) -> Option<&[f32]> {
+    let row = row as u64;
+    if let Some(i) = self.wal.position(row) {
+      return self.wal.vector(column, i);
+    }
+    self
+      .segments
+      .iter()
+      .find_map(|s| s.position(row).and_then(|i| s.vector(column, i)))
   }
 
-  pub fn len(&self) -> usize {
-    self.vectors.len() / self.dims
+  pub fn column(&self, name: &str) -> Option<usize> {
+    self.columns.iter().position(|c| c == name)
   }
 
-  pub fn search_top_k(&self, q: &[f32], top_k: usize) -> anyhow::Result<Vec<(usize, f32)>> {
+  /// Best `top_k` alive rows by `sum(weight * (q . v))` over `fields`
+  /// (column names; empty = default only).
+  pub fn search(&self, fields: &[(String, f32)], q: &[f32], top_k: usize) -> anyhow::Result<Vec<(usize, f32)>> {
     if q.len() != self.dims {
       anyhow::bail!("query dims mismatch: got {}, expected {}", q.len(), self.dims);
     }
+    let mut weighted = Vec::with_capacity(fields.len().max(1));
+    for (name, weight) in fields {
+      let column = self
+        .column(name)
+        .ok_or_else(|| anyhow::anyhow!("unknown vector field `{name}`"))?;
+      weighted.push((column, *weight));
+    }
+    if weighted.is_empty() {
+      weighted.push((0, 1.0));
+    }
+
+    let mut top = TopK::new(top_k.min(self.live.count()).max(1));
+    for segment in &self.segments {
+      for i in 0..segment.len() {
+        let row = segment.row(i);
+        if !self.live.get(row as usize) {
+          continue;
+        }
+        let score = weighted
+          .iter()
+          .map(|(column, weight)| weight * segment.vector(*column, i).map_or(0.0, |v| dot(q, v)))
+          .sum();
+        top.push(row as usize, score);
+      }
+    }
+    for i in 0..self.wal.len() {
+      let row = self.wal.row(i);
+      if !self.live.get(row as usize) {
+        continue;
+      }
+      let score = weighted
+        .iter()
+        .map(|(column, weight)| weight * self.wal.vector(*column, i).map_or(0.0, |v| dot(q, v)))
+        .sum();
+      top.push(row as usize, score);
+    }
+    Ok(top.into_sorted())
+  }
+
+  /// Logs `batch` (one entry per row, all columns, default first) to the WAL
+  /// with one sync and returns the first row id. Needs only a shared borrow,
+  /// so searches go on meanwhile; `push_rows` then makes the rows readable.
+  /// Callers serialize writers.
+  pub fn log_rows(&self, batch: &[Vec<&[f32]>]) -> anyhow::Result<usize> {
+    if batch
+      .iter()
+      .any(|vectors| vectors.len() != self.columns.len() || vectors.iter().any(|v| v.len() != self.dims))
+    {
+      anyhow::bail!("vector shape does not match the index");
+    }
+    self.wal.write(self.next_row, batch)?;
+    Ok(self.next_row as usize)
+  }
 
-    let k = top_k.min(self.len()).max(1);
-    let mut heap: std::collections::BinaryHeap<(Reverse<NotNan<f32>>, usize)> =
-      std::collections::BinaryHeap::new();
+  /// Adds rows logged by `log_rows` (from `first_row`). They start dead; mark
+  /// them alive once `vec_map` points to them.
+  pub fn push_rows(&mut self, first_row: usize, batch: &[Vec<&[f32]>]) {
+    debug_assert_eq!(first_row as u64, self.next_row);
+    self.wal.push(first_row as u64, batch);
+    self.next_row = first_row as u64 + batch.len() as u64;
+  }
 
-    for i in 0..self.len() {
-      let start = i * self.dims;
-      let v = &self.vectors[start..start + self.dims];
-      let mut score = 0.0_f32;
-      for (a, b) in q.iter().zip(v.iter()) {
-        score += a * b;
+  /// Picks what to fold into a new segment: the whole WAL, every non-base
+  /// segment once there are `MERGE_FACTOR` of them, and any segment that is
+  /// mostly dead rows. `None` when there's nothing worth doing.
+  pub fn plan_compaction(&self) -> Option<Compaction> {
+    let small: Vec<&Arc<Segment>> = self.segments.iter().filter(|s| s.name != BASE_SEGMENT).collect();
+    let merge_all = small.len() >= MERGE_FACTOR;
+    let inputs: Vec<&Arc<Segment>> = small
+      .into_iter()
+      .filter(|s| {
+        let dead = (0..s.len()).filter(|&i| !self.live.get(s.row(i) as usize)).count();
+        merge_all || dead as f64 > s.len() as f64 * MAX_DEAD_RATIO
+      })
+      .collect();
+    if inputs.is_empty() && self.wal.len() == 0 {
+      return None;
+    }
+
+    // (row, vector per column) of every alive row being folded.
+    let mut alive: Vec<(u64, Vec<&[f32]>)> = Vec::new();
+    for segment in &inputs {
+      for i in 0..segment.len() {
+        let row = segment.row(i);
+        if self.live.get(row as usize) {
+          alive.push((row, (0..self.columns.len()).map(|c| segment.vector(c, i).unwrap_or(&[])).collect()));
+        }
       }
+    }
+    for i in 0..self.wal.len() {
+      let row = self.wal.row(i);
+      if self.live.get(row as usize) {
+        alive.push((row, (0..self.columns.len()).map(|c| self.wal.vector(c, i).unwrap_or(&[])).collect()));
+      }
+    }
+    // Segments cover disjoint rows, so sorting by row keeps them unique.
+    alive.sort_by_key(|(row, _)| *row);
+    let rows = alive.iter().map(|(row, _)| *row).collect();
+    let columns = (0..self.columns.len())
+      .map(|c| alive.iter().flat_map(|(_, vectors)| vectors[c].iter().copied()).collect())
+      .collect();
 
-      // We L2-normalize both sides, so score should be finite.
-      let Ok(nn) = NotNan::new(score) else {
-        continue;
-      };
+    Some(Compaction {
+      name: format!("seg-{:06}", self.next_id),
+      inputs: inputs.iter().map(|s| s.name.clone()).collect(),
+      wal_rows: self.wal.len(),
+      rows,
+      columns,
+    })
+  }
 
-      if heap.len() < k {
-        heap.push((Reverse(nn), i));
-        continue;
+  /// Writes the compaction's segment files. Needs no lock on the store.
+  pub fn write_segment(dir: &Path, dims: usize, columns: &[String], plan: &Compaction) -> anyhow::Result<Option<Segment>> {
+    if plan.rows.is_empty() {
+      return Ok(None);
+    }
+    Segment::write(dir, &plan.name, dims, columns, &plan.rows, &plan.columns).map(Some)
+  }
+
+  pub fn columns(&self) -> &[String] {
+    &self.columns
+  }
+
+  /// Swaps `segment` in for the plan's inputs, commits `segments.json`, then
+  /// clears the WAL and deletes the replaced files.
+  pub fn finish_compaction(&mut self, plan: Compaction, segment: Option<Segment>) -> anyhow::Result<()> {
+    if plan.wal_rows != self.wal.len() {
+      anyhow::bail!("WAL changed during compaction");
+    }
+    let mut segments: Vec<Arc<Segment>> = self
+      .segments
+      .iter()
+      .filter(|s| !plan.inputs.contains(&s.name))
+      .cloned()
+      .collect();
+    segments.extend(segment.map(Arc::new));
+
+    let wal_start = if plan.wal_rows > 0 { self.next_row } else { self.wal.start() };
+    SegmentsFile {
+      next_id: self.next_id + 1,
+      wal_start,
+      segments: segments.iter().map(|s| s.name.clone()).collect(),
+    }
+    .save(&self.dir)?;
+
+    self.segments = segments;
+    self.next_id += 1;
+    if plan.wal_rows > 0 {
+      self.wal.clear(wal_start)?;
+    }
+    for name in &plan.inputs {
+      // Searches still holding the old generation keep their mappings.
+      if let Err(e) = Segment::remove_files(&self.dir, name, &self.columns) {
+        warn!(segment = %name, error = %e, "failed to delete merged segment files");
       }
+    }
+    Ok(())
+  }
+}
 
-      // BinaryHeap is max-heap; Reverse turns it into min-heap by score.
-      if let Some((Reverse(worst), _)) = heap.peek() {
-        if nn > *worst {
-          let _ = heap.pop();
-          heap.push((Reverse(nn), i));
-        }
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+  let mut score = 0.0_f32;
+  for (x, y) in a.iter().zip(b.iter()) {
+    score += x * y;
+  }
+  score
+}
+
+/// Keeps the `k` highest-scoring rows seen, best first on `into_sorted`.
+pub struct TopK {
+  k: usize,
+  heap: BinaryHeap<(Reverse<NotNan<f32>>, usize)>,
+}
+
+impl TopK {
+  pub fn new(k: usize) -> Self {
+    Self {
+      k,
+      heap: BinaryHeap::with_capacity(k + 1),
+    }
+  }
+
+  pub fn push(&mut self, row: usize, score: f32) {
+    // We L2-normalize both sides, so score should be finite.
+    let Ok(nn) = NotNan::new(score) else {
+      return;
+    };
+
+    if self.heap.len() < self.k {
+      self.heap.push((Reverse(nn), row));
+      return;
+    }
+
+    // BinaryHeap is max-heap; Reverse turns it into min-heap by score.
+    if let Some((Reverse(worst), _)) = self.heap.peek() {
+      if nn > *worst {
+        let _ = self.heap.pop();
+        self.heap.push((Reverse(nn), row));
       }
     }
+  }
 
-    let mut out: Vec<(usize, f32)> = heap
+  pub fn into_sorted(self) -> Vec<(usize, f32)> {
+    let mut out: Vec<(usize, f32)> = self
+      .heap
       .into_iter()
-      .map(|(Reverse(score), idx)| (idx, score.into_inner()))
+      .map(|(Reverse(score), row)| (row, score.into_inner()))
       .collect();
     out.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-    Ok(out)
+    out
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::{vector_file::write_vector_file, wal::WAL_FILE};
+
+  fn batch(rows: &[[f32; 2]]) -> Vec<Vec<&[f32]>> {
+    rows.iter().map(|v| vec![v.as_slice()]).collect()
+  }
+
+  #[test]
+  fn reopen_after_crash_between_segments_save_and_wal_clear() {
+    let dir = std::env::temp_dir().join(format!("local-search-store-crash-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    write_vector_file(&dir.join("vectors.f32"), 2, &[]).unwrap();
+
+    let mut store = VectorStore::open(&dir, 2, &[]).unwrap();
+    store.prepare_writes().unwrap();
+    let rows = [[1.0, 0.0], [0.0, 1.0]];
+    let first = store.log_rows(&batch(&rows)).unwrap();
+    store.push_rows(first, &batch(&rows));
+    store.set_alive(0, true);
+    store.set_alive(1, true);
+    let plan = store.plan_compaction().unwrap();
+    VectorStore::write_segment(&dir, 2, store.columns(), &plan).unwrap();
+    // What `finish_compaction` commits before it clears the WAL.
+    SegmentsFile {
+      next_id: 2,
+      wal_start: 2,
+      segments: vec![BASE_SEGMENT.to_string(), plan.name.clone()],
+    }
+    .save(&dir)
+    .unwrap();
+    drop(store);
+
+    let mut store = VectorStore::open(&dir, 2, &[]).unwrap();
+    assert_eq!(store.wal_rows(), 0);
+    assert_eq!(store.segment_count(), 2);
+    assert_eq!(store.location(1), Some(plan.name.as_str()));
+    assert_eq!(store.vector(0, 1), Some([0.0, 1.0].as_slice()));
+
+    store.prepare_writes().unwrap();
+    assert_eq!(fs::metadata(dir.join(WAL_FILE)).unwrap().len(), 0);
+    let more = [[0.6, 0.8]];
+    assert_eq!(store.log_rows(&batch(&more)).unwrap(), 2);
+    store.push_rows(2, &batch(&more));
+    drop(store);
+
+    let store = VectorStore::open(&dir, 2, &[]).unwrap();
+    assert_eq!(store.wal_rows(), 1);
+    assert_eq!(store.location(2), Some("wal"));
+    assert_eq!(store.location(1), Some(plan.name.as_str()));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,169 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader},
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::embedder::{l2_normalize_in_place, EmbedInput, Embedder, InputKind};
+
+/// One line of the references file, as written by
+/// `huggingface/make_reference_vectors.py`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reference {
+  #[serde(default)]
+  pub id: Option<String>,
+  pub text: String,
+  #[serde(default)]
+  pub kind: InputKind,
+  /// Token limit the reference was encoded with (default: `verify-model --max-length`).
+  #[serde(default)]
+  pub max_length: Option<usize>,
+  pub embedding: Vec<f32>,
+}
+
+/// Reads reference lines and L2-normalizes their vectors.
+pub fn load_references(path: &Path, dims: usize) -> anyhow::Result<Vec<Reference>> {
+  let f = File::open(path).map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+  let mut out = Vec::new();
+  for (i, line) in BufReader::new(f).lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let mut r: Reference = serde_json::from_str(&line)
+      .map_err(|e| anyhow::anyhow!("{}:{}: {e}", path.display(), i + 1))?;
+    if r.embedding.len() != dims {
+      anyhow::bail!(
+        "{}:{}: embedding has {} dims, the model has {dims}",
+        path.display(),
+        i + 1,
+        r.embedding.len()
+      );
+    }
+    l2_normalize_in_place(&mut r.embedding);
+    out.push(r);
+  }
+  if out.is_empty() {
+    anyhow::bail!("{} has no references", path.display());
+  }
+  Ok(out)
+}
+
+/// Normalized vectors for every reference text, plus the time spent embedding.
+pub fn embed_references(
+  embedder: &mut dyn Embedder,
+  refs: &[Reference],
+  max_length: usize,
+  batch_size: usize,
+) -> anyhow::Result<(Vec<Vec<f32>>, Duration)> {
+  let started = Instant::now();
+  let mut out = Vec::with_capacity(refs.len());
+  for chunk in refs.chunks(batch_size.max(1)) {
+    let inputs: Vec<EmbedInput<'_>> = chunk
+      .iter()
+      .map(|r| EmbedInput {
+        text: &r.text,
+        max_length: r.max_length.unwrap_or(max_length),
+        kind: r.kind,
+      })
+      .collect();
+    for mut v in embedder.embed_batch(&inputs)? {
+      l2_normalize_in_place(&mut v);
+      out.push(v);
+    }
+  }
+  Ok((out, started.elapsed()))
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f64 {
+  a.iter().zip(b).map(|(x, y)| *x as f64 * *y as f64).sum()
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CosineStats {
+  pub count: usize,
+  pub min: f64,
+  pub p1: f64,
+  pub p5: f64,
+  pub p50: f64,
+  pub mean: f64,
+  /// Texts below `--min-cosine`.
+  pub below_threshold: usize,
+}
+
+impl CosineStats {
+  pub fn new(cosines: &[f64], threshold: f64) -> Self {
+    let mut sorted = cosines.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let at = |q: f64| {
+      let i = ((sorted.len() - 1) as f64 * q).round() as usize;
+      sorted[i]
+    };
+    Self {
+      count: sorted.len(),
+      min: sorted[0],
+      p1: at(0.01),
+      p5: at(0.05),
+      p50: at(0.5),
+      mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+      below_threshold: sorted.iter().filter(|c| **c < threshold).count(),
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorstText {
+  /// 0-based position among the references.
+  pub index: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  pub text: String,
+  pub cosine: f64,
+}
+
+/// The `n` lowest cosines, with (shortened) texts for eyeballing.
+pub fn worst_texts(refs: &[Reference], cosines: &[f64], n: usize) -> Vec<WorstText> {
+  let mut order: Vec<usize> = (0..cosines.len()).collect();
+  order.sort_by(|&a, &b| cosines[a].total_cmp(&cosines[b]));
+  order
+    .into_iter()
+    .take(n)
+    .map(|i| WorstText {
+      index: i,
+      id: refs[i].id.clone(),
+      text: refs[i].text.chars().take(80).collect(),
+      cosine: cosines[i],
+    })
+    .collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantReport {
+  pub model_file: String,
+  pub ms_per_text: f64,
+  /// Agreement with the reference vectors.
+  pub reference: CosineStats,
+  /// Agreement with the first `--model-file` (absent for the first one).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub baseline: Option<CosineStats>,
+  pub worst: Vec<WorstText>,
+  pub passed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+  pub model: String,
+  pub references: usize,
+  pub min_cosine: f64,
+  pub min_mean_cosine: f64,
+  pub variants: Vec<VariantReport>,
+  pub passed: bool,
+}
@@ -0,0 +1,269 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+/// Rows appended since the last flush into a segment.
+pub const WAL_FILE: &str = "wal.log";
+
+/// Write-ahead log of appended rows, also kept in memory so they're searchable
+/// right away. Records are fixed size: the row as `u64` LE, every column's
+/// vector as `f32` LE, then an FNV-1a checksum of those bytes. A torn or
+/// corrupt tail (crash mid-append) is skipped on open and cut by `repair`.
+///
+/// Batches are logged in two steps so the store lock is held only briefly:
+/// `write` appends and syncs the records (`&self`), then `push` makes them
+/// readable. Callers serialize writers.
+pub struct Wal {
+  path: PathBuf,
+  dims: usize,
+  columns: usize,
+  /// Records below this row are already in a segment.
+  start: u64,
+  rows: Vec<u64>,
+  /// One buffer per column, `rows.len() * dims` floats each.
+  vectors: Vec<Vec<f32>>,
+  /// The file holds exactly the in-memory records (no torn tail, nothing
+  /// below `start`), so appending is safe.
+  clean: bool,
+  /// Kept open for appending once `repair` ran.
+  file: Option<File>,
+}
+
+impl Wal {
+  /// Reads the log without modifying it.
+  pub fn open(dir: &Path, dims: usize, columns: usize, start: u64) -> anyhow::Result<Self> {
+    let path = dir.join(WAL_FILE);
+    let mut wal = Self {
+      path,
+      dims,
+      columns,
+      start,
+      rows: Vec::new(),
+      vectors: vec![Vec::new(); columns],
+      clean: true,
+      file: None,
+    };
+    if !wal.path.exists() {
+      return Ok(wal);
+    }
+
+    let bytes = fs::read(&wal.path)?;
+    let record = wal.record_len();
+    let mut valid = 0;
+    let mut skipped = false;
+    for chunk in bytes.chunks_exact(record) {
+      let (body, sum) = chunk.split_at(record - 4);
+      if fnv1a(body) != u32::from_le_bytes(sum.try_into().expect("4-byte checksum")) {
+        break;
+      }
+      let row = u64::from_le_bytes(body[..8].try_into().expect("8-byte row"));
+      if wal.rows.last().is_some_and(|&last| row <= last) {
+        break;
+      }
+      valid += record;
+      if row < start {
+        skipped = true;
+        continue;
+      }
+      wal.rows.push(row);
+      for (c, floats) in body[8..].chunks_exact(dims * 4).enumerate() {
+        wal.vectors[c].extend(floats.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+      }
+    }
+
+    wal.clean = !skipped && valid == bytes.len();
+    Ok(wal)
+  }
+
+  /// Leaves exactly the in-memory records on disk, so appends follow the
+  /// last valid one, and opens the log for appending. Writers call this
+  /// before the first `write`.
+  pub fn repair(&mut self) -> anyhow::Result<()> {
+    if !self.clean {
+      let mut keep = Vec::with_capacity(self.rows.len() * self.record_len());
+      for i in 0..self.rows.len() {
+        let vectors: Vec<&[f32]> = (0..self.columns).map(|c| self.vector(c, i).expect("row in range")).collect();
+        keep.extend(self.encode(self.rows[i], &vectors));
+      }
+      fs::write(&self.path, keep)?;
+      self.clean = true;
+    }
+    if self.file.is_none() {
+      self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+    }
+    Ok(())
+  }
+
+  fn record_len(&self) -> usize {
+    8 + self.columns * self.dims * 4 + 4
+  }
+
+  fn encode(&self, row: u64, vectors: &[&[f32]]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(self.record_len());
+    out.extend(row.to_le_bytes());
+    for v in vectors {
+      out.extend(v.iter().flat_map(|x| x.to_le_bytes()));
+    }
+    let sum = fnv1a(&out);
+    out.extend(sum.to_le_bytes());
+    out
+  }
+
+  /// Logs `batch` (one row per entry, all columns) as rows `first_row..`
+  /// with a single sync. The records aren't readable until `push`ed. Rows
+  /// must follow the last one logged.
+  pub fn write(&self, first_row: u64, batch: &[Vec<&[f32]>]) -> anyhow::Result<()> {
+    let Some(file) = &self.file else {
+      anyhow::bail!("{} is not open for appending; repair it first", self.path.display());
+    };
+    let mut records = Vec::with_capacity(batch.len() * self.record_len());
+    for (i, vectors) in batch.iter().enumerate() {
+      records.extend(self.encode(first_row + i as u64, vectors));
+    }
+    // Drop whatever a failed earlier write left after the pushed records.
+    let end = (self.rows.len() * self.record_len()) as u64;
+    if file.metadata()?.len() != end {
+      file.set_len(end)?;
+    }
+    let written = (&*file).write_all(&records).and_then(|()| file.sync_data());
+    if let Err(e) = written {
+      let _ = file.set_len(end);
+      return Err(e.into());
+    }
+    Ok(())
+  }
+
+  /// Makes a `write`n batch readable.
+  pub fn push(&mut self, first_row: u64, batch: &[Vec<&[f32]>]) {
+    for (i, vectors) in batch.iter().enumerate() {
+      self.rows.push(first_row + i as u64);
+      for (buf, v) in self.vectors.iter_mut().zip(vectors) {
+        buf.extend_from_slice(v);
+      }
+    }
+  }
+
+  /// Empties the log after its rows were committed to a segment.
+  pub fn clear(&mut self, start: u64) -> anyhow::Result<()> {
+    if let Some(file) = &self.file {
+      file.set_len(0)?;
+    } else if self.path.exists() {
+      File::options().write(true).open(&self.path)?.set_len(0)?;
+    }
+    self.start = start;
+    self.clean = true;
+    self.rows.clear();
+    for buf in &mut self.vectors {
+      buf.clear();
+    }
+    Ok(())
+  }
+
+  pub fn start(&self) -> u64 {
+    self.start
+  }
+
+  pub fn len(&self) -> usize {
+    self.rows.len()
+  }
+
+  pub fn row(&self, i: usize) -> u64 {
+    self.rows[i]
+  }
+
+  pub fn last_row(&self) -> Option<u64> {
+    self.rows.last().copied()
+  }
+
+  pub fn position(&self, row: u64) -> Option<usize> {
+    self.rows.binary_search(&row).ok()
+  }
+
+  pub fn vector(&self, column: usize, i: usize) -> Option<&[f32]> {
+    let start = i.checked_mul(self.dims)?;
+    self.vectors.get(column)?.get(start..start + self.dims)
+  }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+  let mut hash: u32 = 0x811c_9dc5;
+  for b in bytes {
+    hash ^= u32::from(*b);
+    hash = hash.wrapping_mul(0x0100_0193);
+  }
+  hash
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("local-search-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn log(wal: &mut Wal, first_row: u64, rows: &[[f32; 2]]) {
+    let batch: Vec<Vec<&[f32]>> = rows.iter().map(|v| vec![v.as_slice()]).collect();
+    wal.write(first_row, &batch).unwrap();
+    wal.push(first_row, &batch);
+  }
+
+  fn rows(wal: &Wal) -> Vec<u64> {
+    (0..wal.len()).map(|i| wal.row(i)).collect()
+  }
+
+  #[test]
+  fn replay_stops_at_a_torn_record() {
+    let dir = temp_dir("wal-torn");
+    let mut wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    wal.repair().unwrap();
+    log(&mut wal, 0, &[[1.0, 0.0], [0.0, 1.0]]);
+    drop(wal);
+    // Part of a third record, as if the process died mid-write.
+    let mut f = OpenOptions::new().append(true).open(dir.join(WAL_FILE)).unwrap();
+    f.write_all(&[7; 10]).unwrap();
+    drop(f);
+
+    let mut wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    assert_eq!(rows(&wal), [0, 1]);
+    assert_eq!(wal.vector(0, 1), Some([0.0, 1.0].as_slice()));
+    assert!(wal.write(2, &[vec![[0.6, 0.8].as_slice()]]).is_err());
+
+    wal.repair().unwrap();
+    log(&mut wal, 2, &[[0.6, 0.8]]);
+    let wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    assert_eq!(rows(&wal), [0, 1, 2]);
+    assert_eq!(wal.vector(0, 2), Some([0.6, 0.8].as_slice()));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn replay_stops_at_a_corrupt_record() {
+    let dir = temp_dir("wal-corrupt");
+    let mut wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    wal.repair().unwrap();
+    log(&mut wal, 0, &[[1.0, 0.0], [0.0, 1.0], [0.6, 0.8]]);
+    let record = wal.record_len();
+    drop(wal);
+    let path = dir.join(WAL_FILE);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[record + 9] ^= 0x40;
+    fs::write(&path, bytes).unwrap();
+
+    // Records after the bad one are dropped too: rows must stay ascending.
+    let mut wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    assert_eq!(rows(&wal), [0]);
+    wal.repair().unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), record as u64);
+    log(&mut wal, 1, &[[0.8, 0.6]]);
+    let wal = Wal::open(&dir, 2, 1, 0).unwrap();
+    assert_eq!(rows(&wal), [0, 1]);
+    assert_eq!(wal.vector(0, 1), Some([0.8, 0.6].as_slice()));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}